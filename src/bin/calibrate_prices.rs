@@ -0,0 +1,285 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    thread,
+    time::Instant,
+};
+
+use botgame::{
+    cards::{
+        deck::make_deck,
+        generator::random_card,
+        price::{has_linear_price, PriceConfig, PRICE_CONFIG_PATH},
+        Card, Effect,
+    },
+    match_sim::{
+        replay::{list_replays, Replay, REPLAY_DIR},
+        simulation::simulate_match,
+    },
+};
+use rand::{prelude::SliceRandom, thread_rng};
+
+const USAGE: &str = "usage: calibrate_prices [cards] [matches] [output path]
+       calibrate_prices replays [output path]";
+const DEFAULT_CARDS: usize = 60;
+const DEFAULT_MATCHES: usize = 600;
+const DECK_SIZE: usize = 5;
+
+/// How much energy a card is worth for each point of win rate above 50%, e.g. a card whose deck
+/// wins 60% of the matches it is summoned in is undercosted by 1 energy.
+const ENERGY_PER_WIN_RATE: f32 = 10.;
+/// Cards summoned in fewer matches than this are too noisy to fit against.
+const MIN_MATCHES: u32 = 5;
+/// Pulls the fit towards the current coefficients, so a small sample can't move them far.
+const RIDGE: f32 = 0.5;
+const REPORTED_CARDS: usize = 10;
+
+#[derive(Default, Clone, Copy)]
+struct CardRecord {
+    matches: u32,
+    wins: u32,
+}
+
+struct Sample<'a> {
+    card: &'a Card,
+    features: Vec<f32>,
+    /// The part of the price that doesn't depend on the fitted coefficients
+    offset: f32,
+    observed: f32,
+    win_rate: f32,
+    matches: u32,
+}
+
+fn price(config: &PriceConfig, card: &Card) -> f32 {
+    config.price_card(&card.abilities, card.hp, card.starting_energy, card.max_energy)
+}
+
+/// Splits `card`'s price under `config` into a term per fitted coefficient plus a constant, using
+/// the fact that the price is linear in each of them.
+fn linearize(config: &PriceConfig, card: &Card) -> (Vec<f32>, f32) {
+    let mut zeroed = config.clone();
+    zeroed.fitted_coefficients().into_iter().for_each(|(_, c)| *c = 0.);
+    let offset = price(&zeroed, card);
+
+    let n = zeroed.fitted_coefficients().len();
+    let features = (0..n)
+        .map(|i| {
+            let mut basis = zeroed.clone();
+            *basis.fitted_coefficients()[i].1 = 1.;
+            price(&basis, card) - offset
+        })
+        .collect();
+    (features, offset)
+}
+
+/// Solves `a * x = b` by Gaussian elimination with partial pivoting.
+fn solve(mut a: Vec<Vec<f32>>, mut b: Vec<f32>) -> Option<Vec<f32>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|i, j| a[*i][col].abs().total_cmp(&a[*j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-9 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let (pivot_row, pivot_b) = (a[col].clone(), b[col]);
+        for (row, b) in a.iter_mut().zip(&mut b).skip(col + 1) {
+            let factor = row[col] / pivot_row[col];
+            for (x, p) in row.iter_mut().zip(&pivot_row).skip(col) {
+                *x -= factor * p;
+            }
+            *b -= factor * pivot_b;
+        }
+    }
+
+    let mut x = vec![0.; n];
+    for row in (0..n).rev() {
+        let rest = (row + 1..n).map(|k| a[row][k] * x[k]).sum::<f32>();
+        x[row] = (b[row] - rest) / a[row][row];
+    }
+    Some(x)
+}
+
+/// Ridge regression of the observed values onto the features, regularized towards `current`.
+fn fit(samples: &[Sample], current: &[f32]) -> Option<Vec<f32>> {
+    let n = current.len();
+    let lambda = RIDGE * samples.len() as f32;
+    let mut a = vec![vec![0.; n]; n];
+    let mut b = current.iter().map(|c| lambda * c).collect::<Vec<_>>();
+    for (i, row) in a.iter_mut().enumerate() {
+        row[i] = lambda;
+    }
+    for sample in samples {
+        let target = sample.observed - sample.offset;
+        for ((row, b), fi) in a.iter_mut().zip(&mut b).zip(&sample.features) {
+            *b += fi * target;
+            for (a, fj) in row.iter_mut().zip(&sample.features) {
+                *a += fi * fj;
+            }
+        }
+    }
+    solve(a, b)
+}
+
+fn rms_error(samples: &[Sample], config: &PriceConfig) -> f32 {
+    let total = samples.iter().map(|s| (price(config, s.card) - s.observed).powi(2)).sum::<f32>();
+    (total / samples.len().max(1) as f32).sqrt()
+}
+
+/// Plays `n_matches` between random decks drawn from `n_cards` random cards.
+fn simulated_records(n_cards: usize, n_matches: usize) -> (Vec<Card>, Vec<CardRecord>) {
+    // only cards the linear fit holds for, see `PriceConfig::fitted_coefficients`
+    let pool = std::iter::repeat_with(|| random_card(&mut thread_rng()))
+        .filter(has_linear_price)
+        .take(n_cards)
+        .collect::<Vec<_>>();
+
+    // every match is between two random decks with no cards in common
+    let started = Instant::now();
+    let records = Mutex::new(vec![CardRecord::default(); pool.len()]);
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    thread::scope(|scope| {
+        for t in 0..threads {
+            let (pool, records) = (&pool, &records);
+            scope.spawn(move || {
+                for i in (t..n_matches).step_by(threads) {
+                    let mut picks = (0..pool.len()).collect::<Vec<_>>();
+                    picks.shuffle(&mut thread_rng());
+                    let seats = [&picks[..DECK_SIZE], &picks[DECK_SIZE..DECK_SIZE * 2]];
+                    let decks = seats.map(|seat| {
+                        make_deck(seat.iter().map(|idx| pool[*idx].clone()).collect()).deck
+                    });
+
+                    let result = simulate_match([&decks[0], &decks[1]], i % 2);
+                    let mut records = records.lock().unwrap();
+                    for (seat, picks) in seats.iter().enumerate() {
+                        // the deck summons its cards in the order they were picked
+                        for (slot, idx) in picks.iter().enumerate() {
+                            if result.stats[seat].summons.contains_key(&[slot][..]) {
+                                records[*idx].matches += 1;
+                                records[*idx].wins += (result.winner == Some(seat)) as u32;
+                            }
+                        }
+                    }
+                }
+            });
+        }
+    });
+    println!("Simulated {n_matches} matches in {:.1?}", started.elapsed());
+
+    let records = records.into_inner().unwrap();
+    (pool, records)
+}
+
+/// Counts the matches in the saved replays that each card was summoned in, and won.
+fn recorded_records() -> (Vec<Card>, Vec<CardRecord>) {
+    let mut cards = BTreeMap::<String, (Card, CardRecord)>::new();
+    let mut matches = 0;
+    for path in list_replays() {
+        let replay = match Replay::load(&path) {
+            Ok(replay) => replay,
+            Err(e) => {
+                println!("Skipping {}: {e}", path.display());
+                continue;
+            },
+        };
+        let Some(outcome) = replay.outcome else { continue };
+        matches += 1;
+
+        let mut summoned = HashMap::new();
+        for effect in replay.turns.iter().flat_map(|turn| &turn.effects) {
+            let (Effect::SummonCard { card }, Some(target)) =
+                (&effect.effect, effect.targets.first())
+            else {
+                continue;
+            };
+            summoned.insert((target.owner, card.name.clone()), card);
+        }
+        for ((owner, name), card) in summoned {
+            let (_, record) =
+                cards.entry(name).or_insert_with(|| (card.clone(), CardRecord::default()));
+            record.matches += 1;
+            record.wins += (outcome.winner == owner) as u32;
+        }
+    }
+    println!("Read {matches} finished matches from {REPLAY_DIR}");
+
+    // only cards the linear fit holds for, see `PriceConfig::fitted_coefficients`
+    cards.into_values().filter(|(card, _)| has_linear_price(card)).unzip()
+}
+
+fn main() -> Result<(), String> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let parse = |i: usize, default: usize| match args.get(i) {
+        Some(arg) => arg.parse().map_err(|_| USAGE.to_string()),
+        None => Ok(default),
+    };
+    let (pool, records, out_path) = if args.first().is_some_and(|arg| arg == "replays") {
+        let (pool, records) = recorded_records();
+        (pool, records, args.get(1))
+    } else {
+        let n_cards = parse(0, DEFAULT_CARDS)?.max(DECK_SIZE * 2);
+        let n_matches = parse(1, DEFAULT_MATCHES)?;
+        let (pool, records) = simulated_records(n_cards, n_matches);
+        (pool, records, args.get(2))
+    };
+    let out_path = out_path.map(String::as_str).unwrap_or(PRICE_CONFIG_PATH);
+
+    let config = PriceConfig::get().clone();
+    let samples = pool
+        .iter()
+        .zip(records)
+        .filter(|(_, record)| record.matches >= MIN_MATCHES)
+        .map(|(card, record)| {
+            let win_rate = record.wins as f32 / record.matches as f32;
+            let (features, offset) = linearize(&config, card);
+            Sample {
+                card,
+                features,
+                offset,
+                observed: price(&config, card) + ENERGY_PER_WIN_RATE * (win_rate - 0.5),
+                win_rate,
+                matches: record.matches,
+            }
+        })
+        .collect::<Vec<_>>();
+    if samples.is_empty() {
+        return Err("No card was summoned often enough to fit against, try more matches".into());
+    }
+
+    let mut fitted = config.clone();
+    let current = fitted.fitted_coefficients().map(|(_, c)| *c);
+    let coefficients = fit(&samples, &current).ok_or("Could not fit coefficients")?;
+    println!("\nCoefficients fitted to {} cards:", samples.len());
+    for ((name, c), new) in fitted.fitted_coefficients().into_iter().zip(coefficients) {
+        println!("  {name:<20} {:>8.3} -> {new:>8.3}", *c);
+        *c = new;
+    }
+    println!(
+        "RMS error: {:.2} -> {:.2} energy",
+        rms_error(&samples, &config),
+        rms_error(&samples, &fitted)
+    );
+
+    let mut mispriced = samples.iter().collect::<Vec<_>>();
+    mispriced.sort_by(|a, b| {
+        let error = |s: &Sample| (price(&config, s.card) - s.observed).abs();
+        error(b).total_cmp(&error(a))
+    });
+    println!("\nMost mispriced cards:");
+    for Sample { card, observed, win_rate, matches, .. } in mispriced.iter().take(REPORTED_CARDS) {
+        println!(
+            "  {:<28} price {:>5.1} (now {:>5.1}) observed {observed:>5.1}  won {:.0}% of \
+             {matches}",
+            card.name,
+            price(&config, card),
+            price(&fitted, card),
+            win_rate * 100.,
+        );
+    }
+
+    let json = serde_json::to_string_pretty(&fitted).map_err(|e| e.to_string())?;
+    std::fs::write(out_path, json).map_err(|e| format!("Failed to write {out_path}: {e}"))?;
+    println!("\nWrote {out_path}");
+    Ok(())
+}
@@ -0,0 +1,40 @@
+use std::error::Error;
+
+use botgame::cards::{
+    deck::Deck,
+    deck_list::{CardLibrary, DeckList},
+};
+use ron::ser::PrettyConfig;
+
+const USAGE: &str = "\
+usage:
+  convert_deck build <library.ron> <list.ron>  print the .deck file for a deck list
+  convert_deck split <file.deck> <library.ron>  add the deck's cards to the library and print its \
+                     deck list";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["build", library, list] => {
+            let library: CardLibrary = ron::from_str(&std::fs::read_to_string(library)?)?;
+            let list: DeckList = ron::from_str(&std::fs::read_to_string(list)?)?;
+            println!("{}", serde_json::to_string_pretty(&list.build(&library)?)?);
+        },
+        ["split", deck, library_path] => {
+            let deck: Deck = serde_json::from_str(&std::fs::read_to_string(deck)?)?;
+            let mut library = match std::fs::read_to_string(library_path) {
+                Ok(text) => ron::from_str(&text)?,
+                Err(_) => CardLibrary::default(),
+            };
+            let list = DeckList::from_deck(&deck, &mut library)?;
+            std::fs::write(library_path, ron::ser::to_string_pretty(&library, pretty())?)?;
+            println!("{}", ron::ser::to_string_pretty(&list, pretty())?);
+        },
+        _ => return Err(USAGE.into()),
+    }
+    Ok(())
+}
+
+fn pretty() -> PrettyConfig {
+    PrettyConfig::new().struct_names(false).depth_limit(4)
+}
@@ -0,0 +1,108 @@
+use botgame::cards::{
+    deck::Deck,
+    deck_generator::{generate_deck, Archetype, CurveShape, DeckConstraints},
+    Ability, Card, Effect, EffectType,
+};
+use rand::{rngs::StdRng, SeedableRng};
+
+const USAGE: &str = "\
+usage: generate_deck [options]
+  --archetype aggro|aoe|support|big-energy
+  --budget N          most total summon cost
+  --curve low|flat|high
+  --require TYPE,...  physical, explosion, fire or electrical damage the deck must deal
+  --hp MIN-MAX        hp range for every unit
+  --seed N
+  --count N           number of candidates
+  --json              print the decks as .deck files";
+
+fn parse<T: std::str::FromStr>(value: Option<&String>) -> Result<T, String> {
+    value.and_then(|v| v.parse().ok()).ok_or(USAGE.to_string())
+}
+
+fn main() -> Result<(), String> {
+    let mut constraints = DeckConstraints::default();
+    let mut seed = rand::random::<u64>() % 100_000;
+    let mut count = 1;
+    let mut json = false;
+
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--archetype" => {
+                constraints.archetype = Some(match args.next().map(String::as_str) {
+                    Some("aggro") => Archetype::Aggro,
+                    Some("aoe") => Archetype::Aoe,
+                    Some("support") => Archetype::Support,
+                    Some("big-energy") => Archetype::BigEnergy,
+                    _ => return Err(USAGE.to_string()),
+                })
+            },
+            "--budget" => constraints.budget = Some(parse(args.next())?),
+            "--curve" => {
+                constraints.curve = match args.next().map(String::as_str) {
+                    Some("low") => CurveShape::Low,
+                    Some("flat") => CurveShape::Flat,
+                    Some("high") => CurveShape::High,
+                    _ => return Err(USAGE.to_string()),
+                }
+            },
+            "--require" => {
+                let types = args.next().ok_or(USAGE.to_string())?;
+                for effect_type in types.split(',') {
+                    constraints.required_effect_types.push(match effect_type {
+                        "physical" => EffectType::Physical,
+                        "explosion" => EffectType::Explosion,
+                        "fire" => EffectType::Fire,
+                        "electrical" => EffectType::Electrical,
+                        _ => return Err(USAGE.to_string()),
+                    });
+                }
+            },
+            "--hp" => {
+                let range = args.next().ok_or(USAGE.to_string())?;
+                let (min, max) = range.split_once('-').ok_or(USAGE.to_string())?;
+                constraints.min_hp = min.parse().map_err(|_| USAGE.to_string())?;
+                constraints.max_hp = max.parse().map_err(|_| USAGE.to_string())?;
+            },
+            "--seed" => seed = parse(args.next())?,
+            "--count" => count = parse(args.next())?,
+            "--json" => json = true,
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    // each candidate gets its own seed so any one of them can be reproduced
+    for seed in seed..seed + count {
+        let Some(deck) = generate_deck(&mut StdRng::seed_from_u64(seed), &constraints) else {
+            eprintln!("seed {seed}: no deck meets the constraints");
+            continue;
+        };
+        if json {
+            println!("{}", serde_json::to_string_pretty(&deck).unwrap());
+        } else {
+            print_deck(seed, &deck);
+        }
+    }
+    Ok(())
+}
+
+fn print_deck(seed: u64, deck: &Deck) {
+    let cards = deck
+        .deck
+        .abilities
+        .iter()
+        .filter_map(|ability| match ability {
+            Ability::Activated { effect: Effect::SummonCard { card }, .. } => Some(card),
+            _ => None,
+        })
+        .collect::<Vec<&Card>>();
+    let total = cards.iter().map(|card| card.summon_cost.energy).sum::<u32>();
+
+    println!("seed {seed}: {total} total summon cost");
+    for card in cards {
+        println!("\n{{{}}} {}", card.summon_cost.energy, card.full_text());
+    }
+    println!("\n");
+}
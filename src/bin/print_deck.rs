@@ -1,11 +1,8 @@
-use std::vec;
-
 use botgame::cards::{
-    deck::{make_deck, random_deck, Deck},
-    generator::random_card,
-    Ability, AbilityCost, Card, Cost, Effect,
-    Effect::MultipleEffects,
-    EffectType, ImplicitTargetRules, PassiveEffect, TargetAmount, TargetFilter, TargetRules,
+    deck::{make_deck, seeded_deck, Deck},
+    price::PriceConfig,
+    Ability, AbilityCost, Card, Cost, Effect, EffectType, ImplicitTargetRules, PassiveEffect,
+    TargetAmount, TargetFilter, TargetRules, TargetSelection, TargetStep,
 };
 
 fn aoe_deck() -> Deck {
@@ -15,11 +12,14 @@ fn aoe_deck() -> Deck {
             summon_cost: Cost { energy: 3 },
             hp: 6,
             abilities: vec![Ability::Activated {
-                effect: Effect::Attack { damage: 1, effect_type: EffectType::Physical },
+                effect: Effect::Attack { damage: 1, effect_type: EffectType::Physical, falloff: 0 },
                 cost: AbilityCost::Static { cost: Cost { energy: 2 } },
                 target_rules: TargetRules {
                     amount: TargetAmount::All,
                     filter: TargetFilter::Occupied,
+                    selection: TargetSelection::Chosen,
+                    then: vec![],
+                    area: None,
                 },
             }],
             starting_energy: 1,
@@ -48,36 +48,42 @@ fn aoe_deck() -> Deck {
             hp: 10,
             abilities: vec![
                 Ability::Activated {
-                    effect: Effect::GrantAbility {
-                        ability: Box::new(Ability::Passive {
+                    effect: Effect::GrantAbilities {
+                        abilities: vec![Ability::Passive {
                             passive_effect: PassiveEffect::DamageResistance {
                                 effect_type: EffectType::Physical,
                                 factor: 2.0,
                             },
                             //fixme
                             target_filter: TargetFilter::ThisUnit,
-                        }),
+                        }],
                     },
                     cost: AbilityCost::Static { cost: Cost { energy: 3 } },
                     target_rules: TargetRules {
                         amount: TargetAmount::UpToN { n: 3 },
                         filter: TargetFilter::Occupied,
+                        selection: TargetSelection::Chosen,
+                        then: vec![],
+                        area: None,
                     },
                 },
                 Ability::Activated {
-                    effect: Effect::GrantAbility {
-                        ability: Box::new(Ability::Passive {
+                    effect: Effect::GrantAbilities {
+                        abilities: vec![Ability::Passive {
                             passive_effect: PassiveEffect::DamageResistance {
                                 effect_type: EffectType::Physical,
                                 factor: 0.5,
                             },
                             target_filter: TargetFilter::ThisUnit,
-                        }),
+                        }],
                     },
                     cost: AbilityCost::Static { cost: Cost { energy: 3 } },
                     target_rules: TargetRules {
                         amount: TargetAmount::UpToN { n: 3 },
                         filter: TargetFilter::Occupied,
+                        selection: TargetSelection::Chosen,
+                        then: vec![],
+                        area: None,
                     },
                 },
             ],
@@ -97,11 +103,18 @@ pub fn gigablaster_deck() -> Deck {
             hp: 15,
             abilities: vec![
                 Ability::Activated {
-                    effect: Effect::Attack { damage: 50, effect_type: EffectType::Explosion },
+                    effect: Effect::Attack {
+                        damage: 50,
+                        effect_type: EffectType::Explosion,
+                        falloff: 0,
+                    },
                     cost: AbilityCost::Static { cost: Cost { energy: 50 } },
                     target_rules: TargetRules {
                         amount: TargetAmount::N { n: 1 },
                         filter: TargetFilter::Occupied,
+                        selection: TargetSelection::Chosen,
+                        then: vec![],
+                        area: None,
                     },
                 },
                 Ability::Passive {
@@ -124,12 +137,24 @@ pub fn gigablaster_deck() -> Deck {
             hp: 3,
             abilities: vec![{
                 Ability::Activated {
-                    effect: Effect::Attack { damage: 3, effect_type: EffectType::Explosion },
+                    effect: Effect::Attack {
+                        damage: 3,
+                        effect_type: EffectType::Explosion,
+                        falloff: 0,
+                    },
                     cost: AbilityCost::Static { cost: Cost { energy: 1 } },
-                    //todo two phase targeting
                     target_rules: TargetRules {
-                        amount: TargetAmount::N { n: 2 },
+                        amount: TargetAmount::N { n: 1 },
                         filter: TargetFilter::Occupied,
+                        selection: TargetSelection::Chosen,
+                        then: vec![TargetStep {
+                            amount: TargetAmount::UpToN { n: 2 },
+                            filter: TargetFilter::And(vec![
+                                TargetFilter::Adjacent,
+                                TargetFilter::Occupied,
+                            ]),
+                        }],
+                        area: None,
                     },
                 }
             }],
@@ -147,70 +172,85 @@ pub fn gigablaster_deck() -> Deck {
                     target_rules: TargetRules {
                         amount: TargetAmount::N { n: 1 },
                         filter: TargetFilter::Occupied,
+                        selection: TargetSelection::Chosen,
+                        then: vec![],
+                        area: None,
                     },
                 },
                 Ability::Activated {
-                    effect: Effect::GrantAbility {
-                        ability: Box::new(Ability::Passive {
+                    effect: Effect::GrantAbilities {
+                        abilities: vec![Ability::Passive {
                             passive_effect: PassiveEffect::DamageResistance {
                                 effect_type: EffectType::Physical,
                                 factor: 0.5,
                             },
                             target_filter: TargetFilter::ThisUnit,
-                        }),
+                        }],
                     },
                     cost: AbilityCost::Static { cost: Cost { energy: 3 } },
                     target_rules: TargetRules {
                         amount: TargetAmount::N { n: 1 },
                         filter: TargetFilter::Occupied,
+                        selection: TargetSelection::Chosen,
+                        then: vec![],
+                        area: None,
                     },
                 },
                 Ability::Activated {
-                    effect: Effect::GrantAbility {
-                        ability: Box::new(Ability::Passive {
+                    effect: Effect::GrantAbilities {
+                        abilities: vec![Ability::Passive {
                             passive_effect: PassiveEffect::DamageResistance {
                                 effect_type: EffectType::Explosion,
                                 factor: 0.5,
                             },
                             target_filter: TargetFilter::ThisUnit,
-                        }),
+                        }],
                     },
                     cost: AbilityCost::Static { cost: Cost { energy: 3 } },
                     target_rules: TargetRules {
                         amount: TargetAmount::N { n: 1 },
                         filter: TargetFilter::Occupied,
+                        selection: TargetSelection::Chosen,
+                        then: vec![],
+                        area: None,
                     },
                 },
                 Ability::Activated {
-                    effect: Effect::GrantAbility {
-                        ability: Box::new(Ability::Passive {
+                    effect: Effect::GrantAbilities {
+                        abilities: vec![Ability::Passive {
                             passive_effect: PassiveEffect::DamageResistance {
                                 effect_type: EffectType::Electrical,
                                 factor: 0.5,
                             },
                             target_filter: TargetFilter::ThisUnit,
-                        }),
+                        }],
                     },
                     cost: AbilityCost::Static { cost: Cost { energy: 3 } },
                     target_rules: TargetRules {
                         amount: TargetAmount::N { n: 1 },
                         filter: TargetFilter::Occupied,
+                        selection: TargetSelection::Chosen,
+                        then: vec![],
+                        area: None,
                     },
                 },
                 Ability::Activated {
-                    effect: Effect::GrantAbility {
-                        ability: Box::new(Ability::Passive {
+                    effect: Effect::GrantAbilities {
+                        abilities: vec![Ability::Passive {
                             passive_effect: PassiveEffect::DamageResistance {
                                 effect_type: EffectType::Fire,
                                 factor: 0.5,
                             },
                             target_filter: TargetFilter::ThisUnit,
-                        }),
+                        }],
                     },
                     cost: AbilityCost::Static { cost: Cost { energy: 3 } },
                     target_rules: TargetRules {
                         amount: TargetAmount::N { n: 1 },
                         filter: TargetFilter::Occupied,
+                        selection: TargetSelection::Chosen,
+                        then: vec![],
+                        area: None,
                     },
                 },
             ],
@@ -222,6 +262,33 @@ pub fn gigablaster_deck() -> Deck {
     make_deck(deck)
 }
 
+/// Sets the summon cost of every card in `deck` to its price, and prints the prices.
+fn auto_cost(mut deck: Deck) -> Deck {
+    for ability in &mut deck.deck.abilities {
+        let Ability::Activated { effect: Effect::SummonCard { card }, .. } = ability else {
+            continue;
+        };
+        let price = PriceConfig::get().price_of(card);
+        eprintln!("{}: {price:.1} (was {})", card.name, card.summon_cost.energy);
+        card.summon_cost = Cost { energy: price.round().max(0.) as u32 };
+    }
+    deck
+}
+
+const USAGE: &str = "usage: print_deck [random [seed] | aoe | gigablaster]";
+
 fn main() {
-    println!("{}", serde_json::to_string_pretty(&random_deck()).unwrap())
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let deck = match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        [] | ["random"] => {
+            let seed = rand::random::<u64>();
+            eprintln!("seed {seed}");
+            seeded_deck(seed)
+        },
+        ["random", seed] => seeded_deck(seed.parse().expect(USAGE)),
+        ["aoe"] => auto_cost(aoe_deck()),
+        ["gigablaster"] => auto_cost(gigablaster_deck()),
+        _ => panic!("{USAGE}"),
+    };
+    println!("{}", serde_json::to_string_pretty(&deck).unwrap())
 }
@@ -0,0 +1,109 @@
+use std::{collections::BTreeMap, time::Instant};
+
+use botgame::{
+    cards::{
+        deck::{seeded_deck, Deck},
+        Ability, Card, Effect,
+    },
+    match_sim::simulation::{simulate_match, AbilityStats, CardPath, DeckStats},
+};
+
+const USAGE: &str = "usage: simulate <deck|random|random:SEED> <deck|random|random:SEED> [matches]";
+const DEFAULT_MATCHES: usize = 100;
+
+fn load_deck(arg: &str) -> Result<(String, Deck), String> {
+    let seed = match arg.strip_prefix("random") {
+        Some("") => Some(rand::random()),
+        Some(seed) => seed.strip_prefix(':').and_then(|seed| seed.parse().ok()),
+        None => None,
+    };
+    if let Some(seed) = seed {
+        return Ok((format!("random:{seed}"), seeded_deck(seed)));
+    }
+    let fd = std::fs::File::open(arg).map_err(|e| format!("Failed to open {arg}: {e}"))?;
+    let deck = serde_json::from_reader(fd).map_err(|e| format!("Failed to parse {arg}: {e}"))?;
+    Ok((arg.to_string(), deck))
+}
+
+/// Every card that can be summoned when playing with `deck`, by where it is in the deck.
+fn collect_cards<'a>(card: &'a Card, path: CardPath, cards: &mut BTreeMap<CardPath, &'a Card>) {
+    for (idx, ability) in card.abilities.iter().enumerate() {
+        if let Ability::Activated { effect: Effect::SummonCard { card }, .. } = ability {
+            let mut path = path.clone();
+            path.push(idx);
+            collect_cards(card, path, cards);
+        }
+    }
+    cards.insert(path, card);
+}
+
+fn ability_label(card: &Card, idx: usize) -> String {
+    match card.abilities.get(idx) {
+        Some(Ability::Activated { effect: Effect::SummonCard { card }, .. }) => {
+            format!("Summon {}", card.name)
+        },
+        Some(Ability::Activated { effect: Effect::MultipleEffects { effects }, .. })
+            if effects.is_empty() =>
+        {
+            "Pass".to_string()
+        },
+        Some(ability) => ability.full_text().lines().next().unwrap_or_default().to_string(),
+        None => format!("granted ability #{idx}"),
+    }
+}
+
+fn main() -> Result<(), String> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let [a, b, rest @ ..] = args.as_slice() else { return Err(USAGE.to_string()) };
+    let matches = match rest {
+        [] => DEFAULT_MATCHES,
+        [n] => n.parse().map_err(|_| USAGE.to_string())?,
+        _ => return Err(USAGE.to_string()),
+    };
+    let decks = [load_deck(a)?, load_deck(b)?];
+
+    let started = Instant::now();
+    let mut wins = [0; 2];
+    let mut draws = 0;
+    let mut total_turns = 0;
+    let mut stats: [DeckStats; 2] = Default::default();
+    for i in 0..matches {
+        // alternate who goes first so neither deck gets the tempo advantage every time
+        let result = simulate_match([&decks[0].1.deck, &decks[1].1.deck], i % 2);
+        match result.winner {
+            Some(winner) => wins[winner] += 1,
+            None => draws += 1,
+        }
+        total_turns += result.turns;
+        for (total, deck_stats) in stats.iter_mut().zip(result.stats) {
+            total.merge(deck_stats);
+        }
+    }
+
+    let pct = |n: usize| 100. * n as f32 / matches.max(1) as f32;
+    println!("{matches} matches in {:.1?}", started.elapsed());
+    for ((name, _), won) in decks.iter().zip(wins) {
+        println!("  {name}: {won} wins ({:.1}%)", pct(won));
+    }
+    println!("  draws: {draws} ({:.1}%)", pct(draws));
+    println!("  average length: {:.1} turns", total_turns as f32 / matches.max(1) as f32);
+
+    for ((name, deck), stats) in decks.iter().zip(&stats) {
+        let mut cards = BTreeMap::new();
+        collect_cards(&deck.deck, vec![], &mut cards);
+
+        println!("\n{name}");
+        for (path, card) in &cards {
+            let summoned = stats.summons.get(path).copied().unwrap_or_default();
+            println!("  {} (summoned {summoned} times)", card.name);
+            let mut used =
+                stats.abilities.iter().filter(|((p, _), _)| p == path).collect::<Vec<_>>();
+            used.sort_by_key(|((_, idx), _)| *idx);
+            for ((_, idx), AbilityStats { uses, damage }) in used {
+                let label = ability_label(card, *idx);
+                println!("    {uses:>6} uses {damage:>7} damage  {label}");
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,59 +1,156 @@
-use bevy::{prelude::*, utils::HashMap};
+use std::error::Error;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    utils::{BoxedFuture, HashMap},
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
 
 use crate::cards::{
-    generator::random_card, Ability, AbilityCost, Attribute, Card, Cost, Effect, TargetAmount,
-    TargetFilter, TargetRules,
+    generator::random_card,
+    validation::{self, DeckIssue},
+    Ability, AbilityCost, Attribute, Card, Cost, Effect, TargetAmount, TargetFilter, TargetRules,
+    TargetSelection,
 };
 
-// #[derive(Asset, TypePath)]
-#[derive(Serialize, Deserialize, Reflect)]
+#[derive(Asset, Clone, Serialize, Deserialize, Reflect)]
 pub struct Deck {
     pub deck: Card,
 }
+impl Deck {
+    /// Everything that would make the deck misbehave in a match.
+    pub fn validate(&self) -> Result<(), Vec<DeckIssue>> {
+        validation::validate(&self.deck)
+    }
+}
+
+#[derive(Default)]
+pub struct DeckLoader;
+impl AssetLoader for DeckLoader {
+    type Asset = Deck;
+    type Settings = ();
+    type Error = Box<dyn Error + Send + Sync + 'static>;
 
-// pub struct DeckLoader;
-// impl AssetLoader for DeckLoader {
-//     type Asset = Deck;
-//     type Settings = ();
-//     type Error = Box<dyn Error + Send + Sync + 'static>;
-//
-//     fn load<'a>(
-//         &'a self,
-//         reader: &'a mut Reader,
-//         settings: &'a Self::Settings,
-//         load_context: &'a mut LoadContext,
-//     ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
-//         Box::pin(async {
-//             let mut bytes = Vec::new();
-//             reader.read_to_end(&mut bytes).await?;
-//             let deck = serde_json::from_slice(&bytes)?;
-//             Ok(deck)
-//         })
-//     }
-//
-//     fn extensions(&self) -> &[&str] {
-//         &["deck"]
-//     }
-// }
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a Self::Settings,
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            // the asset server already names the file, so just say where in it things went wrong
+            let deck =
+                serde_path_to_error::deserialize(&mut serde_json::Deserializer::from_slice(&bytes))
+                    .map_err(|err| format!("invalid deck at `{}`: {}", err.path(), err.inner()))?;
+            Ok(deck)
+        })
+    }
 
+    fn extensions(&self) -> &[&str] {
+        &["deck"]
+    }
+}
+
+/// Every deck the player can queue with, by name.
 #[derive(Resource, Default)]
 pub struct Decks(pub HashMap<String, Deck>);
 
-pub fn load_decks(mut decks: ResMut<Decks>) {
-    for file in std::fs::read_dir("assets/decks").unwrap() {
-        let Ok(file) = file else { continue };
-        let name = file.file_name().into_string().unwrap().split(".").next().unwrap().to_string();
-        let Ok(fd) = std::fs::File::open(file.path()) else { continue };
-        let Ok(deck) = serde_json::from_reader(fd) else { continue };
-        decks.0.insert(name, deck);
+pub const RANDOM_DECK: &str = "Random";
+
+/// Nothing can be summoned for more than this.
+pub const COMMAND_CENTER_MAX_ENERGY: u32 = 10;
+
+/// Decks shipped with the web build, which can't list the folder.
+#[cfg(target_arch = "wasm32")]
+const BUILTIN_DECKS: [&str; 2] = ["Gigablaster.deck", "Shrapnel.deck"];
+
+/// Keeps the deck files loaded, and watched for changes.
+#[derive(Resource)]
+pub struct DeckHandles(Vec<UntypedHandle>);
+
+/// Seed of the "Random" deck in [`Decks`].
+#[derive(Resource)]
+pub struct RandomDeckSeed(pub u64);
+impl Default for RandomDeckSeed {
+    fn default() -> Self {
+        // short enough to read out in a bug report
+        Self(rand::thread_rng().gen_range(0..100_000))
     }
+}
+
+pub fn load_decks(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut decks: ResMut<Decks>,
+    seed: Res<RandomDeckSeed>,
+) {
+    #[cfg(not(target_arch = "wasm32"))]
+    let handles = vec![asset_server.load_folder("decks").untyped()];
+    #[cfg(target_arch = "wasm32")]
+    let handles = BUILTIN_DECKS
+        .iter()
+        .map(|file| asset_server.load::<Deck>(format!("decks/{file}")).untyped())
+        .collect();
+    commands.insert_resource(DeckHandles(handles));
 
-    decks.0.insert("Random".to_string(), random_deck());
+    decks.0.insert(RANDOM_DECK.to_string(), seeded_deck(seed.0));
 }
 
-pub fn random_deck() -> Deck {
-    make_deck((0..5).map(|_| random_card()).collect())
+/// Mirrors the loaded deck files into [`Decks`], named after the file.
+pub fn sync_decks(
+    mut events: EventReader<AssetEvent<Deck>>,
+    assets: Res<Assets<Deck>>,
+    asset_server: Res<AssetServer>,
+    mut decks: ResMut<Decks>,
+) {
+    if events.read().count() == 0 {
+        return;
+    }
+
+    decks.0.retain(|name, _| name == RANDOM_DECK);
+    for (id, deck) in assets.iter() {
+        let Some(path) = asset_server.get_path(id) else { continue };
+        let Some(name) = path.path().file_stem() else { continue };
+        decks.0.insert(name.to_string_lossy().into_owned(), deck.clone());
+    }
+}
+
+pub fn update_random_deck(mut decks: ResMut<Decks>, seed: Res<RandomDeckSeed>) {
+    decks.0.insert(RANDOM_DECK.to_string(), seeded_deck(seed.0));
+}
+
+pub fn random_deck(rng: &mut impl Rng) -> Deck {
+    // draw every card before replacing any, so seeds whose cards were all fine keep their deck
+    let mut cards = (0..5).map(|_| random_card(rng)).collect::<Vec<_>>();
+    for card in &mut cards {
+        if !is_summonable(card) {
+            *card = random_summonable_card(rng);
+        }
+    }
+    make_deck(cards)
+}
+
+/// A random card that passes deck validation as a summon of the Command Center.
+pub fn random_summonable_card(rng: &mut impl Rng) -> Card {
+    loop {
+        let card = random_card(rng);
+        if is_summonable(&card) {
+            return card;
+        }
+    }
+}
+
+fn is_summonable(card: &Card) -> bool {
+    make_deck(vec![card.clone()]).validate().is_ok()
+}
+
+/// The same seed always gives the same deck.
+pub fn seeded_deck(seed: u64) -> Deck {
+    random_deck(&mut StdRng::seed_from_u64(seed))
 }
 
 pub fn make_deck(cards: Vec<Card>) -> Deck {
@@ -73,6 +170,9 @@ pub fn make_deck(cards: Vec<Card>) -> Deck {
                             TargetFilter::Friendly,
                             TargetFilter::Unoccupied,
                         ]),
+                        selection: TargetSelection::Chosen,
+                        then: vec![],
+                        area: None,
                     },
                 })
                 .chain(std::iter::once(Ability::Activated {
@@ -81,11 +181,50 @@ pub fn make_deck(cards: Vec<Card>) -> Deck {
                     target_rules: TargetRules {
                         amount: TargetAmount::N { n: 0 },
                         filter: TargetFilter::Any,
+                        selection: TargetSelection::Chosen,
+                        then: vec![],
+                        area: None,
                     },
                 }))
                 .collect(),
-            max_energy: 10,
+            max_energy: COMMAND_CENTER_MAX_ENERGY,
             starting_energy: 3,
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summons(deck: &Deck) -> Vec<String> {
+        deck.deck
+            .abilities
+            .iter()
+            .filter_map(|a| match a {
+                Ability::Activated { effect: Effect::SummonCard { card }, .. } => {
+                    Some(serde_json::to_string(card).unwrap())
+                },
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn seeds_keep_their_decks() {
+        let mut kept = 0;
+        for seed in 0..50 {
+            let deck = seeded_deck(seed);
+            assert!(deck.validate().is_ok(), "seed {seed}");
+
+            // the cards random decks were made of before they were validated
+            let mut rng = StdRng::seed_from_u64(seed);
+            let cards = (0..5).map(|_| random_card(&mut rng)).collect::<Vec<_>>();
+            if cards.iter().all(is_summonable) {
+                assert_eq!(summons(&deck), summons(&make_deck(cards)), "seed {seed}");
+                kept += 1;
+            }
+        }
+        assert!(kept > 0);
+    }
+}
@@ -0,0 +1,292 @@
+use rand::{seq::index::sample, Rng};
+
+use crate::cards::{
+    deck::{make_deck, Deck, COMMAND_CENTER_MAX_ENERGY},
+    generator::{random_card_weighted, GeneratorWeights},
+    Ability, Card, Effect, EffectType, PassiveEffect, TargetAmount,
+};
+
+/// Number of candidate cards generated for each search.
+const POOL_SIZE: usize = 150;
+/// Generation attempts per pool slot before giving up on cards in the hp range.
+const POOL_ATTEMPTS: usize = 20;
+const RESTARTS: usize = 8;
+const ITERATIONS: usize = 2_000;
+
+/// Each unmet hard constraint costs this much, so any deck that meets them beats one that doesn't.
+const HARD_PENALTY: f32 = 100.;
+const CURVE_WEIGHT: f32 = 5.;
+const BUDGET_WEIGHT: f32 = 2.;
+const ARCHETYPE_WEIGHT: f32 = 1.;
+/// How many units an attack on `TargetAmount::All` is assumed to hit.
+const ALL_TARGETS: f32 = 5.;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Archetype {
+    /// Cheap units with efficient attacks
+    Aggro,
+    /// Attacks that hit many units at once, like the Shrapnel deck
+    Aoe,
+    /// Heals, energy and granted abilities for friendly units
+    Support,
+    /// Few, huge attacks charged up over many turns, like the Gigablaster deck
+    BigEnergy,
+}
+impl Archetype {
+    /// Tilts the card generator towards the archetype.
+    pub fn weights(&self) -> GeneratorWeights {
+        let default = GeneratorWeights::default();
+        match self {
+            Archetype::Aggro => GeneratorWeights {
+                attack: 80,
+                heal: 5,
+                energy_transfer: 5,
+                summon: 5,
+                grant_ability: 5,
+                ..default
+            },
+            Archetype::Aoe => GeneratorWeights { attack: 80, all_targets: 40, ..default },
+            Archetype::Support => GeneratorWeights {
+                attack: 20,
+                heal: 40,
+                energy_transfer: 30,
+                grant_ability: 25,
+                when_hit: 40,
+                when_dies: 30,
+                self_targeted: 10,
+                ..default
+            },
+            Archetype::BigEnergy => {
+                GeneratorWeights { attack: 70, energy_transfer: 30, heal: 5, ..default }
+            },
+        }
+    }
+
+    /// How well `card` suits the archetype, higher is better.
+    pub fn fit(&self, card: &Card) -> f32 {
+        match self {
+            Archetype::Aggro => {
+                let damage_per_energy = attacks(card)
+                    .map(|attack| attack.damage * attack.targets / (attack.cost + 1.))
+                    .sum::<f32>();
+                damage_per_energy / (1. + card.summon_cost.energy as f32 / 4.)
+            },
+            Archetype::Aoe => attacks(card)
+                .filter(|attack| attack.targets >= 3.)
+                .map(|attack| attack.damage * attack.targets / (attack.cost + 1.))
+                .sum(),
+            Archetype::Support => card.abilities.iter().filter(|a| is_supportive(a)).count() as f32,
+            Archetype::BigEnergy => {
+                let biggest = attacks(card)
+                    .map(|attack| attack.damage * attack.targets)
+                    .max_by(f32::total_cmp)
+                    .unwrap_or_default();
+                biggest / 10. + card.max_energy as f32 / 10.
+            },
+        }
+    }
+}
+
+/// Shape of the deck's sorted summon costs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CurveShape {
+    Any,
+    /// Mostly cheap cards with a few expensive ones
+    Low,
+    /// Costs spread evenly
+    Flat,
+    /// Mostly expensive cards with a few cheap ones
+    High,
+}
+impl CurveShape {
+    /// Fraction of the deck's total cost each card should have, cheapest first.
+    fn targets(&self, deck_size: usize) -> Option<Vec<f32>> {
+        let curve = |f: f32| match self {
+            CurveShape::Any => 1.,
+            CurveShape::Low => 1. + 2. * f * f,
+            CurveShape::Flat => 1. + 2. * f,
+            CurveShape::High => 1. + 2. * f.sqrt(),
+        };
+        if *self == CurveShape::Any {
+            return None;
+        }
+
+        let weights = (0..deck_size)
+            .map(|i| curve(i as f32 / (deck_size.max(2) - 1) as f32))
+            .collect::<Vec<_>>();
+        let total = weights.iter().sum::<f32>();
+        Some(weights.into_iter().map(|w| w / total).collect())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DeckConstraints {
+    pub deck_size: usize,
+    /// Most energy the summon costs of the deck can add up to
+    pub budget: Option<u32>,
+    pub curve: CurveShape,
+    /// Damage types the deck must be able to deal
+    pub required_effect_types: Vec<EffectType>,
+    pub archetype: Option<Archetype>,
+    pub min_hp: u32,
+    pub max_hp: u32,
+}
+impl Default for DeckConstraints {
+    fn default() -> Self {
+        Self {
+            deck_size: 5,
+            budget: None,
+            curve: CurveShape::Any,
+            required_effect_types: vec![],
+            archetype: None,
+            min_hp: 0,
+            max_hp: u32::MAX,
+        }
+    }
+}
+
+impl DeckConstraints {
+    fn over_budget(&self, cards: &[&Card]) -> Option<u32> {
+        let total = total_cost(cards);
+        self.budget.filter(|budget| total > *budget).map(|budget| total - budget)
+    }
+
+    fn missing_effect_types(&self, cards: &[&Card]) -> Vec<EffectType> {
+        let dealt = cards.iter().flat_map(|card| attacks(card)).map(|attack| attack.effect_type);
+        let dealt = dealt.collect::<Vec<_>>();
+        let missing = self.required_effect_types.iter().filter(|t| !dealt.contains(t));
+        missing.copied().collect()
+    }
+
+    /// Lower is better. Below [`HARD_PENALTY`] only if every hard constraint is met.
+    fn penalty(&self, cards: &[&Card]) -> f32 {
+        let mut penalty = 0.;
+
+        let hard = self.over_budget(cards).unwrap_or_default() as usize
+            + self.missing_effect_types(cards).len();
+        penalty += hard as f32 * HARD_PENALTY;
+
+        let total = total_cost(cards);
+        if let Some(budget) = self.budget.filter(|budget| *budget > 0) {
+            // a budget is there to be spent
+            penalty += budget.saturating_sub(total) as f32 / budget as f32 * BUDGET_WEIGHT;
+        }
+
+        if let Some(targets) = self.curve.targets(cards.len()) {
+            let mut costs = cards.iter().map(|card| card.summon_cost.energy).collect::<Vec<_>>();
+            costs.sort();
+            let total = total.max(1) as f32;
+            let deviation = costs
+                .iter()
+                .zip(targets)
+                .map(|(cost, target)| (*cost as f32 - target * total).abs())
+                .sum::<f32>();
+            penalty += deviation / total * CURVE_WEIGHT;
+        }
+
+        if let Some(archetype) = self.archetype {
+            let fit = cards.iter().map(|card| archetype.fit(card)).sum::<f32>();
+            penalty -= fit / cards.len().max(1) as f32 * ARCHETYPE_WEIGHT;
+        }
+        penalty
+    }
+}
+
+/// Searches generated cards for a deck meeting `constraints`, or `None` if none was found.
+pub fn generate_deck(rng: &mut impl Rng, constraints: &DeckConstraints) -> Option<Deck> {
+    let weights = constraints.archetype.map_or_else(GeneratorWeights::default, |a| a.weights());
+    let hp = constraints.min_hp..=constraints.max_hp;
+    let pool = (0..POOL_SIZE * POOL_ATTEMPTS)
+        .map(|_| random_card_weighted(rng, &weights))
+        .filter(|card| {
+            hp.contains(&card.hp) && card.summon_cost.energy <= COMMAND_CENTER_MAX_ENERGY
+        })
+        .take(POOL_SIZE)
+        .collect::<Vec<_>>();
+    let deck_size = constraints.deck_size;
+    if pool.len() < deck_size {
+        return None;
+    }
+
+    let penalty = |picks: &[usize]| {
+        let cards = picks.iter().map(|idx| &pool[*idx]).collect::<Vec<_>>();
+        constraints.penalty(&cards)
+    };
+
+    // hill climbing from a few random starts, swapping one card at a time
+    let mut best: Option<(f32, Vec<usize>)> = None;
+    for _ in 0..RESTARTS {
+        let mut picks = sample(rng, pool.len(), deck_size).into_vec();
+        let mut score = penalty(&picks);
+        for _ in 0..ITERATIONS {
+            let candidate = rng.gen_range(0..pool.len());
+            if picks.contains(&candidate) {
+                continue;
+            }
+            let slot = rng.gen_range(0..deck_size);
+            let replaced = std::mem::replace(&mut picks[slot], candidate);
+            let new_score = penalty(&picks);
+            if new_score <= score {
+                score = new_score;
+            } else {
+                picks[slot] = replaced;
+            }
+        }
+        if !best.as_ref().is_some_and(|(best_score, _)| *best_score <= score) {
+            best = Some((score, picks));
+        }
+    }
+
+    let (score, picks) = best?;
+    if score >= HARD_PENALTY {
+        return None;
+    }
+    let mut cards = picks.into_iter().map(|idx| pool[idx].clone()).collect::<Vec<_>>();
+    cards.sort_by_key(|card| card.summon_cost.energy);
+    Some(make_deck(cards))
+}
+
+fn total_cost(cards: &[&Card]) -> u32 {
+    cards.iter().map(|card| card.summon_cost.energy).sum()
+}
+
+struct AttackStats {
+    damage: f32,
+    targets: f32,
+    cost: f32,
+    effect_type: EffectType,
+}
+
+fn attacks(card: &Card) -> impl Iterator<Item = AttackStats> + '_ {
+    card.abilities.iter().filter_map(|ability| {
+        let Ability::Activated { effect, cost, target_rules } = ability else { return None };
+        let Effect::Attack { damage, effect_type, .. } = effect else { return None };
+        let targets = match target_rules.amount {
+            TargetAmount::All => ALL_TARGETS,
+            TargetAmount::N { n } | TargetAmount::UpToN { n } => n as f32,
+        };
+        Some(AttackStats {
+            damage: *damage as f32,
+            targets,
+            cost: cost.get(effect).ok()?.energy as f32,
+            effect_type: *effect_type,
+        })
+    })
+}
+
+fn is_supportive(ability: &Ability) -> bool {
+    let helps = |effect: &Effect| match effect {
+        Effect::ChangeHp { amount } | Effect::ChangeEnergy { amount } => *amount > 0,
+        Effect::GrantAbilities { .. } => true,
+        _ => false,
+    };
+    match ability {
+        Ability::Activated { effect, .. } => helps(effect),
+        Ability::Passive { passive_effect, .. } => match passive_effect {
+            PassiveEffect::WhenHit { effect, .. } | PassiveEffect::WhenDies { effect, .. } => {
+                helps(effect)
+            },
+            PassiveEffect::DamageResistance { .. } => false,
+        },
+    }
+}
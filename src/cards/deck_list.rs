@@ -0,0 +1,485 @@
+//! A compact, hand-written alternative to `.deck` files: cards are defined once in a
+//! [`CardLibrary`] and a [`DeckList`] names the cards it uses.
+//!
+//! Both are written in RON. Target rules can be shorthand like `"1 enemy unit"`,
+//! `"up to 2 friendly units"`, `"all units"`, `"1 friendly empty space"`, `"2 random enemy units"`,
+//! `"this unit"` or `"no targets"`, and passive target filters like `"friendly units"`. Anything
+//! else is written out in full: `(amount: N(n: 2), filter: Or([Enemy, Unoccupied]))` for target
+//! rules and `(filter: Or([Enemy, Unoccupied]))` for filters. Rules with more than one step,
+//! automatic targets or an area are always in full, like
+//! `(amount: N(n: 1), filter: Occupied, then: [(amount: N(n: 1), filter: Adjacent)])`,
+//! `(amount: N(n: 1), filter: Enemy, selection: Auto(LowestHp))` or
+//! `(amount: N(n: 1), filter: Enemy, area: Some(Splash(radius: 1)))`.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Formatter},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cards::{
+    deck::{make_deck, Deck},
+    validation::DeckProblem,
+    Ability, AbilityCost, Card, Cost, Effect, PassiveEffect, TargetAmount, TargetFilter,
+    TargetRules, TargetSelection,
+};
+
+/// The card library shared by the deck lists and the deck builder.
+pub const CARD_LIBRARY_PATH: &str = "assets/cards.ron";
+
+#[derive(Debug)]
+pub enum DeckListError {
+    UnknownCard(String),
+    /// Two different cards with the same name
+    ConflictingCard(String),
+    /// The Command Center can do something other than summon cards and pass
+    CommandCenterAbility,
+    /// A card that wouldn't pass deck validation, so has no definition
+    InvalidCard(String, DeckProblem),
+}
+impl Display for DeckListError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeckListError::UnknownCard(name) => write!(f, "No card named `{name}` in the library."),
+            DeckListError::ConflictingCard(name) => {
+                write!(f, "The library already has a different card named `{name}`.")
+            },
+            DeckListError::CommandCenterAbility => {
+                f.write_str("Command Center abilities other than summons can't be listed.")
+            },
+            DeckListError::InvalidCard(name, problem) => write!(f, "`{name}`: {problem}"),
+        }
+    }
+}
+impl std::error::Error for DeckListError {}
+
+/// Card definitions by name.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CardLibrary(pub BTreeMap<String, CardDef>);
+impl CardLibrary {
+    pub fn card(&self, name: &str) -> Result<Card, DeckListError> {
+        let def = self.0.get(name).ok_or_else(|| DeckListError::UnknownCard(name.to_string()))?;
+        Ok(def.to_card(name))
+    }
+
+    /// Adds `card` unless an identical card with its name is already there.
+    pub fn add(&mut self, card: &Card) -> Result<(), DeckListError> {
+        let def = CardDef::from_card(card)
+            .map_err(|problem| DeckListError::InvalidCard(card.name.clone(), problem))?;
+        match self.0.get(&card.name) {
+            Some(existing) if !same(existing, &def) => {
+                Err(DeckListError::ConflictingCard(card.name.clone()))
+            },
+            Some(_) => Ok(()),
+            None => {
+                self.0.insert(card.name.clone(), def);
+                Ok(())
+            },
+        }
+    }
+}
+
+/// A [`Card`] without its name, which is its key in the [`CardLibrary`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CardDef {
+    pub cost: u32,
+    pub hp: u32,
+    #[serde(default)]
+    pub starting_energy: u32,
+    #[serde(default)]
+    pub max_energy: u32,
+    #[serde(default)]
+    pub abilities: Vec<AbilityDef>,
+}
+impl CardDef {
+    pub fn to_card(&self, name: &str) -> Card {
+        Card {
+            name: name.to_string(),
+            summon_cost: Cost { energy: self.cost },
+            hp: self.hp,
+            abilities: self.abilities.iter().map(AbilityDef::to_ability).collect(),
+            starting_energy: self.starting_energy,
+            max_energy: self.max_energy,
+        }
+    }
+
+    pub fn from_card(card: &Card) -> Result<Self, DeckProblem> {
+        Ok(Self {
+            cost: card.summon_cost.energy,
+            hp: card.hp,
+            starting_energy: card.starting_energy,
+            max_energy: card.max_energy,
+            abilities: card
+                .abilities
+                .iter()
+                .map(AbilityDef::from_ability)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// An [`Ability`] with a plain energy cost and shorthand targets.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AbilityDef {
+    Activated {
+        effect: Effect,
+        cost: u32,
+        #[serde(with = "shorthand_rules")]
+        targets: TargetRules,
+    },
+    Passive {
+        effect: PassiveEffect,
+        #[serde(with = "shorthand_filter")]
+        targets: TargetFilter,
+    },
+}
+impl AbilityDef {
+    pub fn to_ability(&self) -> Ability {
+        match self {
+            AbilityDef::Activated { effect, cost, targets } => Ability::Activated {
+                effect: effect.clone(),
+                cost: AbilityCost::Static { cost: Cost { energy: *cost } },
+                target_rules: targets.clone(),
+            },
+            AbilityDef::Passive { effect, targets } => {
+                Ability::Passive { passive_effect: effect.clone(), target_filter: targets.clone() }
+            },
+        }
+    }
+
+    pub fn from_ability(ability: &Ability) -> Result<Self, DeckProblem> {
+        Ok(match ability {
+            Ability::Activated { effect, cost, target_rules } => AbilityDef::Activated {
+                effect: effect.clone(),
+                // derived costs are fixed once the card is
+                cost: cost.get(effect)?.energy,
+                targets: target_rules.clone(),
+            },
+            Ability::Passive { passive_effect, target_filter } => AbilityDef::Passive {
+                effect: passive_effect.clone(),
+                targets: target_filter.clone(),
+            },
+        })
+    }
+}
+
+/// Cards from a [`CardLibrary`] and how many of each a deck has. The Command Center is always
+/// the one from [`make_deck`].
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DeckList {
+    pub cards: Vec<(String, u32)>,
+}
+impl DeckList {
+    pub fn build(&self, library: &CardLibrary) -> Result<Deck, DeckListError> {
+        let mut cards = vec![];
+        for (name, count) in &self.cards {
+            let card = library.card(name)?;
+            cards.extend(vec![card; *count as usize]);
+        }
+        Ok(make_deck(cards))
+    }
+
+    /// Lists the cards `deck` can summon, adding their definitions to `library`.
+    pub fn from_deck(deck: &Deck, library: &mut CardLibrary) -> Result<Self, DeckListError> {
+        let mut list = DeckList::default();
+        for ability in &deck.deck.abilities {
+            match ability {
+                Ability::Activated { effect: Effect::SummonCard { card }, .. } => {
+                    library.add(card)?;
+                    match list.cards.last_mut() {
+                        Some((name, count)) if *name == card.name => *count += 1,
+                        _ => list.cards.push((card.name.clone(), 1)),
+                    }
+                },
+                // the pass ability every Command Center has
+                Ability::Activated { effect: Effect::MultipleEffects { effects }, .. }
+                    if effects.is_empty() => {},
+                _ => return Err(DeckListError::CommandCenterAbility),
+            }
+        }
+        Ok(list)
+    }
+}
+
+fn same(a: &CardDef, b: &CardDef) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+/// Parses target rules shorthand like `"up to 2 enemy units"`.
+pub fn parse_rules(text: &str) -> Option<TargetRules> {
+    let words = text.split_whitespace().collect::<Vec<_>>();
+    let (amount, rest) = match words.as_slice() {
+        ["this", "unit"] => {
+            return Some(TargetRules {
+                amount: TargetAmount::N { n: 1 },
+                filter: TargetFilter::ThisUnit,
+                selection: TargetSelection::Chosen,
+                then: vec![],
+                area: None,
+            })
+        },
+        ["no", "targets"] => {
+            return Some(TargetRules {
+                amount: TargetAmount::N { n: 0 },
+                filter: TargetFilter::Any,
+                selection: TargetSelection::Chosen,
+                then: vec![],
+                area: None,
+            })
+        },
+        [n, "random", rest @ ..] => {
+            let n = n.parse().ok()?;
+            return Some(TargetRules {
+                amount: TargetAmount::N { n },
+                filter: parse_filter_words(rest)?,
+                selection: TargetSelection::Random { n },
+                then: vec![],
+                area: None,
+            });
+        },
+        ["all", rest @ ..] => (TargetAmount::All, rest),
+        ["up", "to", n, rest @ ..] => (TargetAmount::UpToN { n: n.parse().ok()? }, rest),
+        [n, rest @ ..] => (TargetAmount::N { n: n.parse().ok()? }, rest),
+        [] => return None,
+    };
+    Some(TargetRules {
+        amount,
+        filter: parse_filter_words(rest)?,
+        selection: TargetSelection::Chosen,
+        then: vec![],
+        area: None,
+    })
+}
+
+/// Parses target filter shorthand like `"friendly units"`.
+pub fn parse_filter(text: &str) -> Option<TargetFilter> {
+    parse_filter_words(&text.split_whitespace().collect::<Vec<_>>())
+}
+
+fn parse_filter_words(words: &[&str]) -> Option<TargetFilter> {
+    let (owner, rest) = match words {
+        ["this", "unit"] => return Some(TargetFilter::ThisUnit),
+        ["friendly", rest @ ..] => (Some(TargetFilter::Friendly), rest),
+        ["enemy", rest @ ..] => (Some(TargetFilter::Enemy), rest),
+        rest => (None, rest),
+    };
+    let kind = match rest {
+        ["unit" | "units"] => Some(TargetFilter::Occupied),
+        ["empty", "space" | "spaces"] => Some(TargetFilter::Unoccupied),
+        ["space" | "spaces"] => None,
+        _ => return None,
+    };
+    Some(match (owner, kind) {
+        (Some(owner), Some(kind)) => TargetFilter::And(vec![owner, kind]),
+        (Some(filter), None) | (None, Some(filter)) => filter,
+        (None, None) => TargetFilter::Any,
+    })
+}
+
+/// The shorthand for `rules`, if it has one.
+pub fn describe_rules(rules: &TargetRules) -> Option<String> {
+    if !rules.then.is_empty() || rules.area.is_some() {
+        return None;
+    }
+    match rules.selection {
+        TargetSelection::Chosen => {},
+        TargetSelection::Random { n } => {
+            return Some(format!("{n} random {}", describe_filter_words(&rules.filter, n != 1)?))
+        },
+        TargetSelection::Auto(_) => return None,
+    }
+    match (&rules.amount, &rules.filter) {
+        (TargetAmount::N { n: 1 }, TargetFilter::ThisUnit) => return Some("this unit".to_string()),
+        (TargetAmount::N { n: 0 }, TargetFilter::Any) => return Some("no targets".to_string()),
+        (_, TargetFilter::ThisUnit) => return None,
+        _ => {},
+    }
+    let (amount, plural) = match rules.amount {
+        TargetAmount::All => ("all".to_string(), true),
+        TargetAmount::N { n } => (n.to_string(), n != 1),
+        TargetAmount::UpToN { n } => (format!("up to {n}"), n != 1),
+    };
+    Some(format!("{amount} {}", describe_filter_words(&rules.filter, plural)?))
+}
+
+/// The shorthand for `filter`, if it has one.
+pub fn describe_filter(filter: &TargetFilter) -> Option<String> {
+    match filter {
+        TargetFilter::ThisUnit => Some("this unit".to_string()),
+        filter => describe_filter_words(filter, true),
+    }
+}
+
+fn describe_filter_words(filter: &TargetFilter, plural: bool) -> Option<String> {
+    let (owner, kind) = match filter {
+        TargetFilter::And(filters) => match filters.as_slice() {
+            [owner @ (TargetFilter::Friendly | TargetFilter::Enemy), kind] => (Some(owner), kind),
+            _ => return None,
+        },
+        owner @ (TargetFilter::Friendly | TargetFilter::Enemy) => (Some(owner), &TargetFilter::Any),
+        kind => (None, kind),
+    };
+    let owner = match owner {
+        Some(TargetFilter::Friendly) => "friendly ",
+        Some(_) => "enemy ",
+        None => "",
+    };
+    let kind = match kind {
+        TargetFilter::Occupied => "unit",
+        TargetFilter::Unoccupied => "empty space",
+        TargetFilter::Any => "space",
+        _ => return None,
+    };
+    let s = if plural { "s" } else { "" };
+    Some(format!("{owner}{kind}{s}"))
+}
+
+/// A filter written out in full. RON can only tell a struct apart from a string, not an enum.
+#[derive(Serialize, Deserialize)]
+struct FullFilter {
+    filter: TargetFilter,
+}
+impl From<TargetFilter> for FullFilter {
+    fn from(filter: TargetFilter) -> Self {
+        Self { filter }
+    }
+}
+impl From<FullFilter> for TargetFilter {
+    fn from(full: FullFilter) -> Self {
+        full.filter
+    }
+}
+
+/// Reads either shorthand or the full value, and writes shorthand when there is one.
+macro_rules! shorthand_serde {
+    ($module:ident, $ty:ty, $full:ty, $parse:ident, $describe:ident, $expected:literal) => {
+        mod $module {
+            use std::fmt::Formatter;
+
+            use serde::{
+                de::{value::MapAccessDeserializer, Error, MapAccess, Visitor},
+                Deserialize, Deserializer, Serialize, Serializer,
+            };
+
+            use super::*;
+
+            pub fn serialize<S: Serializer>(value: &$ty, serializer: S) -> Result<S::Ok, S::Error> {
+                match $describe(value) {
+                    Some(shorthand) => serializer.serialize_str(&shorthand),
+                    None => <$full>::from(value.clone()).serialize(serializer),
+                }
+            }
+
+            pub fn deserialize<'de, D: Deserializer<'de>>(
+                deserializer: D,
+            ) -> Result<$ty, D::Error> {
+                struct ShorthandVisitor;
+                impl<'de> Visitor<'de> for ShorthandVisitor {
+                    type Value = $ty;
+
+                    fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+                        f.write_str($expected)
+                    }
+
+                    fn visit_str<E: Error>(self, v: &str) -> Result<Self::Value, E> {
+                        $parse(v).ok_or_else(|| E::custom(format!("unknown shorthand `{v}`")))
+                    }
+
+                    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                        <$full>::deserialize(MapAccessDeserializer::new(map)).map(Into::into)
+                    }
+                }
+                deserializer.deserialize_any(ShorthandVisitor)
+            }
+        }
+    };
+}
+shorthand_serde!(
+    shorthand_rules,
+    TargetRules,
+    TargetRules,
+    parse_rules,
+    describe_rules,
+    "(amount: .., filter: ..) or shorthand like \"1 enemy unit\""
+);
+shorthand_serde!(
+    shorthand_filter,
+    TargetFilter,
+    FullFilter,
+    parse_filter,
+    describe_filter,
+    "(filter: ..) or shorthand like \"friendly units\""
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shorthand_round_trips() {
+        for text in [
+            "1 enemy unit",
+            "up to 2 friendly units",
+            "all units",
+            "1 friendly empty space",
+            "2 random enemy units",
+            "this unit",
+            "no targets",
+        ] {
+            let rules = parse_rules(text).unwrap();
+            assert_eq!(describe_rules(&rules).as_deref(), Some(text));
+        }
+        for text in ["friendly units", "enemy empty spaces", "this unit", "spaces"] {
+            let filter = parse_filter(text).unwrap();
+            assert_eq!(describe_filter(&filter).as_deref(), Some(text));
+        }
+        assert!(parse_rules("1 purple unit").is_none());
+    }
+
+    #[test]
+    fn library_round_trips_through_ron() {
+        let text = r#"{
+            "Test Bot": (
+                cost: 2,
+                hp: 5,
+                abilities: [
+                    Activated(
+                        effect: Attack(damage: 2, effect_type: Explosion),
+                        cost: 1,
+                        targets: "up to 2 enemy units",
+                    ),
+                    Activated(
+                        effect: Attack(damage: 1, effect_type: Explosion),
+                        cost: 1,
+                        targets: (amount: N(n: 1), filter: Enemy, selection: Auto(LowestHp)),
+                    ),
+                    Passive(
+                        effect: WhenHit(effect: ChangeEnergy(amount: 1), target_rules: ThatUnit),
+                        targets: "friendly units",
+                    ),
+                ],
+            ),
+        }"#;
+        let library: CardLibrary = ron::from_str(text).unwrap();
+        let written = ron::to_string(&library).unwrap();
+        assert!(written.contains("\"up to 2 enemy units\""));
+        assert!(written.contains("\"friendly units\""));
+        assert!(written.contains("Auto(LowestHp)"));
+
+        let reread: CardLibrary = ron::from_str(&written).unwrap();
+        assert_eq!(ron::to_string(&reread).unwrap(), written);
+
+        let list: DeckList = ron::from_str(r#"(cards: [("Test Bot", 3)])"#).unwrap();
+        let deck = list.build(&reread).unwrap();
+        let summons = deck
+            .deck
+            .abilities
+            .iter()
+            .filter(|a| matches!(a, Ability::Activated { effect: Effect::SummonCard { .. }, .. }))
+            .count();
+        assert_eq!(summons, 3);
+        assert!(matches!(list.build(&CardLibrary::default()), Err(DeckListError::UnknownCard(_))));
+    }
+}
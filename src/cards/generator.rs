@@ -1,33 +1,99 @@
-use rand::{prelude::SliceRandom, thread_rng, Rng};
+use rand::{prelude::SliceRandom, Rng};
 
 use crate::cards::{
     price::{price_card, price_effect},
-    Ability, AbilityCost, Card, Cost, Effect, EffectType, PassiveEffect, TargetAmount,
-    TargetFilter, TargetRules,
+    validation::has_legal_targets,
+    Ability, AbilityCost, Card, Cost, Effect, EffectType, ImplicitTargetRules, PassiveEffect,
+    TargetAmount, TargetFilter, TargetRules, TargetSelection,
 };
 
 /// Returns a random number in `[1, limit)`
-fn rnd(limit: usize) -> usize {
-    thread_rng().gen_range(0..limit)
+fn rnd(rng: &mut impl Rng, limit: usize) -> usize {
+    rng.gen_range(0..limit)
 }
 
 /// Returns a random number in `[1, max]` where each number is twice as likely
 /// to appear as the next highest number.
-fn rnd_log(max: u32) -> u32 {
-    let r = thread_rng().gen_range(1..(2usize.pow(max)));
+fn rnd_log(rng: &mut impl Rng, max: u32) -> u32 {
+    let r = rng.gen_range(1..(2usize.pow(max)));
     max - r.ilog2()
 }
 
-fn rnd_log_n(max: u32, n: u32) -> u32 {
+fn rnd_log_n(rng: &mut impl Rng, max: u32, n: u32) -> u32 {
     let mut sum = 0;
     for i in 0..n {
-        sum += rnd_log(max) - 1;
+        sum += rnd_log(rng, max) - 1;
     }
     sum
 }
 
-fn random_effect_type() -> EffectType {
-    match rnd(4) {
+/// Relative odds of each kind of ability the generator produces. A weight of 0 turns that kind
+/// off.
+#[derive(Debug, Clone)]
+pub struct GeneratorWeights {
+    pub attack: u32,
+    pub heal: u32,
+    pub energy_transfer: u32,
+    pub summon: u32,
+    pub grant_ability: u32,
+
+    pub resistance: u32,
+    pub when_hit: u32,
+    pub when_dies: u32,
+
+    /// Odds out of 100 that a helpful active ability can only target the unit that has it
+    pub self_targeted: u32,
+    /// Odds out of 100 that an attack hits every enemy unit instead of chosen ones
+    pub all_targets: u32,
+}
+impl Default for GeneratorWeights {
+    fn default() -> Self {
+        Self {
+            attack: 50,
+            heal: 12,
+            energy_transfer: 10,
+            summon: 8,
+            grant_ability: 8,
+            resistance: 50,
+            when_hit: 25,
+            when_dies: 15,
+            self_targeted: 25,
+            all_targets: 0,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+enum ActiveKind {
+    Attack,
+    Heal,
+    EnergyTransfer,
+    Summon,
+    GrantAbility,
+}
+
+#[derive(Copy, Clone)]
+enum PassiveKind {
+    Resistance,
+    WhenHit,
+    WhenDies,
+}
+
+/// Picks one of `options` with odds proportional to its weight.
+fn weighted<T: Copy>(rng: &mut impl Rng, options: &[(T, u32)]) -> T {
+    let total = options.iter().map(|(_, w)| *w as usize).sum::<usize>();
+    let mut r = rnd(rng, total.max(1));
+    for (option, weight) in options {
+        if r < *weight as usize {
+            return *option;
+        }
+        r -= *weight as usize;
+    }
+    options[0].0
+}
+
+fn random_effect_type(rng: &mut impl Rng) -> EffectType {
+    match rnd(rng, 4) {
         0 => EffectType::Fire,
         1 => EffectType::Electrical,
         2 => EffectType::Explosion,
@@ -35,29 +101,88 @@ fn random_effect_type() -> EffectType {
     }
 }
 
-pub fn random_card() -> Card {
-    let mut abilities = vec![random_active_ability()];
-    if rnd(4) < 2 {
-        abilities.push(random_ability());
+fn random_amount(rng: &mut impl Rng, n: usize) -> TargetAmount {
+    if rnd(rng, 5) < 2 {
+        TargetAmount::UpToN { n }
+    } else {
+        TargetAmount::N { n }
+    }
+}
+
+fn friendly_units() -> TargetFilter {
+    TargetFilter::And(vec![TargetFilter::Friendly, TargetFilter::Occupied])
+}
+
+fn enemy_units() -> TargetFilter {
+    TargetFilter::And(vec![TargetFilter::Enemy, TargetFilter::Occupied])
+}
+
+pub fn random_card(rng: &mut impl Rng) -> Card {
+    random_card_weighted(rng, &GeneratorWeights::default())
+}
+
+/// Generates cards until one is playable, i.e. has a price and an activated ability it can afford.
+pub fn random_card_weighted(rng: &mut impl Rng, weights: &GeneratorWeights) -> Card {
+    loop {
+        let card = try_random_card(rng, weights, true);
+        if is_playable(&card) {
+            return card;
+        }
+    }
+}
+
+/// Whether `card` has a price and can do something: one of its activated abilities has something
+/// to target and costs no more than the energy it fills up to, and every card it summons is
+/// playable too.
+pub fn is_playable(card: &Card) -> bool {
+    fn summons_playable(effect: &Effect) -> bool {
+        match effect {
+            Effect::SummonCard { card } => is_playable(card),
+            Effect::MultipleEffects { effects } => effects.iter().all(summons_playable),
+            _ => true,
+        }
+    }
+
+    let price = price_card(&card.abilities, card.hp, card.starting_energy, card.max_energy);
+    // energy goes up by one every turn, so anything up to the max is reached eventually
+    let usable = card.abilities.iter().any(|a| match a {
+        Ability::Activated { cost, effect, target_rules } => {
+            cost.get(effect).is_ok_and(|cost| cost.energy <= card.max_energy)
+                && has_legal_targets(target_rules)
+        },
+        Ability::Passive { .. } => false,
+    });
+    let summons = card.abilities.iter().all(|a| match a {
+        Ability::Activated { effect, .. } => summons_playable(effect),
+        Ability::Passive { .. } => true,
+    });
+    price.is_finite() && usable && summons
+}
+
+/// Summoned cards can't summon more cards themselves, to keep them small.
+fn try_random_card(rng: &mut impl Rng, weights: &GeneratorWeights, can_summon: bool) -> Card {
+    let mut abilities = vec![random_active_ability(rng, weights, can_summon)];
+    if rnd(rng, 4) < 2 {
+        abilities.push(random_ability(rng, weights, can_summon));
     }
-    abilities.push(random_passive_ability());
-    if rnd(3) < 2 {
-        abilities.push(random_passive_ability());
+    abilities.push(random_passive_ability(rng, weights));
+    if rnd(rng, 3) < 2 {
+        abilities.push(random_passive_ability(rng, weights));
     }
 
     let max_energy = abilities
         .iter()
         .filter_map(|a| match a {
-            Ability::Activated { cost, effect, .. } => Some(cost.get(effect).energy),
+            Ability::Activated { cost, effect, .. } => cost.get(effect).ok().map(|c| c.energy),
             Ability::Passive { .. } => None,
         })
         .max()
         .unwrap();
-    let starting_energy = max_energy.min(rnd_log_n(3, 3));
-    let hp = rnd_log_n(8, 12);
+    let starting_energy = max_energy.min(rnd_log_n(rng, 3, 3));
+    let hp = if can_summon { rnd_log_n(rng, 8, 12) } else { rnd_log_n(rng, 8, 4) + 1 };
 
     Card {
-        name: random_name(),
+        name: random_name(rng),
         summon_cost: Cost {
             energy: price_card(&abilities, hp, starting_energy, max_energy) as u32,
         },
@@ -68,59 +193,183 @@ pub fn random_card() -> Card {
     }
 }
 
-pub fn random_ability() -> Ability {
-    match rnd(100) {
-        0..80 => random_active_ability(),
-        _ => random_passive_ability(),
+pub fn random_ability(rng: &mut impl Rng, weights: &GeneratorWeights, can_summon: bool) -> Ability {
+    match rnd(rng, 100) {
+        0..80 => random_active_ability(rng, weights, can_summon),
+        _ => random_passive_ability(rng, weights),
     }
 }
 
-fn random_active_ability() -> Ability {
-    let (effect, target_rules) = match rnd(100) {
-        _ => {
-            let n = rnd_log(10) as usize;
-            let amount = if rnd(5) < 2 { TargetAmount::UpToN { n } } else { TargetAmount::N { n } };
+fn random_active_ability(
+    rng: &mut impl Rng,
+    weights: &GeneratorWeights,
+    can_summon: bool,
+) -> Ability {
+    let kind = weighted(rng, &[
+        (ActiveKind::Attack, weights.attack),
+        (ActiveKind::Heal, weights.heal),
+        (ActiveKind::EnergyTransfer, weights.energy_transfer),
+        (ActiveKind::Summon, if can_summon { weights.summon } else { 0 }),
+        (ActiveKind::GrantAbility, weights.grant_ability),
+    ]);
+    let mut min_cost = 0;
+    let (effect, target_rules) = match kind {
+        ActiveKind::Attack => {
+            // only rolled when enabled, so decks from existing seeds stay the same
+            let amount = if weights.all_targets > 0 && rnd(rng, 100) < weights.all_targets as usize
+            {
+                TargetAmount::All
+            } else {
+                let n = rnd_log(rng, 10) as usize;
+                random_amount(rng, n)
+            };
             let target_rules = TargetRules {
                 amount,
-                filter: TargetFilter::And(vec![TargetFilter::Enemy, TargetFilter::Occupied]),
+                filter: enemy_units(),
+                selection: TargetSelection::Chosen,
+                then: vec![],
+                area: None,
             };
 
-            let damage = rnd_log_n(10, 3) + 1;
-            let effect = Effect::Attack { damage, effect_type: random_effect_type() };
+            let damage = rnd_log_n(rng, 10, 3) + 1;
+            let effect =
+                Effect::Attack { damage, effect_type: random_effect_type(rng), falloff: 0 };
 
             (effect, target_rules)
         },
+        ActiveKind::Heal => {
+            let amount = rnd_log_n(rng, 8, 3) as i32 + 2;
+            (Effect::ChangeHp { amount }, friendly_rules(rng, weights, 4))
+        },
+        ActiveKind::EnergyTransfer => {
+            let n = rnd_log(rng, 3) as usize;
+            let target_rules = TargetRules {
+                amount: random_amount(rng, n),
+                filter: friendly_units(),
+                selection: TargetSelection::Chosen,
+                then: vec![],
+                area: None,
+            };
+            let amount = rnd_log(rng, 3);
+            // a transfer, so it can't make more energy than it costs
+            min_cost = amount * n as u32;
+            (Effect::ChangeEnergy { amount: amount as i32 }, target_rules)
+        },
+        ActiveKind::Summon => {
+            let target_rules = TargetRules {
+                amount: TargetAmount::N { n: 1 },
+                filter: TargetFilter::And(vec![TargetFilter::Friendly, TargetFilter::Unoccupied]),
+                selection: TargetSelection::Chosen,
+                then: vec![],
+                area: None,
+            };
+            (Effect::SummonCard { card: try_random_card(rng, weights, false) }, target_rules)
+        },
+        ActiveKind::GrantAbility => {
+            let abilities = vec![random_passive_ability(rng, weights)];
+            (Effect::GrantAbilities { abilities }, friendly_rules(rng, weights, 3))
+        },
     };
 
     let score = price_effect(&effect, &target_rules);
-    let jitter = rnd(3) as f32 - 1.;
-    let cost = AbilityCost::Static { cost: Cost { energy: (score + jitter) as u32 } };
+    let jitter = rnd(rng, 3) as f32 - 1.;
+    let energy = ((score + jitter) as u32).max(min_cost);
+    let cost = AbilityCost::Static { cost: Cost { energy } };
     Ability::Activated { effect, cost, target_rules }
 }
 
-fn random_passive_ability() -> Ability {
-    let (passive_effect, target_filter) = match rnd(100) {
-        _ => {
+/// Targets for a helpful effect.
+fn friendly_rules(rng: &mut impl Rng, weights: &GeneratorWeights, max_n: u32) -> TargetRules {
+    if rnd(rng, 100) < weights.self_targeted as usize {
+        TargetRules {
+            amount: TargetAmount::N { n: 1 },
+            filter: TargetFilter::ThisUnit,
+            selection: TargetSelection::Chosen,
+            then: vec![],
+            area: None,
+        }
+    } else {
+        let n = rnd_log(rng, max_n) as usize;
+        TargetRules {
+            amount: random_amount(rng, n),
+            filter: friendly_units(),
+            selection: TargetSelection::Chosen,
+            then: vec![],
+            area: None,
+        }
+    }
+}
+
+fn random_passive_ability(rng: &mut impl Rng, weights: &GeneratorWeights) -> Ability {
+    let kind = weighted(rng, &[
+        (PassiveKind::Resistance, weights.resistance),
+        (PassiveKind::WhenHit, weights.when_hit),
+        (PassiveKind::WhenDies, weights.when_dies),
+    ]);
+
+    let (passive_effect, target_filter) = match kind {
+        PassiveKind::Resistance => {
             let effect = PassiveEffect::DamageResistance {
-                effect_type: random_effect_type(),
-                factor: if rnd(2) == 1 { 0.5 } else { 2.0 },
+                effect_type: random_effect_type(rng),
+                factor: if rnd(rng, 2) == 1 { 0.5 } else { 2.0 },
             };
             let target_filter = TargetFilter::ThisUnit;
             (effect, target_filter)
         },
+        PassiveKind::WhenHit => match rnd(rng, 3) {
+            // charges up when this unit is hit
+            0 => {
+                let effect = PassiveEffect::WhenHit {
+                    effect: Effect::ChangeEnergy { amount: rnd_log(rng, 3) as i32 },
+                    target_rules: ImplicitTargetRules::ThisUnit,
+                };
+                (effect, TargetFilter::ThisUnit)
+            },
+            // supports friendly units that are hit
+            1 => {
+                let effect = PassiveEffect::WhenHit {
+                    effect: random_boost(rng),
+                    target_rules: ImplicitTargetRules::ThatUnit,
+                };
+                (effect, friendly_units())
+            },
+            // piles on to enemy units that are hit
+            _ => {
+                let effect = PassiveEffect::WhenHit {
+                    effect: Effect::ChangeHp { amount: -(rnd_log(rng, 3) as i32) },
+                    target_rules: ImplicitTargetRules::ThatUnit,
+                };
+                (effect, enemy_units())
+            },
+        },
+        PassiveKind::WhenDies => {
+            let effect = PassiveEffect::WhenDies {
+                effect: random_boost(rng),
+                target_rules: ImplicitTargetRules::ThisUnit,
+            };
+            let filter = if rnd(rng, 2) == 0 { friendly_units() } else { enemy_units() };
+            (effect, filter)
+        },
     };
 
     Ability::Passive { passive_effect, target_filter }
 }
 
-fn random_name() -> String {
-    let mut rng = thread_rng();
+/// A small helpful effect for triggers.
+fn random_boost(rng: &mut impl Rng) -> Effect {
+    if rnd(rng, 2) == 0 {
+        Effect::ChangeHp { amount: rnd_log(rng, 4) as i32 }
+    } else {
+        Effect::ChangeEnergy { amount: rnd_log(rng, 3) as i32 }
+    }
+}
 
-    let adj = ADJECTIVES.choose(&mut rng).unwrap();
-    let noun = NOUNS.choose(&mut rng).unwrap();
+fn random_name(rng: &mut impl Rng) -> String {
+    let adj = ADJECTIVES.choose(rng).unwrap();
+    let noun = NOUNS.choose(rng).unwrap();
 
     if rng.gen_ratio(1, 20) {
-        let n = rnd_log(10) * 1000;
+        let n = rnd_log(rng, 10) * 1000;
         format!("{adj} {noun} {n}")
     } else {
         format!("{adj} {noun}")
@@ -398,3 +647,84 @@ const NOUNS: [&'static str; 83] = [
     "Bot",
     "Robot",
 ];
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn card(max_energy: u32, abilities: Vec<Ability>) -> Card {
+        Card {
+            name: "Test Bot".to_string(),
+            summon_cost: Cost { energy: 3 },
+            hp: 5,
+            abilities,
+            starting_energy: 0,
+            max_energy,
+        }
+    }
+
+    fn ability(effect: Effect, energy: u32, filter: TargetFilter) -> Ability {
+        Ability::Activated {
+            effect,
+            cost: AbilityCost::Static { cost: Cost { energy } },
+            target_rules: TargetRules {
+                amount: TargetAmount::N { n: 1 },
+                filter,
+                selection: TargetSelection::Chosen,
+                then: vec![],
+                area: None,
+            },
+        }
+    }
+
+    fn attack() -> Effect {
+        Effect::Attack { damage: 2, effect_type: EffectType::Physical, falloff: 0 }
+    }
+
+    fn summon(card: Card) -> Ability {
+        let filter = TargetFilter::And(vec![TargetFilter::Friendly, TargetFilter::Unoccupied]);
+        ability(Effect::SummonCard { card }, 1, filter)
+    }
+
+    #[test]
+    fn random_cards_are_playable() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..100 {
+            assert!(is_playable(&random_card(&mut rng)));
+        }
+    }
+
+    #[test]
+    fn playable_cards() {
+        let attacker = card(2, vec![ability(attack(), 2, enemy_units())]);
+        assert!(is_playable(&attacker));
+        // starts empty, but fills up to the cost
+        assert!(is_playable(&card(3, vec![
+            ability(attack(), 5, enemy_units()),
+            ability(attack(), 3, enemy_units()),
+        ])));
+        assert!(is_playable(&card(1, vec![summon(attacker)])));
+    }
+
+    #[test]
+    fn unplayable_cards() {
+        let too_expensive = card(2, vec![ability(attack(), 3, enemy_units())]);
+        assert!(!is_playable(&too_expensive));
+
+        let nowhere = TargetFilter::And(vec![TargetFilter::Friendly, TargetFilter::Enemy]);
+        assert!(!is_playable(&card(2, vec![ability(attack(), 1, nowhere)])));
+
+        let passive_only = card(2, vec![Ability::Passive {
+            passive_effect: PassiveEffect::DamageResistance {
+                effect_type: EffectType::Fire,
+                factor: 0.5,
+            },
+            target_filter: TargetFilter::ThisUnit,
+        }]);
+        assert!(!is_playable(&passive_only));
+
+        assert!(!is_playable(&card(1, vec![summon(too_expensive)])));
+    }
+}
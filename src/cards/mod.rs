@@ -1,18 +1,20 @@
 pub mod deck;
+pub mod deck_generator;
+pub mod deck_list;
 pub mod generator;
 pub mod mesh;
 pub mod price;
 pub mod text;
+pub mod validation;
 
 use std::fmt::Debug;
 
-use bevy::{math::UVec2, reflect::Reflect};
-use bevy_mod_index::index::Index;
+use bevy::{math::IVec2, reflect::Reflect};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    match_sim::{Cards, GridLocation, PlayerId},
-    ui::game_scene::{GRID_H, GRID_W},
+    cards::validation::DeckProblem,
+    match_sim::{engine::MatchState, GridLocation, SIDE_ROWS},
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize, Reflect)]
@@ -34,7 +36,7 @@ pub enum Ability {
 impl Ability {
     fn cost(&self) -> Option<Cost> {
         match self {
-            Ability::Activated { effect, cost, .. } => Some(cost.get(effect)),
+            Ability::Activated { effect, cost, .. } => cost.get(effect).ok(),
             Ability::Passive { .. } => None,
         }
     }
@@ -68,14 +70,12 @@ pub enum AbilityCost {
     Derived { attribute: Attribute },
 }
 impl AbilityCost {
-    pub fn get(&self, effect: &Effect) -> Cost {
+    pub fn get(&self, effect: &Effect) -> Result<Cost, DeckProblem> {
         match self {
-            AbilityCost::Static { cost } => *cost,
-            AbilityCost::Derived { attribute } => {
-                let Effect::SummonCard { card } = effect else {
-                    panic!("DerivedCost only valid for SummonCard.")
-                };
-                Cost { energy: attribute.get(card) }
+            AbilityCost::Static { cost } => Ok(*cost),
+            AbilityCost::Derived { attribute } => match effect {
+                Effect::SummonCard { card } => Ok(Cost { energy: attribute.get(card) }),
+                _ => Err(DeckProblem::DerivedCostWithoutCard),
             },
         }
     }
@@ -85,20 +85,76 @@ impl AbilityCost {
 // #[reflect(where Ability: FromReflect)]
 #[reflect(no_field_bounds)]
 pub enum Effect {
-    Attack { damage: u32, effect_type: EffectType },
-    GrantAbilities { abilities: Vec<Ability> }, // Vec to avoid infinite type
-    SummonCard { card: Card },
+    Attack {
+        damage: u32,
+        effect_type: EffectType,
+        /// Damage lost for each ring out from the center of an [`AreaPattern`]
+        #[serde(default)]
+        falloff: u32,
+    },
+    GrantAbilities {
+        abilities: Vec<Ability>,
+    }, // Vec to avoid infinite type
+    SummonCard {
+        card: Card,
+    },
     // SharableEnergy {
     //     factor: f32,
     // },
     // Cloaking {
     //
     // },
-    MultipleEffects { effects: Vec<Effect> },
+    MultipleEffects {
+        effects: Vec<Effect>,
+    },
 
-    ChangeHp { amount: i32 },
-    ChangeEnergy { amount: i32 },
+    ChangeHp {
+        amount: i32,
+    },
+    ChangeEnergy {
+        amount: i32,
+    },
     DestroyCard,
+
+    // Units only ever move within their owner's half, and command units never move.
+    /// Moves each target to an empty location picked after the targets, which `destination` is
+    /// checked against from the target's point of view.
+    Move {
+        destination: TargetFilter,
+    },
+    /// Like `Move`, but a unit at the destination trades places with the target.
+    Swap {
+        destination: TargetFilter,
+    },
+    /// Pushes each target up to `distance` rows towards its owner's back row, stopping early at
+    /// the edge or another unit.
+    Push {
+        distance: u32,
+    },
+}
+impl Effect {
+    /// The filter for the destinations an effect needs picked after its targets, if it needs any.
+    pub fn destination_filter(&self) -> Option<&TargetFilter> {
+        match self {
+            Effect::Move { destination } | Effect::Swap { destination } => Some(destination),
+            _ => None,
+        }
+    }
+
+    /// This effect as it lands `ring` rings out from the center of an [`AreaPattern`].
+    pub fn at_ring(&self, ring: u32) -> Effect {
+        match self {
+            Effect::Attack { damage, effect_type, falloff } => Effect::Attack {
+                damage: damage.saturating_sub(falloff.saturating_mul(ring)),
+                effect_type: *effect_type,
+                falloff: *falloff,
+            },
+            Effect::MultipleEffects { effects } => Effect::MultipleEffects {
+                effects: effects.iter().map(|e| e.at_ring(ring)).collect(),
+            },
+            effect => effect.clone(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Reflect)]
@@ -124,55 +180,227 @@ pub enum ImplicitTargetRules {
     ThatUnit,
     // AttackingUnit,
 }
+impl ImplicitTargetRules {
+    pub fn target(&self, this_unit: GridLocation, that_unit: GridLocation) -> GridLocation {
+        match self {
+            ImplicitTargetRules::ThisUnit => this_unit,
+            ImplicitTargetRules::ThatUnit => that_unit,
+        }
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, Reflect)]
 pub struct TargetRules {
     pub amount: TargetAmount,
     pub filter: TargetFilter,
-    // chosen, random, or auto
+    /// Who picks the targets of every step. `amount` only applies to chosen targets.
+    #[serde(default)]
+    pub selection: TargetSelection,
+    /// Further targets picked after these, in order. Each step's filter is checked from the first
+    /// target of the step before it, as if that target were the source of the effect.
+    #[serde(default)]
+    pub then: Vec<TargetStep>,
+    /// A shape around every target of every step that the effect also lands on
+    #[serde(default)]
+    pub area: Option<AreaPattern>,
 }
 impl TargetRules {
+    /// The amount and filter of every step, starting with this one.
+    pub fn steps(&self) -> impl Iterator<Item = (&TargetAmount, &TargetFilter)> + '_ {
+        std::iter::once((&self.amount, &self.filter))
+            .chain(self.then.iter().map(|step| (&step.amount, &step.filter)))
+    }
+
+    /// Where the filter of step `step` is checked from, given the targets `picked` for the steps
+    /// before it. `None` when the step before picked nothing.
+    pub fn step_origin(
+        step: usize,
+        effect_source: GridLocation,
+        picked: &[Vec<GridLocation>],
+    ) -> Option<GridLocation> {
+        match step {
+            0 => Some(effect_source),
+            _ => picked.get(step - 1)?.first().copied(),
+        }
+    }
+
+    /// Checks the targets picked for every step, `steps[0]` being the targets of this step.
     pub fn validate(
         &self,
-        targets: &[GridLocation],
-        loc_idx: &mut Index<GridLocation>,
-        cards: &Cards,
-        players: &[PlayerId],
+        steps: &[Vec<GridLocation>],
+        state: &MatchState,
         effect_source: &GridLocation,
     ) -> bool {
-        // todo: not verify untargeted squares unless needed for `All`
-
-        let mut total_valid = 0;
-        let mut targeted_valid = 0;
-        for x in 0..(GRID_H as u32 / 2) {
-            for y in 0..(GRID_W as u32) {
-                for p in players {
-                    let loc = GridLocation { coord: UVec2::new(x, y), owner: *p };
-
-                    let valid = self.filter.validate(&loc, loc_idx, cards, effect_source);
-                    if valid {
-                        total_valid += 1;
-                    }
-
-                    if targets.contains(&loc) {
-                        if valid {
-                            targeted_valid += 1;
-                        } else {
-                            return false;
-                        }
-                    }
-                }
+        if steps.len() != self.then.len() + 1 {
+            return false;
+        }
+
+        let mut taken = vec![];
+        for (i, ((amount, filter), targets)) in self.steps().zip(steps).enumerate() {
+            let origin = Self::step_origin(i, *effect_source, steps);
+            if !validate_step(amount, filter, targets, state, origin.as_ref(), &taken) {
+                return false;
             }
+            taken.extend_from_slice(targets);
         }
+        true
+    }
 
-        if targeted_valid != targets.len() {
-            return false;
+    /// Every location the effect lands on and its ring, given the picked `targets` of every step.
+    /// A location in several targets' areas is only hit once, at its closest ring.
+    pub fn area_targets(
+        &self,
+        targets: &[GridLocation],
+        state: &MatchState,
+    ) -> Vec<(GridLocation, u32)> {
+        let Some(area) = &self.area else {
+            return targets.iter().map(|t| (*t, 0)).collect();
+        };
+
+        let mut hit: Vec<(GridLocation, u32)> = vec![];
+        for (loc, ring) in targets.iter().flat_map(|center| area.locations(center, state)) {
+            match hit.iter_mut().find(|(hit_loc, _)| *hit_loc == loc) {
+                Some((_, closest)) => *closest = ring.min(*closest),
+                None => hit.push((loc, ring)),
+            }
+        }
+        hit
+    }
+}
+
+/// How the targets of an ability are picked.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Reflect)]
+pub enum TargetSelection {
+    /// By the player using the ability
+    #[default]
+    Chosen,
+    /// By the server, `n` of the valid locations at random, or all of them if there are fewer
+    Random { n: usize },
+    /// By the server, the valid location that comes first by a priority
+    Auto(AutoPriority),
+}
+impl TargetSelection {
+    pub fn is_chosen(&self) -> bool {
+        matches!(self, TargetSelection::Chosen)
+    }
+}
+
+/// What [`TargetSelection::Auto`] picks first. Ties go to the first in
+/// [`MatchState::locations`] order.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, Reflect)]
+pub enum AutoPriority {
+    LowestHp,
+    HighestHp,
+    MostEnergy,
+    /// Closest to the enemy's side of the board
+    FrontMost,
+    /// Closest to its owner's edge of the board
+    BackMost,
+}
+impl AutoPriority {
+    /// Sort key for `target`, the lowest is picked. Empty locations go last for unit stats.
+    pub fn rank(&self, target: &GridLocation, state: &MatchState) -> i32 {
+        let unit = state.units.get(target);
+        match self {
+            AutoPriority::LowestHp => unit.map_or(i32::MAX, |u| u.health.0),
+            AutoPriority::HighestHp => unit.map_or(i32::MAX, |u| -u.health.0),
+            AutoPriority::MostEnergy => unit.map_or(i32::MAX, |u| -(u.energy.current as i32)),
+            AutoPriority::FrontMost => -(target.coord.x as i32),
+            AutoPriority::BackMost => target.coord.x as i32,
         }
+    }
+}
+
+/// The locations around a target that an effect also lands on. The target is the center, and
+/// everything else is as many rings out as it is steps away, not counting diagonal steps.
+#[derive(Debug, Clone, Serialize, Deserialize, Reflect)]
+pub enum AreaPattern {
+    /// Everything up to `radius` rings out
+    Splash { radius: u32 },
+    /// The center's whole column, across both halves of the board
+    Column,
+    /// The center's whole row
+    Row,
+    /// The center's row and column, up to `reach` rings out
+    Cross { reach: u32 },
+}
+impl AreaPattern {
+    /// Which ring around `center` `target` is in, if the area covers it. Both are board
+    /// coordinates, see [`GridLocation::board_coord`].
+    pub fn ring(&self, center: IVec2, target: IVec2) -> Option<u32> {
+        let offset = target - center;
+        let distance = offset.x.unsigned_abs() + offset.y.unsigned_abs();
+        let covered = match self {
+            AreaPattern::Splash { radius } => distance <= *radius,
+            AreaPattern::Column => offset.y == 0,
+            AreaPattern::Row => offset.x == 0,
+            AreaPattern::Cross { reach } => (offset.x == 0 || offset.y == 0) && distance <= *reach,
+        };
+        covered.then_some(distance)
+    }
 
-        self.amount.validate(targeted_valid, total_valid)
+    /// Every location the area around `center` covers and its ring, in
+    /// [`MatchState::locations`] order.
+    pub fn locations<'a>(
+        &'a self,
+        center: &'a GridLocation,
+        state: &'a MatchState,
+    ) -> impl Iterator<Item = (GridLocation, u32)> + 'a {
+        let viewer = center.owner;
+        let center_pos = center.board_coord(viewer);
+        state
+            .locations()
+            .filter_map(move |loc| Some((loc, self.ring(center_pos, loc.board_coord(viewer))?)))
     }
 }
 
+/// A targeting step after the first, see [`TargetRules::then`].
+#[derive(Debug, Clone, Serialize, Deserialize, Reflect)]
+pub struct TargetStep {
+    pub amount: TargetAmount,
+    pub filter: TargetFilter,
+}
+
+/// Checks the targets picked for a single step, whose filter is checked from `origin`. Nothing
+/// can be picked without an origin, and locations `taken` by earlier steps can't be picked again.
+pub fn validate_step(
+    amount: &TargetAmount,
+    filter: &TargetFilter,
+    targets: &[GridLocation],
+    state: &MatchState,
+    origin: Option<&GridLocation>,
+    taken: &[GridLocation],
+) -> bool {
+    let Some(origin) = origin else {
+        return targets.is_empty() && amount.validate(0, 0);
+    };
+
+    // todo: not verify untargeted squares unless needed for `All`
+
+    let mut total_valid = 0;
+    let mut targeted_valid = 0;
+    for loc in state.locations() {
+        let valid = !taken.contains(&loc) && filter.validate(&loc, state, origin);
+        if valid {
+            total_valid += 1;
+        }
+
+        if targets.contains(&loc) {
+            if valid {
+                targeted_valid += 1;
+            } else {
+                return false;
+            }
+        }
+    }
+
+    if targeted_valid != targets.len() {
+        return false;
+    }
+
+    amount.validate(targeted_valid, total_valid)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Reflect)]
 pub enum TargetAmount {
     All,
@@ -198,6 +426,20 @@ pub enum TargetFilter {
     Enemy,
     Unoccupied,
     Occupied,
+    // Positional filters compare board coordinates (see `GridLocation::board_coord`) with the
+    // effect source. The relative ones never match the source's own location.
+    /// One step forward, back or to the side
+    Adjacent,
+    SameColumn,
+    SameRow,
+    /// The location one step towards the enemy's side
+    DirectlyInFront,
+    /// Either player's front row
+    FrontRow,
+    /// Either player's back row
+    BackRow,
+    /// At most this many steps away, not counting diagonal steps
+    WithinDistance(u32),
     And(Vec<TargetFilter>),
     Or(Vec<TargetFilter>),
 }
@@ -205,24 +447,203 @@ impl TargetFilter {
     pub fn validate(
         &self,
         target: &GridLocation,
-        loc_idx: &mut Index<GridLocation>,
-        cards: &Cards,
+        state: &MatchState,
         effect_source: &GridLocation,
     ) -> bool {
-        let card = loc_idx.lookup(target).next().and_then(|e| cards.get(e).ok());
+        let unit = state.units.get(target);
         match self {
             TargetFilter::Any => true,
-            TargetFilter::ThisUnit => card.is_some() && card.unwrap().grid_loc == effect_source,
+            TargetFilter::ThisUnit => unit.is_some() && target == effect_source,
             TargetFilter::Friendly => target.owner == effect_source.owner,
             TargetFilter::Enemy => target.owner != effect_source.owner,
-            TargetFilter::Unoccupied => card.is_none(),
-            TargetFilter::Occupied => card.is_some(),
+            TargetFilter::Unoccupied => unit.is_none(),
+            TargetFilter::Occupied => unit.is_some(),
             TargetFilter::And(conds) => {
-                conds.iter().all(|c| c.validate(target, loc_idx, cards, effect_source))
+                conds.iter().all(|c| c.validate(target, state, effect_source))
             },
             TargetFilter::Or(conds) => {
-                conds.iter().any(|c| c.validate(target, loc_idx, cards, effect_source))
+                conds.iter().any(|c| c.validate(target, state, effect_source))
             },
+            position => position.allows_position(
+                effect_source.board_coord(effect_source.owner),
+                target.board_coord(effect_source.owner),
+            ),
+        }
+    }
+
+    /// Whether this is one of the filters that only look at where the target is.
+    pub fn is_positional(&self) -> bool {
+        matches!(
+            self,
+            TargetFilter::Adjacent
+                | TargetFilter::SameColumn
+                | TargetFilter::SameRow
+                | TargetFilter::DirectlyInFront
+                | TargetFilter::FrontRow
+                | TargetFilter::BackRow
+                | TargetFilter::WithinDistance(_)
+        )
+    }
+
+    /// Whether the positional parts of this filter allow `target` to be affected from `source`,
+    /// both in board coordinates seen by the source's owner. Filters that don't care about
+    /// position allow anything.
+    pub fn allows_position(&self, source: IVec2, target: IVec2) -> bool {
+        let offset = target - source;
+        let distance = offset.x.abs() + offset.y.abs();
+        let half = SIDE_ROWS as i32;
+        match self {
+            TargetFilter::Any
+            | TargetFilter::ThisUnit
+            | TargetFilter::Friendly
+            | TargetFilter::Enemy
+            | TargetFilter::Unoccupied
+            | TargetFilter::Occupied => true,
+            TargetFilter::Adjacent => distance == 1,
+            TargetFilter::SameColumn => offset.y == 0 && offset.x != 0,
+            TargetFilter::SameRow => offset.x == 0 && offset.y != 0,
+            TargetFilter::DirectlyInFront => offset == IVec2::X,
+            TargetFilter::FrontRow => target.x == half - 1 || target.x == half,
+            TargetFilter::BackRow => target.x == 0 || target.x == half * 2 - 1,
+            TargetFilter::WithinDistance(n) => distance > 0 && distance <= *n as i32,
+            TargetFilter::And(conds) => conds.iter().all(|c| c.allows_position(source, target)),
+            TargetFilter::Or(conds) => conds.iter().any(|c| c.allows_position(source, target)),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::UVec2;
+
+    use super::*;
+    use crate::match_sim::{board_coord, side_coords, MatchId, PlayerId};
+
+    fn at(owner: PlayerId, x: u32, y: u32) -> GridLocation {
+        GridLocation { owner, coord: UVec2::new(x, y) }
+    }
+
+    /// Every location `filter` allows from `source` on an empty board, friendly ones first.
+    fn allowed(filter: &TargetFilter, source: GridLocation, other: PlayerId) -> Vec<GridLocation> {
+        let state = MatchState::new(MatchId::new(), vec![source.owner, other], 0);
+        [source.owner, other]
+            .into_iter()
+            .flat_map(|owner| side_coords().map(move |coord| GridLocation { owner, coord }))
+            .filter(|target| filter.validate(target, &state, &source))
+            .collect()
+    }
+
+    fn row(owner: PlayerId, x: u32) -> Vec<GridLocation> {
+        (0..5).map(|y| at(owner, x, y)).collect()
+    }
+
+    #[test]
+    fn board_coord_counts_rows_from_the_viewer() {
+        assert_eq!(board_coord(UVec2::new(0, 2), true), IVec2::new(0, 2));
+        assert_eq!(board_coord(UVec2::new(1, 4), true), IVec2::new(1, 4));
+        assert_eq!(board_coord(UVec2::new(1, 4), false), IVec2::new(2, 4));
+        assert_eq!(board_coord(UVec2::new(0, 0), false), IVec2::new(3, 0));
+
+        let (a, b) = (PlayerId::new(), PlayerId::new());
+        let loc = at(a, 1, 3);
+        assert_eq!(loc.board_coord(a), IVec2::new(1, 3));
+        assert_eq!(loc.board_coord(b), IVec2::new(2, 3));
+    }
+
+    #[test]
+    fn rows_are_the_same_from_both_sides() {
+        let (a, b) = (PlayerId::new(), PlayerId::new());
+        for (us, them) in [(a, b), (b, a)] {
+            for source in [at(us, 0, 2), at(us, 1, 0)] {
+                let front = allowed(&TargetFilter::FrontRow, source, them);
+                assert_eq!(front, [row(us, 1), row(them, 1)].concat());
+                let back = allowed(&TargetFilter::BackRow, source, them);
+                assert_eq!(back, [row(us, 0), row(them, 0)].concat());
+            }
+        }
+    }
+
+    #[test]
+    fn directly_in_front_faces_the_enemy() {
+        let (a, b) = (PlayerId::new(), PlayerId::new());
+        for (us, them) in [(a, b), (b, a)] {
+            let filter = TargetFilter::DirectlyInFront;
+            assert_eq!(allowed(&filter, at(us, 0, 2), them), vec![at(us, 1, 2)]);
+            // across the middle of the board, into the enemy's front row
+            assert_eq!(allowed(&filter, at(us, 1, 4), them), vec![at(them, 1, 4)]);
+
+            let enemy_front = TargetFilter::And(vec![TargetFilter::Enemy, filter]);
+            assert_eq!(allowed(&enemy_front, at(us, 0, 0), them), vec![]);
+        }
+    }
+
+    fn area(
+        pattern: AreaPattern,
+        center: GridLocation,
+        other: PlayerId,
+    ) -> Vec<(GridLocation, u32)> {
+        let state = MatchState::new(MatchId::new(), vec![center.owner, other], 0);
+        pattern.locations(&center, &state).collect()
+    }
+
+    #[test]
+    fn areas_stop_at_the_board_edges() {
+        let (a, b) = (PlayerId::new(), PlayerId::new());
+
+        let splash = area(AreaPattern::Splash { radius: 1 }, at(a, 0, 0), b);
+        assert_eq!(splash, vec![(at(a, 0, 0), 0), (at(a, 0, 1), 1), (at(a, 1, 0), 1)]);
+
+        // across the middle, to the enemy's back row
+        let column = area(AreaPattern::Column, at(a, 0, 4), b);
+        assert_eq!(column, vec![
+            (at(a, 0, 4), 0),
+            (at(a, 1, 4), 1),
+            (at(b, 0, 4), 3),
+            (at(b, 1, 4), 2)
+        ]);
+
+        let row = area(AreaPattern::Row, at(b, 0, 0), a);
+        assert_eq!(row, (0..5).map(|y| (at(b, 0, y), y)).collect::<Vec<_>>());
+
+        let cross = area(AreaPattern::Cross { reach: 2 }, at(a, 1, 4), b);
+        assert_eq!(cross, vec![
+            (at(a, 0, 4), 1),
+            (at(a, 1, 2), 2),
+            (at(a, 1, 3), 1),
+            (at(a, 1, 4), 0),
+            (at(b, 0, 4), 2),
+            (at(b, 1, 4), 1)
+        ]);
+    }
+
+    #[test]
+    fn rings_follow_the_pattern() {
+        let center = IVec2::new(1, 2);
+        let cross = AreaPattern::Cross { reach: 1 };
+        assert_eq!(cross.ring(center, IVec2::new(2, 2)), Some(1));
+        assert_eq!(cross.ring(center, IVec2::new(2, 3)), None);
+        assert_eq!(cross.ring(center, IVec2::new(1, 4)), None);
+        assert_eq!(AreaPattern::Splash { radius: 2 }.ring(center, IVec2::new(2, 3)), Some(2));
+        assert_eq!(AreaPattern::Column.ring(center, IVec2::new(3, 2)), Some(2));
+        assert_eq!(AreaPattern::Row.ring(center, IVec2::new(3, 2)), None);
+    }
+
+    #[test]
+    fn falloff_never_underflows() {
+        let damage = |effect: Effect| match effect {
+            Effect::Attack { damage, .. } => damage,
+            effect => panic!("not an attack: {effect:?}"),
+        };
+        let attack = |falloff| Effect::Attack { damage: 3, effect_type: EffectType::Fire, falloff };
+
+        let rings = (0..4).map(|ring| damage(attack(2).at_ring(ring))).collect::<Vec<_>>();
+        assert_eq!(rings, vec![3, 1, 0, 0]);
+        assert_eq!(damage(attack(u32::MAX).at_ring(2)), 0);
+        assert_eq!(damage(attack(0).at_ring(u32::MAX)), 3);
+
+        let both = Effect::MultipleEffects { effects: vec![attack(1), Effect::DestroyCard] };
+        let Effect::MultipleEffects { effects } = both.at_ring(5) else { unreachable!() };
+        assert_eq!(damage(effects[0].clone()), 0);
+        assert!(matches!(effects[1], Effect::DestroyCard));
+    }
+}
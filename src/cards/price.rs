@@ -1,59 +1,403 @@
-use crate::cards::{Ability, Effect, PassiveEffect, TargetAmount, TargetFilter, TargetRules};
+use std::sync::OnceLock;
 
-pub fn price_effect(effect: &Effect, target_rules: &TargetRules) -> f32 {
-    let (mut score, positive_effect) = match effect {
-        Effect::Attack { damage, .. } => ((*damage as f32).log10() * 10., false),
-        _ => todo!(),
-    };
+use bevy::log;
+use serde::{Deserialize, Serialize};
 
-    let multiplier = match target_rules.amount {
-        TargetAmount::UpToN { n } => 0.7 + (n as f32 * 0.08),
-        TargetAmount::N { n } => 0.5 + (n as f32 * 0.08),
-        TargetAmount::All => 0.5 + (10. * 0.08),
-    };
-    score *= multiplier;
+use crate::{
+    cards::{
+        Ability, AreaPattern, Card, Effect, ImplicitTargetRules, PassiveEffect, TargetAmount,
+        TargetFilter, TargetRules, TargetSelection,
+    },
+    match_sim::{board_coord, side_coords, SIDE_ROWS},
+};
 
-    score
+pub const PRICE_CONFIG_PATH: &str = "assets/price_config.json";
+
+/// Coefficients of the pricing model. Loaded from [`PRICE_CONFIG_PATH`] if it exists, which is
+/// written by the `calibrate_prices` binary.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PriceConfig {
+    pub base_price: f32,
+    /// Price of each hp above `hp_baseline`
+    pub hp_value: f32,
+    pub hp_baseline: f32,
+    /// Price of starting with a full battery, and refund for starting empty
+    pub energy_ratio_value: f32,
+    /// Added for every ability, on top of the ability's own price
+    pub ability_base_cost: f32,
+    /// Attack score is `damage_value * log10(damage)`
+    pub damage_value: f32,
+    pub up_to_n_base: f32,
+    pub n_base: f32,
+    pub per_target: f32,
+    /// How many targets `TargetAmount::All` is priced as
+    pub all_target_count: f32,
+    /// Price of each halving of damage taken
+    pub resistance_value: f32,
+    /// Healing is priced like damage, times this
+    pub heal_value: f32,
+    pub energy_value: f32,
+    pub destroy_value: f32,
+    /// Value of moving a unit with `Move` or `Swap`
+    pub move_value: f32,
+    /// Price of each row a `Push` can move a unit back
+    pub push_value: f32,
+    /// Fraction of its value an effect on every unit of both sides is worth
+    pub indiscriminate_factor: f32,
+    /// How many units a filter matching a whole side is priced as
+    pub aura_units: f32,
+    /// Fraction of its price an effect would lose if position filters left it no locations to
+    /// target. Effects that can reach part of the board lose part of that.
+    pub position_penalty: f32,
+    /// Expected number of times a `WhenHit` trigger fires per unit it watches
+    pub when_hit_factor: f32,
+    /// Expected number of times a `WhenDies` trigger fires per unit it watches
+    pub when_dies_factor: f32,
+}
+impl Default for PriceConfig {
+    fn default() -> Self {
+        Self {
+            base_price: 3.,
+            hp_value: 0.15,
+            hp_baseline: 12.,
+            energy_ratio_value: 1.,
+            ability_base_cost: 0.3,
+            damage_value: 10.,
+            up_to_n_base: 0.7,
+            n_base: 0.5,
+            per_target: 0.08,
+            all_target_count: 10.,
+            resistance_value: 1.,
+            heal_value: 0.8,
+            energy_value: 1.,
+            destroy_value: 12.,
+            move_value: 1.5,
+            push_value: 1.,
+            indiscriminate_factor: 0.3,
+            aura_units: 2.,
+            position_penalty: 0.5,
+            when_hit_factor: 1.,
+            when_dies_factor: 0.5,
+        }
+    }
 }
 
-pub fn price_passive_effect(passive_effect: &PassiveEffect, target_filter: &TargetFilter) -> f32 {
-    let muiltiplier = match target_filter {
-        TargetFilter::ThisUnit => 1.,
-        _ => panic!("TODO: determine passive effect filter multipliers"),
-    };
+impl PriceConfig {
+    pub fn get() -> &'static PriceConfig {
+        static CONFIG: OnceLock<PriceConfig> = OnceLock::new();
+        CONFIG.get_or_init(|| {
+            let Ok(fd) = std::fs::File::open(PRICE_CONFIG_PATH) else {
+                return PriceConfig::default();
+            };
+            serde_json::from_reader(fd).unwrap_or_else(|e| {
+                log::warn!("Invalid price config {PRICE_CONFIG_PATH}, using defaults: {e}");
+                PriceConfig::default()
+            })
+        })
+    }
 
-    let base_price = match passive_effect {
-        PassiveEffect::DamageResistance { factor, .. } => -1. * factor.log2(),
-        _ => panic!("TODO: determine passive effect prices"),
-    };
+    /// The coefficients that prices depend on linearly, which calibration can fit directly. This
+    /// only holds for cards that don't grant abilities or summon other cards.
+    pub fn fitted_coefficients(&mut self) -> [(&'static str, &mut f32); 8] {
+        [
+            ("base_price", &mut self.base_price),
+            ("hp_value", &mut self.hp_value),
+            ("energy_ratio_value", &mut self.energy_ratio_value),
+            ("ability_base_cost", &mut self.ability_base_cost),
+            ("up_to_n_base", &mut self.up_to_n_base),
+            ("n_base", &mut self.n_base),
+            ("per_target", &mut self.per_target),
+            ("resistance_value", &mut self.resistance_value),
+        ]
+    }
 
-    base_price * muiltiplier
-}
+    /// How much `effect` helps each unit it lands on. Negative for effects that hurt.
+    pub fn effect_value(&self, effect: &Effect) -> f32 {
+        match effect {
+            Effect::Attack { damage, .. } => -self.magnitude(*damage),
+            Effect::ChangeHp { amount } if *amount < 0 => -self.magnitude(amount.unsigned_abs()),
+            Effect::ChangeHp { amount } => self.magnitude(*amount as u32) * self.heal_value,
+            Effect::ChangeEnergy { amount } => *amount as f32 * self.energy_value,
+            Effect::GrantAbilities { abilities } => {
+                abilities.iter().map(|ability| self.price_ability(ability)).sum()
+            },
+            Effect::SummonCard { card } => self.price_of(card),
+            Effect::DestroyCard => -self.destroy_value,
+            Effect::Move { .. } | Effect::Swap { .. } => self.move_value,
+            // a side is only so deep, further pushes can't do anything
+            Effect::Push { distance } => -self.push_value * (*distance).min(SIDE_ROWS - 1) as f32,
+            Effect::MultipleEffects { effects } => {
+                effects.iter().map(|effect| self.effect_value(effect)).sum()
+            },
+        }
+    }
 
-pub fn price_card(abilities: &[Ability], hp: u32, starting_energy: u32, max_energy: u32) -> f32 {
-    let mut price = 3.;
+    fn magnitude(&self, amount: u32) -> f32 {
+        // nothing is worth nothing, not negative infinity
+        (amount.max(1) as f32).log10() * self.damage_value
+    }
 
-    let hp_diff = hp as f32 - 12.;
-    price += hp_diff * 0.15;
+    /// Prices each targeting step of `target_rules` on its own, as if the effect had several
+    /// abilities' worth of targets.
+    pub fn price_effect(&self, effect: &Effect, target_rules: &TargetRules) -> f32 {
+        let value = self.effect_value(effect);
+        let selection = &target_rules.selection;
+        let area_targets =
+            target_rules.area.as_ref().map_or(1., |area| self.area_targets(effect, area));
+        target_rules
+            .steps()
+            .map(|(amount, filter)| self.price_step(value, amount, filter, selection, area_targets))
+            .sum()
+    }
 
-    if max_energy > 0 {
-        // cheaper if < 50% energey, costlier if >
-        price += (2. * starting_energy as f32 / max_energy as f32) - 1.;
+    /// How many targets each target of `effect` is priced as with `area` around it. The rest of
+    /// the area adds as much of a target as the effect still reaches it with, and takes as much
+    /// away on the other side of the board, where the effect lands on the wrong side.
+    fn area_targets(&self, effect: &Effect, area: &AreaPattern) -> f32 {
+        let value = self.effect_value(effect).abs();
+        if value == 0. {
+            return 1.;
+        }
+        let extra = area_rings(area)
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(ring, (same_side, other_side))| {
+                let reached = self.effect_value(&effect.at_ring(ring as u32)).abs() / value;
+                reached * (same_side - other_side)
+            })
+            .sum::<f32>();
+        (1. + extra).max(0.)
     }
 
-    for ability in abilities {
+    fn price_step(
+        &self,
+        value: f32,
+        amount: &TargetAmount,
+        filter: &TargetFilter,
+        selection: &TargetSelection,
+        area_targets: f32,
+    ) -> f32 {
+        // targets the server picks can't be aimed at the side the effect is good for
+        let (amount, aimed) = match selection {
+            TargetSelection::Chosen => (amount.clone(), true),
+            TargetSelection::Random { n } => (TargetAmount::N { n: *n }, false),
+            TargetSelection::Auto(_) => (TargetAmount::N { n: 1 }, false),
+        };
+        let (friendly, enemy) = self.reach(filter);
+        let (friendly, enemy) = (friendly > 0., enemy > 0.);
+        let (friendly_coverage, enemy_coverage) = position_coverage(filter);
+        let coverage = match (friendly, enemy) {
+            (true, false) => friendly_coverage,
+            (false, true) => enemy_coverage,
+            _ => friendly_coverage.max(enemy_coverage),
+        };
+
+        let mut score = match (&amount, friendly, enemy) {
+            // hits both sides, so only worth using when the trade is favorable
+            (TargetAmount::All, true, true) => value.abs() * self.indiscriminate_factor,
+            (_, true, true) if !aimed => value.abs() * self.indiscriminate_factor,
+            (TargetAmount::All, true, false) => value,
+            (TargetAmount::All, false, true) => -value,
+            // chosen targets can be aimed at whichever side the effect is good for
+            (_, true, _) if value >= 0. => value,
+            (_, _, true) if value < 0. => -value,
+            (_, true, _) | (_, _, true) => -value.abs(),
+            (_, false, false) => 0.,
+        };
+
+        // `All` already hits everything an area could add
+        let multiplier = match amount {
+            TargetAmount::UpToN { n } => {
+                self.up_to_n_base + (n as f32 * area_targets * self.per_target)
+            },
+            TargetAmount::N { n } => self.n_base + (n as f32 * area_targets * self.per_target),
+            TargetAmount::All => self.n_base + (self.all_target_count * self.per_target),
+        };
+        score *= multiplier;
+        score *= 1. - self.position_penalty * (1. - coverage);
+
+        score
+    }
+
+    /// Roughly how many friendly and enemy units `filter` can match, where `ThisUnit` is one unit
+    /// and any other side is `aura_units`, shrunk by how much of it position filters allow.
+    fn reach(&self, filter: &TargetFilter) -> (f32, f32) {
+        match filter {
+            TargetFilter::ThisUnit => (1., 0.),
+            TargetFilter::Friendly => (self.aura_units, 0.),
+            TargetFilter::Enemy => (0., self.aura_units),
+            TargetFilter::Any | TargetFilter::Unoccupied | TargetFilter::Occupied => {
+                (self.aura_units, self.aura_units)
+            },
+            TargetFilter::And(filters) => filters.iter().fold(
+                (self.aura_units, self.aura_units),
+                |(friendly, enemy), filter| {
+                    let (f, e) = self.reach(filter);
+                    (friendly.min(f), enemy.min(e))
+                },
+            ),
+            TargetFilter::Or(filters) => {
+                filters.iter().fold((0., 0.), |(friendly, enemy), filter| {
+                    let (f, e) = self.reach(filter);
+                    (friendly.max(f), enemy.max(e))
+                })
+            },
+            position => {
+                let (friendly, enemy) = position_coverage(position);
+                (self.aura_units * friendly, self.aura_units * enemy)
+            },
+        }
+    }
+
+    pub fn price_passive_effect(
+        &self,
+        passive_effect: &PassiveEffect,
+        target_filter: &TargetFilter,
+    ) -> f32 {
+        let (friendly, enemy) = self.reach(target_filter);
+        // helping enemy units is as bad as helping friendly ones is good
+        let alignment = friendly - enemy;
+        // but triggers on this unit fire no matter whose unit set them off
+        let coverage = friendly + enemy;
+
+        let trigger_value = |effect: &Effect, target_rules: &ImplicitTargetRules| {
+            let value = self.effect_value(effect);
+            match target_rules {
+                ImplicitTargetRules::ThisUnit => value * coverage,
+                ImplicitTargetRules::ThatUnit => value * alignment,
+            }
+        };
+
+        match passive_effect {
+            PassiveEffect::DamageResistance { factor, .. } => {
+                -self.resistance_value * factor.log2() * alignment
+            },
+            PassiveEffect::WhenHit { effect, target_rules } => {
+                trigger_value(effect, target_rules) * self.when_hit_factor
+            },
+            PassiveEffect::WhenDies { effect, target_rules } => {
+                trigger_value(effect, target_rules) * self.when_dies_factor
+            },
+        }
+    }
+
+    /// What having `ability` adds to a unit's price.
+    pub fn price_ability(&self, ability: &Ability) -> f32 {
         let cost_change = match ability {
             Ability::Activated { effect, cost, target_rules } => {
-                let ability_price = price_effect(effect, target_rules);
-                let ability_cost = cost.get(effect).energy as f32;
-                ability_price - ability_cost
+                // an ability that can't be paid for can't be used either
+                let Ok(cost) = cost.get(effect) else { return 0. };
+                let ability_price = self.price_effect(effect, target_rules);
+                ability_price - cost.energy as f32
             },
             Ability::Passive { passive_effect, target_filter } => {
-                price_passive_effect(passive_effect, target_filter)
+                self.price_passive_effect(passive_effect, target_filter)
             },
         };
-        price += cost_change + 0.3 /*base ability cost*/;
+        cost_change + self.ability_base_cost
+    }
+
+    pub fn price_of(&self, card: &Card) -> f32 {
+        self.price_card(&card.abilities, card.hp, card.starting_energy, card.max_energy)
     }
 
-    price
+    pub fn price_card(
+        &self,
+        abilities: &[Ability],
+        hp: u32,
+        starting_energy: u32,
+        max_energy: u32,
+    ) -> f32 {
+        let mut price = self.base_price;
+
+        let hp_diff = hp as f32 - self.hp_baseline;
+        price += hp_diff * self.hp_value;
+
+        if max_energy > 0 {
+            // cheaper if < 50% energey, costlier if >
+            price +=
+                ((2. * starting_energy as f32 / max_energy as f32) - 1.) * self.energy_ratio_value;
+        }
+
+        for ability in abilities {
+            price += self.price_ability(ability);
+        }
+
+        price
+    }
+}
+
+/// Whether `card`'s price is linear in [`PriceConfig::fitted_coefficients`], which it isn't when
+/// anything it does grants abilities or summons cards.
+pub fn has_linear_price(card: &Card) -> bool {
+    fn linear(effect: &Effect) -> bool {
+        match effect {
+            Effect::GrantAbilities { .. } | Effect::SummonCard { .. } => false,
+            Effect::MultipleEffects { effects } => effects.iter().all(linear),
+            _ => true,
+        }
+    }
+    card.abilities.iter().all(|ability| match ability {
+        Ability::Activated { effect, .. } => linear(effect),
+        Ability::Passive { passive_effect, .. } => match passive_effect {
+            PassiveEffect::WhenHit { effect, .. } | PassiveEffect::WhenDies { effect, .. } => {
+                linear(effect)
+            },
+            PassiveEffect::DamageResistance { .. } => true,
+        },
+    })
+}
+
+/// Fraction of the friendly and enemy side that the position filters in `filter` allow, averaged
+/// over every location a friendly source could be in. Both are 1 when position doesn't matter.
+fn position_coverage(filter: &TargetFilter) -> (f32, f32) {
+    let pairs = side_coords().count().pow(2) as f32;
+    let coverage = |friendly: bool| {
+        let allowed = side_coords()
+            .flat_map(|source| side_coords().map(move |target| (source, target)))
+            .filter(|(source, target)| {
+                filter.allows_position(board_coord(*source, true), board_coord(*target, friendly))
+            })
+            .count();
+        allowed as f32 / pairs
+    };
+    (coverage(true), coverage(false))
+}
+
+/// Average number of locations in each ring of `area` on the center's side of the board and on
+/// the other side, over every location the center could be in. Ring 0 is the center.
+fn area_rings(area: &AreaPattern) -> Vec<(f32, f32)> {
+    let centers = side_coords().count() as f32;
+    let mut rings: Vec<(f32, f32)> = vec![];
+    for center in side_coords() {
+        let center_pos = board_coord(center, true);
+        for same_side in [true, false] {
+            for coord in side_coords() {
+                let Some(ring) = area.ring(center_pos, board_coord(coord, same_side)) else {
+                    continue;
+                };
+                let ring = ring as usize;
+                if rings.len() <= ring {
+                    rings.resize(ring + 1, (0., 0.));
+                }
+                let count = if same_side { &mut rings[ring].0 } else { &mut rings[ring].1 };
+                *count += 1. / centers;
+            }
+        }
+    }
+    rings
+}
+
+pub fn price_effect(effect: &Effect, target_rules: &TargetRules) -> f32 {
+    PriceConfig::get().price_effect(effect, target_rules)
+}
+
+pub fn price_passive_effect(passive_effect: &PassiveEffect, target_filter: &TargetFilter) -> f32 {
+    PriceConfig::get().price_passive_effect(passive_effect, target_filter)
+}
+
+pub fn price_card(abilities: &[Ability], hp: u32, starting_energy: u32, max_energy: u32) -> f32 {
+    PriceConfig::get().price_card(abilities, hp, starting_energy, max_energy)
 }
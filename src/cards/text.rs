@@ -2,8 +2,8 @@ use std::fmt::{Display, Formatter};
 
 use crate::{
     cards::{
-        Ability, Card, Effect, EffectType, ImplicitTargetRules, PassiveEffect, TargetAmount,
-        TargetFilter, TargetRules,
+        Ability, AreaPattern, AutoPriority, Card, Effect, EffectType, ImplicitTargetRules,
+        PassiveEffect, TargetAmount, TargetFilter, TargetRules, TargetSelection,
     },
     utils::StrJoin,
 };
@@ -27,7 +27,7 @@ impl Ability {
     pub fn full_text(&self) -> String {
         match self {
             Ability::Activated { effect, cost, target_rules } => {
-                let energy_cost = self.cost().unwrap().energy;
+                let energy_cost = self.cost().map_or("?".to_string(), |c| c.energy.to_string());
                 let effect_str = effect.full_text(target_rules.text());
 
                 format!("{{{energy_cost}}}: {effect_str}",)
@@ -42,8 +42,12 @@ impl Ability {
 impl Effect {
     pub fn full_text(&self, target_str: String) -> String {
         match self {
-            Effect::Attack { damage, effect_type } => {
-                format!("Deal {damage} {effect_type} damage to {target_str}.")
+            Effect::Attack { damage, effect_type, falloff } => {
+                let falloff = match falloff {
+                    0 => String::new(),
+                    n => format!(", {n} less per step out"),
+                };
+                format!("Deal {damage} {effect_type} damage to {target_str}{falloff}.")
             },
             Effect::GrantAbilities { abilities } => std::iter::once(format!("Give {target_str}:"))
                 .chain(abilities.iter().map(Ability::full_text))
@@ -65,6 +69,15 @@ impl Effect {
             Effect::DestroyCard => {
                 format!("Destroy {target_str}")
             },
+            Effect::Move { destination } => {
+                format!("Move {target_str} to chosen {} on the same side.", destination.text())
+            },
+            Effect::Swap { destination } => {
+                format!("Swap {target_str} with chosen {} on the same side.", destination.text())
+            },
+            Effect::Push { distance } => {
+                format!("Push {target_str} back up to {distance} row(s).")
+            },
         }
     }
 }
@@ -112,25 +125,100 @@ impl ImplicitTargetRules {
 
 impl TargetRules {
     pub fn text(&self) -> String {
-        match self.amount {
-            TargetAmount::All => format!("all {}", self.filter.text()),
-            TargetAmount::N { n } => format!("{n} {}", self.filter.text()),
-            TargetAmount::UpToN { n } => format!("up to {n} {}", self.filter.text()),
+        // later steps are checked from the target before them
+        let step_text = |amount, filter_text| match &self.selection {
+            TargetSelection::Chosen => amount_text(amount, filter_text),
+            TargetSelection::Random { n } => format!("{n} random {filter_text}"),
+            TargetSelection::Auto(priority) => format!("the {filter_text} {priority}"),
+        };
+        let targets = std::iter::once(step_text(&self.amount, self.filter.text()))
+            .chain(
+                self.then.iter().map(|step| step_text(&step.amount, step.filter.text_from("that"))),
+            )
+            .join(", then ");
+        match &self.area {
+            Some(area) => format!("{targets} and {area}"),
+            None => targets,
+        }
+    }
+}
+
+impl Display for AreaPattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AreaPattern::Splash { radius } => {
+                write!(f, "everything within {radius} step(s) of each")
+            },
+            AreaPattern::Column => f.write_str("the rest of each one's column"),
+            AreaPattern::Row => f.write_str("the rest of each one's row"),
+            AreaPattern::Cross { reach } => {
+                write!(f, "each one's row and column within {reach} step(s)")
+            },
         }
     }
 }
 
+fn amount_text(amount: &TargetAmount, filter_text: String) -> String {
+    match amount {
+        TargetAmount::All => format!("all {filter_text}"),
+        TargetAmount::N { n } => format!("{n} {filter_text}"),
+        TargetAmount::UpToN { n } => format!("up to {n} {filter_text}"),
+    }
+}
+
+impl Display for AutoPriority {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            AutoPriority::LowestHp => "with the lowest HP",
+            AutoPriority::HighestHp => "with the highest HP",
+            AutoPriority::MostEnergy => "with the most energy",
+            AutoPriority::FrontMost => "furthest forward",
+            AutoPriority::BackMost => "furthest back",
+        })
+    }
+}
+
 impl TargetFilter {
     pub fn text(&self) -> String {
+        self.text_from("this")
+    }
+
+    /// Like [`TargetFilter::text`], with `this` naming what the filter is checked from.
+    fn text_from(&self, this: &str) -> String {
         match self {
             TargetFilter::Any => "location(s)".to_string(),
-            TargetFilter::ThisUnit => "this unit".to_string(),
+            TargetFilter::ThisUnit => format!("{this} unit"),
             TargetFilter::Friendly => "friendly".to_string(),
             TargetFilter::Enemy => "enemy".to_string(),
             TargetFilter::Unoccupied => "open location(s)".to_string(),
             TargetFilter::Occupied => "unit(s)".to_string(),
-            TargetFilter::And(conds) => conds.iter().map(|f| f.text()).join(" "),
-            TargetFilter::Or(conds) => conds.iter().map(|f| f.text()).join(" or "),
+            TargetFilter::And(conds) => {
+                // positions read best after the rest, like "enemy unit(s) in this column"
+                let (positions, rest): (Vec<_>, Vec<_>) =
+                    conds.iter().partition(|f| f.is_positional());
+                let mut words = rest.iter().map(|f| f.text_from(this)).collect::<Vec<_>>();
+                if words.is_empty() {
+                    words.push("location(s)".to_string());
+                }
+                words.extend(positions.iter().map(|f| f.position_text(this)));
+                words.join(" ")
+            },
+            TargetFilter::Or(conds) => conds.iter().map(|f| f.text_from(this)).join(" or "),
+            position => format!("location(s) {}", position.position_text(this)),
+        }
+    }
+
+    /// Where a positional filter's targets are, empty for any other filter.
+    fn position_text(&self, this: &str) -> String {
+        match self {
+            TargetFilter::Adjacent => format!("adjacent to {this}"),
+            TargetFilter::SameColumn => format!("in {this} column"),
+            TargetFilter::SameRow => format!("in {this} row"),
+            TargetFilter::DirectlyInFront => format!("directly in front of {this}"),
+            TargetFilter::FrontRow => "in a front row".to_string(),
+            TargetFilter::BackRow => "in a back row".to_string(),
+            TargetFilter::WithinDistance(n) => format!("within {n} of {this}"),
+            _ => String::new(),
         }
     }
 }
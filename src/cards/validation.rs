@@ -0,0 +1,453 @@
+use std::fmt::{Display, Formatter};
+
+use bevy::math::IVec2;
+
+use crate::{
+    cards::{Ability, Card, Effect, PassiveEffect, TargetAmount, TargetFilter, TargetRules},
+    match_sim::{board_coord, side_coords, COMMAND_UNIT_COORD},
+};
+
+/// Deepest a deck can nest cards, granted abilities and multiple effects.
+pub const MAX_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeckProblem {
+    EmptyName,
+    StartingEnergyOverMax {
+        starting_energy: u32,
+        max_energy: u32,
+    },
+    /// A derived cost on anything but summoning a card, which has nothing to derive it from
+    DerivedCostWithoutCard,
+    CostOverMaxEnergy {
+        cost: u32,
+        max_energy: u32,
+    },
+    /// Needs exactly `n` targets but the board never has more than `max`
+    UnsatisfiableTargets {
+        n: usize,
+        max: usize,
+    },
+    TooDeep,
+    /// A `Move` or `Swap` anywhere but as the whole effect of an activated ability with chosen
+    /// targets, where there is no way to pick its destinations
+    UnchosenDestination,
+    /// A `Move` or `Swap` with an area, whose extra locations have no destinations
+    AreaDestination,
+}
+impl Display for DeckProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeckProblem::EmptyName => f.write_str("Card has no name."),
+            DeckProblem::StartingEnergyOverMax { starting_energy, max_energy } => {
+                write!(f, "Starting energy {starting_energy} is more than max energy {max_energy}.")
+            },
+            DeckProblem::DerivedCostWithoutCard => {
+                f.write_str("Derived costs are only valid for summoning a card.")
+            },
+            DeckProblem::CostOverMaxEnergy { cost, max_energy } => {
+                write!(f, "Costs {cost} energy but the unit can only hold {max_energy}.")
+            },
+            DeckProblem::UnsatisfiableTargets { n, max } => {
+                write!(f, "Needs {n} targets but at most {max} can be valid.")
+            },
+            DeckProblem::TooDeep => write!(f, "Nested more than {MAX_DEPTH} levels deep."),
+            DeckProblem::UnchosenDestination => f.write_str(
+                "Move and Swap must be the whole effect of an ability with chosen targets.",
+            ),
+            DeckProblem::AreaDestination => f.write_str("Move and Swap can't have an area."),
+        }
+    }
+}
+
+/// A [`DeckProblem`] and where in the deck it is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeckIssue {
+    /// Like `Command Center > summon Shrapnel Bot > ability 1`
+    pub path: String,
+    pub problem: DeckProblem,
+}
+impl Display for DeckIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.problem)
+    }
+}
+
+/// Checks the Command Center of a deck and everything it can summon.
+pub fn validate(deck: &Card) -> Result<(), Vec<DeckIssue>> {
+    let mut validator = Validator::default();
+    validator.card(deck, &deck.name, 0);
+    if validator.issues.is_empty() {
+        Ok(())
+    } else {
+        Err(validator.issues)
+    }
+}
+
+#[derive(Default)]
+struct Validator {
+    issues: Vec<DeckIssue>,
+}
+impl Validator {
+    fn report(&mut self, path: &str, problem: DeckProblem) {
+        self.issues.push(DeckIssue { path: path.to_string(), problem });
+    }
+
+    fn card(&mut self, card: &Card, path: &str, depth: usize) {
+        if depth > MAX_DEPTH {
+            return self.report(path, DeckProblem::TooDeep);
+        }
+
+        if card.name.trim().is_empty() {
+            self.report(path, DeckProblem::EmptyName);
+        }
+        if card.starting_energy > card.max_energy {
+            self.report(path, DeckProblem::StartingEnergyOverMax {
+                starting_energy: card.starting_energy,
+                max_energy: card.max_energy,
+            });
+        }
+        for (i, ability) in card.abilities.iter().enumerate() {
+            let path = match ability {
+                Ability::Activated { effect: Effect::SummonCard { card }, .. } => {
+                    format!("{path} > summon {}", card.name)
+                },
+                _ => format!("{path} > ability {}", i + 1),
+            };
+            self.ability(ability, Some(card.max_energy), &path, depth);
+        }
+    }
+
+    /// `max_energy` is `None` for granted abilities, whose unit isn't known yet.
+    fn ability(&mut self, ability: &Ability, max_energy: Option<u32>, path: &str, depth: usize) {
+        match ability {
+            Ability::Activated { effect, cost, target_rules } => {
+                let cost = match cost.get(effect) {
+                    Ok(cost) => Some(cost.energy),
+                    Err(problem) => {
+                        self.report(path, problem);
+                        None
+                    },
+                };
+                if let (Some(cost), Some(max_energy)) = (cost, max_energy) {
+                    if cost > max_energy {
+                        self.report(path, DeckProblem::CostOverMaxEnergy { cost, max_energy });
+                    }
+                }
+                // later steps are checked from a target rather than the source, but filters are
+                // relative, so the same bound holds. Picked targets take what they can get.
+                let chosen = target_rules.selection.is_chosen();
+                for (amount, filter) in target_rules.steps().filter(|_| chosen) {
+                    if let TargetAmount::N { n } = *amount {
+                        let max = max_targets(filter);
+                        if n > max {
+                            self.report(path, DeckProblem::UnsatisfiableTargets { n, max });
+                        }
+                    }
+                }
+                // the only place a destination can be chosen, see `DeckProblem::UnchosenDestination`
+                if effect.destination_filter().is_none() {
+                    self.effect(effect, path, depth + 1);
+                } else if !target_rules.selection.is_chosen() {
+                    self.report(path, DeckProblem::UnchosenDestination);
+                } else if target_rules.area.is_some() {
+                    self.report(path, DeckProblem::AreaDestination);
+                }
+            },
+            Ability::Passive { passive_effect, .. } => match passive_effect {
+                PassiveEffect::WhenHit { effect, .. } | PassiveEffect::WhenDies { effect, .. } => {
+                    self.effect(effect, path, depth + 1)
+                },
+                PassiveEffect::DamageResistance { .. } => {},
+            },
+        }
+    }
+
+    fn effect(&mut self, effect: &Effect, path: &str, depth: usize) {
+        if depth > MAX_DEPTH {
+            return self.report(path, DeckProblem::TooDeep);
+        }
+        match effect {
+            Effect::SummonCard { card } => self.card(card, path, depth + 1),
+            Effect::GrantAbilities { abilities } => {
+                for (i, ability) in abilities.iter().enumerate() {
+                    let path = format!("{path} > granted ability {}", i + 1);
+                    self.ability(ability, None, &path, depth + 1);
+                }
+            },
+            Effect::MultipleEffects { effects } => {
+                for effect in effects {
+                    self.effect(effect, path, depth + 1);
+                }
+            },
+            Effect::Move { .. } | Effect::Swap { .. } => {
+                self.report(path, DeckProblem::UnchosenDestination)
+            },
+            Effect::Attack { .. }
+            | Effect::ChangeHp { .. }
+            | Effect::ChangeEnergy { .. }
+            | Effect::DestroyCard
+            | Effect::Push { .. } => {},
+        }
+    }
+}
+
+/// Whether every step of `target_rules` can be given valid targets on some board. Steps that may
+/// take nothing always can.
+pub fn has_legal_targets(target_rules: &TargetRules) -> bool {
+    let chosen = target_rules.selection.is_chosen();
+    target_rules.steps().all(|(amount, filter)| {
+        let max = max_targets(filter);
+        match amount {
+            TargetAmount::N { n } if chosen => *n <= max,
+            TargetAmount::UpToN { n: 0 } if chosen => true,
+            _ => max > 0,
+        }
+    })
+}
+
+/// Most locations `filter` can ever match at once, from the point of view of a friendly unit
+/// anywhere on the board.
+fn max_targets(filter: &TargetFilter) -> usize {
+    side_coords()
+        .map(|source| {
+            let source_pos = board_coord(source, true);
+            let mut matches = 0;
+            for friendly in [true, false] {
+                for coord in side_coords() {
+                    let this_unit = friendly && coord == source;
+                    // command units never leave and the source is a unit, every other space may
+                    // or may not have a unit
+                    let occupancy: &[bool] = if this_unit || coord == COMMAND_UNIT_COORD {
+                        &[true]
+                    } else {
+                        &[true, false]
+                    };
+                    let pos = board_coord(coord, friendly);
+                    if occupancy.iter().any(|&occupied| {
+                        let target =
+                            Target { friendly, this_unit, occupied, source: source_pos, pos };
+                        can_match(filter, &target)
+                    }) {
+                        matches += 1;
+                    }
+                }
+            }
+            matches
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// A location a filter could be checked against, seen from the effect source.
+struct Target {
+    friendly: bool,
+    this_unit: bool,
+    occupied: bool,
+    /// Board coordinates of the source and the target
+    source: IVec2,
+    pos: IVec2,
+}
+
+fn can_match(filter: &TargetFilter, target: &Target) -> bool {
+    match filter {
+        TargetFilter::Any => true,
+        TargetFilter::ThisUnit => target.this_unit,
+        TargetFilter::Friendly => target.friendly,
+        TargetFilter::Enemy => !target.friendly,
+        TargetFilter::Unoccupied => !target.occupied,
+        TargetFilter::Occupied => target.occupied,
+        TargetFilter::And(filters) => filters.iter().all(|f| can_match(f, target)),
+        TargetFilter::Or(filters) => filters.iter().any(|f| can_match(f, target)),
+        position => position.allows_position(target.source, target.pos),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cards::{
+        deck::make_deck, AbilityCost, AreaPattern, Attribute, Cost, EffectType,
+        ImplicitTargetRules, TargetSelection, TargetStep,
+    };
+
+    fn bot(abilities: Vec<Ability>) -> Card {
+        Card {
+            name: "Test Bot".to_string(),
+            summon_cost: Cost { energy: 1 },
+            hp: 5,
+            abilities,
+            starting_energy: 1,
+            max_energy: 3,
+        }
+    }
+
+    fn one_enemy() -> TargetRules {
+        TargetRules {
+            amount: TargetAmount::N { n: 1 },
+            filter: TargetFilter::Enemy,
+            selection: TargetSelection::Chosen,
+            then: vec![],
+            area: None,
+        }
+    }
+
+    fn problems(card: Card) -> Vec<DeckProblem> {
+        match validate(&make_deck(vec![card]).deck) {
+            Ok(()) => vec![],
+            Err(issues) => issues.into_iter().map(|i| i.problem).collect(),
+        }
+    }
+
+    #[test]
+    fn derived_cost_without_card_is_reported() {
+        let attack = Effect::Attack { damage: 1, effect_type: EffectType::Explosion, falloff: 0 };
+        let ability = Ability::Activated {
+            effect: attack.clone(),
+            cost: AbilityCost::Derived { attribute: Attribute::Hp },
+            target_rules: one_enemy(),
+        };
+        assert_eq!(problems(bot(vec![ability])), vec![DeckProblem::DerivedCostWithoutCard]);
+
+        let ability = Ability::Activated {
+            effect: attack,
+            cost: AbilityCost::Static { cost: Cost { energy: 2 } },
+            target_rules: one_enemy(),
+        };
+        assert_eq!(problems(bot(vec![ability])), vec![]);
+    }
+
+    #[test]
+    fn costs_over_max_energy_are_reported() {
+        let ability = Ability::Activated {
+            effect: Effect::DestroyCard,
+            cost: AbilityCost::Static { cost: Cost { energy: 4 } },
+            target_rules: one_enemy(),
+        };
+        assert_eq!(problems(bot(vec![ability])), vec![DeckProblem::CostOverMaxEnergy {
+            cost: 4,
+            max_energy: 3
+        }]);
+    }
+
+    #[test]
+    fn empty_names_are_reported() {
+        let card = Card { name: "  ".to_string(), ..bot(vec![]) };
+        assert_eq!(problems(card), vec![DeckProblem::EmptyName]);
+    }
+
+    #[test]
+    fn starting_energy_over_max_is_reported() {
+        let card = Card { starting_energy: 4, ..bot(vec![]) };
+        assert_eq!(problems(card), vec![DeckProblem::StartingEnergyOverMax {
+            starting_energy: 4,
+            max_energy: 3
+        }]);
+    }
+
+    #[test]
+    fn unsatisfiable_chosen_targets_are_reported() {
+        let in_front = |n, selection| Ability::Activated {
+            effect: Effect::DestroyCard,
+            cost: AbilityCost::Static { cost: Cost { energy: 1 } },
+            target_rules: TargetRules {
+                amount: TargetAmount::N { n },
+                filter: TargetFilter::DirectlyInFront,
+                selection,
+                then: vec![],
+                area: None,
+            },
+        };
+        assert_eq!(problems(bot(vec![in_front(1, TargetSelection::Chosen)])), vec![]);
+        assert_eq!(problems(bot(vec![in_front(3, TargetSelection::Chosen)])), vec![
+            DeckProblem::UnsatisfiableTargets { n: 3, max: 1 }
+        ]);
+        // the server takes what it can get
+        assert_eq!(problems(bot(vec![in_front(3, TargetSelection::Random { n: 1 })])), vec![]);
+    }
+
+    #[test]
+    fn unsatisfiable_later_steps_are_reported() {
+        let ability = Ability::Activated {
+            effect: Effect::DestroyCard,
+            cost: AbilityCost::Static { cost: Cost { energy: 1 } },
+            target_rules: TargetRules {
+                then: vec![TargetStep {
+                    amount: TargetAmount::N { n: 2 },
+                    filter: TargetFilter::ThisUnit,
+                }],
+                ..one_enemy()
+            },
+        };
+        assert_eq!(problems(bot(vec![ability])), vec![DeckProblem::UnsatisfiableTargets {
+            n: 2,
+            max: 1
+        }]);
+    }
+
+    #[test]
+    fn positional_filters_limit_targets() {
+        assert_eq!(max_targets(&TargetFilter::DirectlyInFront), 1);
+        let in_front = |n| TargetRules {
+            amount: TargetAmount::N { n },
+            filter: TargetFilter::DirectlyInFront,
+            ..one_enemy()
+        };
+        assert!(has_legal_targets(&in_front(1)));
+        assert!(!has_legal_targets(&in_front(3)));
+        let both = TargetRules {
+            filter: TargetFilter::And(vec![TargetFilter::SameRow, TargetFilter::SameColumn]),
+            ..one_enemy()
+        };
+        assert!(!has_legal_targets(&both));
+    }
+
+    #[test]
+    fn nesting_too_deep_is_reported() {
+        let summon = |card| Ability::Activated {
+            effect: Effect::SummonCard { card },
+            cost: AbilityCost::Derived { attribute: Attribute::SummonCost },
+            target_rules: one_enemy(),
+        };
+        let mut card = bot(vec![]);
+        assert_eq!(problems(card.clone()), vec![]);
+        for _ in 0..MAX_DEPTH {
+            card = bot(vec![summon(card)]);
+        }
+        assert_eq!(problems(card), vec![DeckProblem::TooDeep]);
+    }
+
+    #[test]
+    fn destinations_must_be_chosen() {
+        let ability = |target_rules| Ability::Activated {
+            effect: Effect::Move { destination: TargetFilter::Unoccupied },
+            cost: AbilityCost::Static { cost: Cost { energy: 1 } },
+            target_rules,
+        };
+        assert_eq!(problems(bot(vec![ability(one_enemy())])), vec![]);
+        let random = TargetRules { selection: TargetSelection::Random { n: 1 }, ..one_enemy() };
+        assert_eq!(problems(bot(vec![ability(random)])), vec![DeckProblem::UnchosenDestination]);
+
+        let on_hit = Ability::Passive {
+            passive_effect: PassiveEffect::WhenHit {
+                effect: Effect::Move { destination: TargetFilter::Unoccupied },
+                target_rules: ImplicitTargetRules::ThatUnit,
+            },
+            target_filter: TargetFilter::Any,
+        };
+        assert_eq!(problems(bot(vec![on_hit])), vec![DeckProblem::UnchosenDestination]);
+    }
+
+    #[test]
+    fn destinations_cant_have_an_area() {
+        let ability = Ability::Activated {
+            effect: Effect::Swap { destination: TargetFilter::Any },
+            cost: AbilityCost::Static { cost: Cost { energy: 1 } },
+            target_rules: TargetRules {
+                area: Some(AreaPattern::Splash { radius: 1 }),
+                ..one_enemy()
+            },
+        };
+        assert_eq!(problems(bot(vec![ability])), vec![DeckProblem::AreaDestination]);
+    }
+}
@@ -0,0 +1,234 @@
+use crate::{
+    cards::{validate_step, Ability, Effect, TargetAmount, TargetRules},
+    match_sim::{engine::MatchState, GridLocation, PlayerId, COMMAND_UNIT_COORD},
+    network::messages::ActivateAbilityMessage,
+};
+
+/// Most target sets considered for a single ability, to keep large `N`/`UpToN` rules cheap.
+const MAX_TARGET_SETS: usize = 64;
+/// How many of the best looking actions are checked against the opponent's reply.
+const SEARCH_WIDTH: usize = 6;
+
+const WIN_SCORE: f32 = 1_000_000.;
+const UNIT_VALUE: f32 = 4.;
+const ENERGY_VALUE: f32 = 0.5;
+/// Extra value of each hp on a Command Center, since losing it loses the match
+const COMMAND_UNIT_HP_VALUE: f32 = 1.;
+
+/// Every ability activation `player` could make right now.
+pub fn legal_actions(state: &MatchState, player: PlayerId) -> Vec<ActivateAbilityMessage> {
+    let mut actions = vec![];
+    if state.current_turn != Some(player) || state.outcome.is_some() {
+        return actions;
+    }
+
+    for (loc, unit) in state.units().filter(|(loc, _)| loc.owner == player) {
+        for (ability_idx, ability) in unit.abilities.0.iter().enumerate() {
+            let Ability::Activated { effect, cost, target_rules } = ability else { continue };
+            let Ok(cost) = cost.get(effect) else { continue };
+            if cost.energy > unit.energy.current {
+                continue;
+            }
+
+            // the server picks these targets, so there's only one way to use the ability
+            if !target_rules.selection.is_chosen() {
+                actions.push(ActivateAbilityMessage {
+                    match_id: state.match_id,
+                    unit_location: loc.coord,
+                    ability_idx,
+                    targets: vec![],
+                    then_targets: vec![],
+                    destinations: vec![],
+                });
+                continue;
+            }
+
+            for mut steps in step_target_sets(state, target_rules, loc) {
+                let all_targets = steps.concat();
+                let targets = steps.remove(0);
+                let then_targets = steps;
+                for destinations in destination_sets(state, effect, &all_targets) {
+                    actions.push(ActivateAbilityMessage {
+                        match_id: state.match_id,
+                        unit_location: loc.coord,
+                        ability_idx,
+                        targets: targets.clone(),
+                        then_targets: then_targets.clone(),
+                        destinations,
+                    });
+                }
+            }
+        }
+    }
+    actions
+}
+
+/// Every valid way to pick targets for all of `target_rules`' steps, one list per step.
+fn step_target_sets(
+    state: &MatchState,
+    target_rules: &TargetRules,
+    source: GridLocation,
+) -> Vec<Vec<Vec<GridLocation>>> {
+    let mut sets = vec![vec![]];
+    for (step, (amount, filter)) in target_rules.steps().enumerate() {
+        let mut next = vec![];
+        for picked in sets {
+            let origin = TargetRules::step_origin(step, source, &picked);
+            let taken = picked.concat();
+            let valid = state
+                .locations()
+                .filter(|t| !taken.contains(t))
+                .filter(|t| origin.is_some_and(|origin| filter.validate(t, state, &origin)))
+                .collect::<Vec<_>>();
+            for targets in target_sets(amount, &valid) {
+                if next.len() >= MAX_TARGET_SETS {
+                    break;
+                }
+                if validate_step(amount, filter, &targets, state, origin.as_ref(), &taken) {
+                    let mut picked = picked.clone();
+                    picked.push(targets);
+                    next.push(picked);
+                }
+            }
+        }
+        sets = next;
+    }
+    sets
+}
+
+fn target_sets(amount: &TargetAmount, valid: &[GridLocation]) -> Vec<Vec<GridLocation>> {
+    let mut sets = vec![];
+    match amount {
+        TargetAmount::All => sets.push(valid.to_vec()),
+        TargetAmount::N { n } => combinations(valid, *n, &mut vec![], &mut sets),
+        TargetAmount::UpToN { n } => {
+            for k in 0..=(*n).min(valid.len()) {
+                combinations(valid, k, &mut vec![], &mut sets);
+            }
+        },
+    }
+    sets
+}
+
+/// Every way to pick distinct destinations for `targets`, or a single empty set for effects that
+/// don't move anything.
+fn destination_sets(
+    state: &MatchState,
+    effect: &Effect,
+    targets: &[GridLocation],
+) -> Vec<Vec<GridLocation>> {
+    if effect.destination_filter().is_none() {
+        return vec![vec![]];
+    }
+    let options = targets
+        .iter()
+        .map(|unit| {
+            state.locations().filter(|d| state.valid_destination(effect, unit, d)).collect()
+        })
+        .collect::<Vec<Vec<_>>>();
+    let mut sets = vec![];
+    destination_product(&options, &mut vec![], &mut sets);
+    sets
+}
+
+fn destination_product(
+    options: &[Vec<GridLocation>],
+    chosen: &mut Vec<GridLocation>,
+    out: &mut Vec<Vec<GridLocation>>,
+) {
+    if out.len() >= MAX_TARGET_SETS {
+        return;
+    }
+    let Some((first, rest)) = options.split_first() else {
+        out.push(chosen.clone());
+        return;
+    };
+    for option in first {
+        if chosen.contains(option) {
+            continue;
+        }
+        chosen.push(*option);
+        destination_product(rest, chosen, out);
+        chosen.pop();
+    }
+}
+
+fn combinations(
+    items: &[GridLocation],
+    k: usize,
+    chosen: &mut Vec<GridLocation>,
+    out: &mut Vec<Vec<GridLocation>>,
+) {
+    if out.len() >= MAX_TARGET_SETS {
+        return;
+    }
+    if chosen.len() == k {
+        out.push(chosen.clone());
+        return;
+    }
+    for (i, item) in items.iter().enumerate() {
+        chosen.push(*item);
+        combinations(&items[i + 1..], k, chosen, out);
+        chosen.pop();
+    }
+}
+
+/// How good the board looks for `player`, higher is better.
+pub fn evaluate(state: &MatchState, player: PlayerId) -> f32 {
+    if let Some(outcome) = state.outcome {
+        return if outcome.winner == player { WIN_SCORE } else { -WIN_SCORE };
+    }
+
+    state
+        .units()
+        .map(|(loc, unit)| {
+            let hp = unit.health.0.max(0) as f32;
+            let mut value = UNIT_VALUE + hp + unit.energy.current as f32 * ENERGY_VALUE;
+            if loc.coord == COMMAND_UNIT_COORD {
+                value += hp * COMMAND_UNIT_HP_VALUE;
+            }
+            if loc.owner == player {
+                value
+            } else {
+                -value
+            }
+        })
+        .sum()
+}
+
+/// Picks the action that leaves `player` best off after the opponent's best reply.
+pub fn choose_action(state: &MatchState, player: PlayerId) -> Option<ActivateAbilityMessage> {
+    let mut candidates = legal_actions(state, player)
+        .into_iter()
+        .filter_map(|action| {
+            let mut next = state.clone();
+            next.apply_action(player, &action).ok()?;
+            Some((evaluate(&next, player), action, next))
+        })
+        .collect::<Vec<_>>();
+    candidates.sort_by(|(a, ..), (b, ..)| b.total_cmp(a));
+    candidates.truncate(SEARCH_WIDTH);
+
+    candidates
+        .into_iter()
+        .map(|(score, action, next)| match next.current_turn {
+            Some(opponent) if opponent != player && next.outcome.is_none() => {
+                (worst_reply(&next, opponent, player).unwrap_or(score), action)
+            },
+            _ => (score, action),
+        })
+        .max_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map(|(_, action)| action)
+}
+
+/// `player`'s score after `opponent` makes the reply that hurts them most.
+fn worst_reply(state: &MatchState, opponent: PlayerId, player: PlayerId) -> Option<f32> {
+    legal_actions(state, opponent)
+        .into_iter()
+        .filter_map(|action| {
+            let mut next = state.clone();
+            next.apply_action(opponent, &action).ok()?;
+            Some(evaluate(&next, player))
+        })
+        .min_by(|a, b| a.total_cmp(b))
+}
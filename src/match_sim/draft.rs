@@ -0,0 +1,104 @@
+use std::fmt::{Display, Formatter};
+
+use bevy::prelude::Resource;
+use rand::Rng;
+
+use crate::{
+    cards::{
+        deck::{make_deck, random_summonable_card, Deck},
+        price::PriceConfig,
+        Card,
+    },
+    match_sim::MatchId,
+};
+
+/// Cards each player drafts, which make up their deck.
+pub const DRAFT_DECK_SIZE: usize = 5;
+/// Cards in each pack. Both players pick one, the rest are discarded.
+pub const PACK_SIZE: usize = 4;
+
+#[derive(Debug)]
+pub enum DraftError {
+    NotYourPick,
+    NoSuchCard,
+    DraftOver,
+}
+impl Display for DraftError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DraftError::NotYourPick => "Not your pick.",
+            DraftError::NoSuchCard => "No such card in the pack.",
+            DraftError::DraftOver => "Draft is over.",
+        })
+    }
+}
+
+/// Two players taking turns picking cards from shared packs. Seats are `0` and `1`, and who picks
+/// first alternates between packs.
+pub struct DraftState {
+    pub pack: Vec<Card>,
+    pub picks: [Vec<Card>; 2],
+    /// The seat whose pick it is
+    pub picking: usize,
+}
+impl DraftState {
+    pub fn new(rng: &mut impl Rng) -> Self {
+        let mut draft = Self { pack: vec![], picks: [vec![], vec![]], picking: 0 };
+        draft.open_pack(rng);
+        draft
+    }
+
+    fn open_pack(&mut self, rng: &mut impl Rng) {
+        self.pack = (0..PACK_SIZE).map(|_| random_summonable_card(rng)).collect();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.picks.iter().all(|picks| picks.len() >= DRAFT_DECK_SIZE)
+    }
+
+    /// Moves card `idx` of the pack to `seat`'s picks, opening a new pack once both have picked.
+    pub fn pick(&mut self, rng: &mut impl Rng, seat: usize, idx: usize) -> Result<(), DraftError> {
+        if self.is_finished() {
+            return Err(DraftError::DraftOver);
+        }
+        if seat != self.picking {
+            return Err(DraftError::NotYourPick);
+        }
+        if idx >= self.pack.len() {
+            return Err(DraftError::NoSuchCard);
+        }
+
+        self.picks[seat].push(self.pack.remove(idx));
+        let round = self.picks[0].len().min(self.picks[1].len());
+        if self.picks[0].len() == self.picks[1].len() {
+            // both picked, the other player goes first next time
+            self.picking = round % 2;
+            if !self.is_finished() {
+                self.open_pack(rng);
+            }
+        } else {
+            self.picking = 1 - seat;
+        }
+        Ok(())
+    }
+
+    pub fn decks(&self) -> [Deck; 2] {
+        self.picks.clone().map(make_deck)
+    }
+}
+
+/// The strongest card in `pack` going by its price.
+pub fn choose_draft_pick(pack: &[Card]) -> usize {
+    let config = PriceConfig::get();
+    let value = |card: &Card| config.price_of(card);
+    (0..pack.len()).max_by(|a, b| value(&pack[*a]).total_cmp(&value(&pack[*b]))).unwrap_or(0)
+}
+
+/// The pack a client is drafting from.
+#[derive(Resource)]
+pub struct DraftPack {
+    pub match_id: MatchId,
+    pub cards: Vec<Card>,
+    pub your_pick: bool,
+    pub picks: Vec<Card>,
+}
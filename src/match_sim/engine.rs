@@ -0,0 +1,1048 @@
+use std::fmt::{Display, Formatter};
+
+use bevy::{
+    log::warn,
+    math::UVec2,
+    utils::{HashMap, HashSet},
+};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cards::{Ability, Card, Effect, PassiveEffect, TargetRules, TargetSelection},
+    match_sim::{
+        side_coords, Abilities, EffectEvent, Energy, GridLocation, Health, MatchEndReason, MatchId,
+        PlayerId, UnitId, COMMAND_UNIT_COORD,
+    },
+    network::messages::ActivateAbilityMessage,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Unit {
+    pub id: UnitId,
+    pub card: Card,
+    pub health: Health,
+    pub energy: Energy,
+    pub abilities: Abilities,
+}
+impl Unit {
+    fn new(id: UnitId, card: Card) -> Self {
+        Self {
+            id,
+            health: Health(card.hp as i32),
+            energy: Energy { current: card.starting_energy, max: card.max_energy },
+            abilities: Abilities(card.abilities.clone()),
+            card,
+        }
+    }
+}
+
+/// Maximum number of effects resolved at once, to stop trigger loops.
+pub const MAX_RESOLUTION_STEPS: usize = 512;
+/// Maximum number of triggers that can be caused by a single effect in a chain.
+pub const MAX_TRIGGER_DEPTH: usize = 16;
+
+/// An effect waiting to be resolved.
+#[derive(Debug, Clone)]
+struct StackEntry {
+    effect: Effect,
+    targets: Vec<GridLocation>,
+    /// How many triggers deep this effect is
+    depth: usize,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MatchOutcome {
+    pub winner: PlayerId,
+    pub loser: PlayerId,
+    pub reason: MatchEndReason,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RuleError {
+    MatchOver,
+    NotInMatch,
+    NotYourTurn,
+    NoUnit,
+    NoSuchAbility,
+    PassiveAbility,
+    NotEnoughEnergy,
+    /// A derived cost with nothing to derive it from
+    InvalidCost,
+    InvalidTargets,
+}
+impl Display for RuleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            RuleError::MatchOver => "Match is over.",
+            RuleError::NotInMatch => "Not in this match.",
+            RuleError::NotYourTurn => "Not your turn.",
+            RuleError::NoUnit => "No unit there.",
+            RuleError::NoSuchAbility => "No such ability.",
+            RuleError::PassiveAbility => "Ability is passive.",
+            RuleError::NotEnoughEnergy => "Not enough energy.",
+            RuleError::InvalidCost => "Ability has no valid cost.",
+            RuleError::InvalidTargets => "Invalid Targets.",
+        })
+    }
+}
+
+/// Everything a client needs to rebuild a match it lost track of.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchSnapshot {
+    pub current_turn: Option<PlayerId>,
+    pub units: Vec<(GridLocation, Unit)>,
+    next_unit_id: u32,
+    effects_applied: u64,
+    seed: u64,
+}
+
+/// Identifies the state of a match after a given number of effects, so clients can check that
+/// they agree with the server.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Checksum {
+    pub effects_applied: u64,
+    pub hash: u64,
+}
+
+/// The complete rules state of a single match, independent of the ECS.
+///
+/// The server resolves actions with [`MatchState::apply_action`], clients replay the resulting
+/// effects with [`MatchState::apply_effect`], and the `MatchSimPlugin` mirrors the units into
+/// entities for rendering.
+#[derive(Debug, Clone)]
+pub struct MatchState {
+    pub match_id: MatchId,
+    pub players: Vec<PlayerId>,
+    pub current_turn: Option<PlayerId>,
+    pub units: HashMap<GridLocation, Unit>,
+    pub outcome: Option<MatchOutcome>,
+    next_unit_id: u32,
+    /// Number of calls to [`MatchState::apply_effect`], which is the same on every client
+    effects_applied: u64,
+    stack: Vec<StackEntry>,
+    /// Chosen when the match is created. Random targets are drawn from it and the number of
+    /// effects applied so far, so the same seed and actions always pick the same targets.
+    pub seed: u64,
+}
+
+impl MatchState {
+    pub fn new(match_id: MatchId, players: Vec<PlayerId>, seed: u64) -> Self {
+        Self {
+            match_id,
+            players,
+            current_turn: None,
+            units: HashMap::default(),
+            outcome: None,
+            next_unit_id: 0,
+            effects_applied: 0,
+            stack: vec![],
+            seed,
+        }
+    }
+
+    /// Every location on the board, ordered by player, then row, then column.
+    pub fn locations(&self) -> impl Iterator<Item = GridLocation> + '_ {
+        self.players
+            .iter()
+            .flat_map(|owner| side_coords().map(|coord| GridLocation { coord, owner: *owner }))
+    }
+
+    /// Every unit on the board, in the same order as [`MatchState::locations`].
+    pub fn units(&self) -> impl Iterator<Item = (GridLocation, &Unit)> + '_ {
+        self.locations().filter_map(|loc| self.units.get(&loc).map(|unit| (loc, unit)))
+    }
+
+    pub fn snapshot(&self) -> MatchSnapshot {
+        MatchSnapshot {
+            current_turn: self.current_turn,
+            units: self.units().map(|(loc, unit)| (loc, unit.clone())).collect(),
+            next_unit_id: self.next_unit_id,
+            effects_applied: self.effects_applied,
+            seed: self.seed,
+        }
+    }
+
+    /// Replaces the board with one from [`MatchState::snapshot`].
+    pub fn restore(&mut self, snapshot: MatchSnapshot) {
+        let MatchSnapshot { current_turn, units, next_unit_id, effects_applied, seed } = snapshot;
+        self.current_turn = current_turn;
+        self.units = units.into_iter().collect();
+        self.next_unit_id = next_unit_id;
+        self.effects_applied = effects_applied;
+        self.seed = seed;
+        self.stack.clear();
+    }
+
+    /// A hash of the board and current turn that is the same on every platform.
+    pub fn checksum(&self) -> Checksum {
+        let units = self.units().collect::<Vec<_>>();
+        let bytes = bincode::serialize(&(self.current_turn, units, self.next_unit_id))
+            .expect("MatchState is serializable");
+
+        // FNV-1a
+        let mut hash = 0xcbf29ce484222325_u64;
+        for byte in bytes {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        Checksum { effects_applied: self.effects_applied, hash }
+    }
+
+    /// Describes how this state differs from `other`, one line per difference.
+    pub fn diff(&self, other: &MatchSnapshot) -> Vec<String> {
+        let mut diff = vec![];
+        if self.current_turn != other.current_turn {
+            diff.push(format!("current turn: {:?} vs {:?}", self.current_turn, other.current_turn));
+        }
+        if self.effects_applied != other.effects_applied {
+            diff.push(format!(
+                "effects applied: {} vs {}",
+                self.effects_applied, other.effects_applied
+            ));
+        }
+
+        let others = other.units.iter().map(|(loc, unit)| (*loc, unit)).collect::<HashMap<_, _>>();
+        for loc in self.locations() {
+            let at = format!("{} ({}, {})", loc.owner, loc.coord.x, loc.coord.y);
+            match (self.units.get(&loc), others.get(&loc)) {
+                (None, None) => {},
+                (Some(ours), None) => diff.push(format!("{at}: {} vs empty", ours.card.name)),
+                (None, Some(theirs)) => diff.push(format!("{at}: empty vs {}", theirs.card.name)),
+                (Some(ours), Some(theirs)) => {
+                    if ours.id != theirs.id || ours.card.name != theirs.card.name {
+                        diff.push(format!(
+                            "{at}: {} {:?} vs {} {:?}",
+                            ours.card.name, ours.id, theirs.card.name, theirs.id
+                        ));
+                    }
+                    if ours.health.0 != theirs.health.0 {
+                        diff.push(format!("{at}: health {} vs {}", ours.health.0, theirs.health.0));
+                    }
+                    if (ours.energy.current, ours.energy.max)
+                        != (theirs.energy.current, theirs.energy.max)
+                    {
+                        diff.push(format!(
+                            "{at}: energy {}/{} vs {}/{}",
+                            ours.energy.current,
+                            ours.energy.max,
+                            theirs.energy.current,
+                            theirs.energy.max
+                        ));
+                    }
+                    let ours = format!("{:?}", ours.abilities.0);
+                    let theirs = format!("{:?}", theirs.abilities.0);
+                    if ours != theirs {
+                        diff.push(format!("{at}: abilities {ours} vs {theirs}"));
+                    }
+                },
+            }
+        }
+        diff
+    }
+
+    pub fn opponent(&self, player: PlayerId) -> Option<PlayerId> {
+        self.players.iter().copied().find(|p| *p != player)
+    }
+
+    /// Summons each player's deck and hands the first turn to `first_player`.
+    pub fn start(
+        &mut self,
+        decks: Vec<(PlayerId, Card)>,
+        first_player: PlayerId,
+    ) -> Vec<EffectEvent> {
+        let mut events = vec![];
+        for (owner, deck) in decks {
+            self.push_resolved(
+                Effect::SummonCard { card: deck },
+                vec![GridLocation { owner, coord: COMMAND_UNIT_COORD }],
+                &mut events,
+            );
+        }
+        self.current_turn = Some(first_player);
+        events
+    }
+
+    /// Validates and fully resolves an ability activation, then passes the turn.
+    ///
+    /// Returns the resolved effects, already applied to this state, in the order clients must
+    /// apply them.
+    pub fn apply_action(
+        &mut self,
+        player: PlayerId,
+        action: &ActivateAbilityMessage,
+    ) -> Result<Vec<EffectEvent>, RuleError> {
+        if self.outcome.is_some() {
+            return Err(RuleError::MatchOver);
+        }
+        if !self.players.contains(&player) {
+            return Err(RuleError::NotInMatch);
+        }
+        if self.current_turn != Some(player) {
+            return Err(RuleError::NotYourTurn);
+        }
+
+        let source_loc = GridLocation { owner: player, coord: action.unit_location };
+        let unit = self.units.get(&source_loc).ok_or(RuleError::NoUnit)?;
+        let ability = unit.abilities.0.get(action.ability_idx).ok_or(RuleError::NoSuchAbility)?;
+        let Ability::Activated { effect, cost, target_rules } = ability else {
+            return Err(RuleError::PassiveAbility);
+        };
+
+        let energy_cost = cost.get(effect).map_err(|_| RuleError::InvalidCost)?.energy;
+        if energy_cost > unit.energy.current {
+            return Err(RuleError::NotEnoughEnergy);
+        }
+        let (effect, target_rules) = (effect.clone(), target_rules.clone());
+
+        let steps = if target_rules.selection.is_chosen() {
+            let mut steps = vec![action.targets.clone()];
+            steps.extend_from_slice(&action.then_targets);
+            if !target_rules.validate(&steps, self, &source_loc) {
+                return Err(RuleError::InvalidTargets);
+            }
+            steps
+        } else if action.targets.is_empty() && action.then_targets.is_empty() {
+            self.select_targets(&target_rules, source_loc)
+        } else {
+            return Err(RuleError::InvalidTargets);
+        };
+        // the effect lands on the targets of every step
+        let targets = steps.concat();
+        let entries = if effect.destination_filter().is_some() {
+            // destinations are picked per target, there's no area to move
+            let targets = self
+                .move_targets(&effect, &targets, &action.destinations)
+                .filter(|_| target_rules.area.is_none())
+                .ok_or(RuleError::InvalidTargets)?;
+            vec![StackEntry { effect, targets, depth: 0 }]
+        } else if action.destinations.is_empty() {
+            self.ring_entries(&effect, &target_rules, &targets)
+        } else {
+            return Err(RuleError::InvalidTargets);
+        };
+
+        let mut events = vec![];
+        self.resolve(
+            Effect::ChangeEnergy { amount: -(energy_cost as i32) },
+            vec![source_loc],
+            &mut events,
+        );
+        self.resolve_all(entries, &mut events);
+
+        if self.outcome.is_none() {
+            let next_player = self.opponent(player).unwrap_or(player);
+            self.current_turn = Some(next_player);
+
+            let next_units =
+                self.units().filter(|(loc, _)| loc.owner == next_player).map(|(loc, _)| loc);
+            let next_units = next_units.collect::<Vec<_>>();
+            if !next_units.is_empty() {
+                self.resolve(Effect::ChangeEnergy { amount: 1 }, next_units, &mut events);
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// `effect` on `targets` and the areas around them, split by ring so each ring gets its own
+    /// falloff, center first.
+    fn ring_entries(
+        &self,
+        effect: &Effect,
+        target_rules: &TargetRules,
+        targets: &[GridLocation],
+    ) -> Vec<StackEntry> {
+        if target_rules.area.is_none() {
+            return vec![StackEntry {
+                effect: effect.clone(),
+                targets: targets.to_vec(),
+                depth: 0,
+            }];
+        }
+
+        let mut rings: Vec<Vec<GridLocation>> = vec![];
+        for (loc, ring) in target_rules.area_targets(targets, self) {
+            let ring = ring as usize;
+            if rings.len() <= ring {
+                rings.resize(ring + 1, vec![]);
+            }
+            rings[ring].push(loc);
+        }
+        rings
+            .into_iter()
+            .enumerate()
+            .filter(|(_, targets)| !targets.is_empty())
+            .map(|(ring, targets)| StackEntry {
+                effect: effect.at_ring(ring as u32),
+                targets,
+                depth: 0,
+            })
+            .collect()
+    }
+
+    /// Picks the targets of every step for rules whose targets the player doesn't choose.
+    fn select_targets(
+        &self,
+        target_rules: &TargetRules,
+        source: GridLocation,
+    ) -> Vec<Vec<GridLocation>> {
+        let mut rng = StdRng::seed_from_u64(self.seed.wrapping_add(self.effects_applied));
+        let mut steps: Vec<Vec<GridLocation>> = vec![];
+        for (i, (_, filter)) in target_rules.steps().enumerate() {
+            let origin = TargetRules::step_origin(i, source, &steps);
+            let taken = steps.concat();
+            let valid = self
+                .locations()
+                .filter(|loc| !taken.contains(loc))
+                .filter(|loc| origin.is_some_and(|origin| filter.validate(loc, self, &origin)))
+                .collect::<Vec<_>>();
+            let targets = match &target_rules.selection {
+                TargetSelection::Chosen => vec![],
+                TargetSelection::Random { n } => {
+                    valid.choose_multiple(&mut rng, *n).copied().collect()
+                },
+                TargetSelection::Auto(priority) => valid
+                    .into_iter()
+                    .min_by_key(|loc| priority.rank(loc, self))
+                    .into_iter()
+                    .collect(),
+            };
+            steps.push(targets);
+        }
+        steps
+    }
+
+    /// Pairs each target with its destination, as `[unit, destination, unit, destination, ..]`,
+    /// if every destination is valid for `effect` before anything has moved.
+    fn move_targets(
+        &self,
+        effect: &Effect,
+        targets: &[GridLocation],
+        destinations: &[GridLocation],
+    ) -> Option<Vec<GridLocation>> {
+        if targets.len() != destinations.len() {
+            return None;
+        }
+        let mut used = HashSet::new();
+        let mut pairs = vec![];
+        for (unit, destination) in targets.iter().zip(destinations) {
+            if !used.insert(*destination) || !self.valid_destination(effect, unit, destination) {
+                return None;
+            }
+            pairs.extend([*unit, *destination]);
+        }
+        Some(pairs)
+    }
+
+    /// Whether `effect` can move the unit at `unit` to `destination`.
+    pub fn valid_destination(
+        &self,
+        effect: &Effect,
+        unit: &GridLocation,
+        destination: &GridLocation,
+    ) -> bool {
+        let Some(filter) = effect.destination_filter() else { return false };
+        let swap = matches!(effect, Effect::Swap { .. });
+        filter.validate(destination, self, unit) && self.can_relocate(unit, destination, swap)
+    }
+
+    /// Whether `effect` can move the unit at `unit` anywhere at all.
+    pub fn has_destination(&self, effect: &Effect, unit: &GridLocation) -> bool {
+        self.locations().any(|destination| self.valid_destination(effect, unit, &destination))
+    }
+
+    /// Whether the unit at `from` may end up at `to`. Units stay on their owner's half, command
+    /// units never move, and only a swap may land on another unit.
+    fn can_relocate(&self, from: &GridLocation, to: &GridLocation, swap: bool) -> bool {
+        from != to
+            && from.owner == to.owner
+            && from.coord != COMMAND_UNIT_COORD
+            && to.coord != COMMAND_UNIT_COORD
+            && self.units.contains_key(from)
+            && (swap || !self.units.contains_key(to))
+            && self.locations().any(|loc| loc == *to)
+    }
+
+    /// Applies an effect that needs no further resolution, i.e. one produced by
+    /// [`MatchState::apply_action`].
+    pub fn apply_effect(&mut self, effect: &Effect, targets: &[GridLocation]) {
+        self.effects_applied += 1;
+        match effect {
+            Effect::SummonCard { card } => {
+                for t in targets {
+                    let id = UnitId(self.next_unit_id);
+                    self.next_unit_id += 1;
+                    self.units.insert(*t, Unit::new(id, card.clone()));
+                }
+            },
+            Effect::GrantAbilities { abilities } => {
+                for t in targets {
+                    let Some(unit) = self.units.get_mut(t) else { continue };
+                    unit.abilities.0.extend_from_slice(abilities);
+                }
+            },
+            Effect::ChangeHp { amount } => {
+                for t in targets {
+                    let Some(unit) = self.units.get_mut(t) else { continue };
+                    unit.health.0 += amount;
+                }
+            },
+            Effect::ChangeEnergy { amount } => {
+                for t in targets {
+                    let Some(unit) = self.units.get_mut(t) else { continue };
+                    let e = &mut unit.energy;
+                    e.current = (e.current as i32 + amount).clamp(0, e.max as i32) as u32;
+                }
+            },
+            Effect::DestroyCard => {
+                for t in targets {
+                    if self.units.remove(t).is_none() || t.coord != COMMAND_UNIT_COORD {
+                        continue;
+                    }
+                    if let (None, Some(winner)) = (self.outcome, self.opponent(t.owner)) {
+                        self.outcome = Some(MatchOutcome {
+                            winner,
+                            loser: t.owner,
+                            reason: MatchEndReason::CommandUnitDestroyed,
+                        });
+                    }
+                }
+            },
+            // resolved into `[from, to, ..]` pairs
+            Effect::Move { .. } | Effect::Swap { .. } | Effect::Push { .. } => {
+                for pair in targets.chunks_exact(2) {
+                    let (from, to) = (pair[0], pair[1]);
+                    let moving = self.units.remove(&from);
+                    if let Some(displaced) = self.units.remove(&to) {
+                        self.units.insert(from, displaced);
+                    }
+                    if let Some(unit) = moving {
+                        self.units.insert(to, unit);
+                    }
+                }
+            },
+            Effect::Attack { .. } | Effect::MultipleEffects { .. } => {
+                warn!("{effect:?} must be resolved before being applied");
+            },
+        }
+    }
+
+    /// Resolves `effect` and everything it triggers before returning.
+    ///
+    /// Effects are resolved last in, first out, so triggers resolve before the effects that were
+    /// already waiting on the stack. Dead units are only destroyed once the stack is empty.
+    fn resolve(
+        &mut self,
+        effect: Effect,
+        targets: Vec<GridLocation>,
+        events: &mut Vec<EffectEvent>,
+    ) {
+        self.resolve_all(vec![StackEntry { effect, targets, depth: 0 }], events);
+    }
+
+    /// Like [`MatchState::resolve`], for several effects that resolve in order.
+    fn resolve_all(&mut self, entries: Vec<StackEntry>, events: &mut Vec<EffectEvent>) {
+        // reversed so the first entry is on top of the stack
+        self.stack.extend(entries.into_iter().rev());
+
+        let mut steps = 0;
+        loop {
+            let Some(StackEntry { effect, targets, depth }) = self.stack.pop() else {
+                let dead = self.dead_units();
+                if dead.is_empty() {
+                    return;
+                }
+                self.stack.push(StackEntry {
+                    effect: Effect::DestroyCard,
+                    targets: dead,
+                    depth: 0,
+                });
+                continue;
+            };
+
+            steps += 1;
+            if steps > MAX_RESOLUTION_STEPS {
+                warn!(
+                    "Effect resolution exceeded {MAX_RESOLUTION_STEPS} steps, dropping the stack."
+                );
+                self.stack.clear();
+                break;
+            }
+
+            match effect {
+                Effect::Attack { damage, effect_type, .. } => {
+                    // reversed so the first target is on top of the stack
+                    for t in targets.into_iter().rev() {
+                        let mut final_factor = 1.;
+                        let mut triggers = vec![];
+                        for (passive_effect, ability_source_loc) in self.passives_affecting(&t) {
+                            match passive_effect {
+                                PassiveEffect::DamageResistance {
+                                    effect_type: effect_filter,
+                                    factor,
+                                } => {
+                                    if effect_type == *effect_filter {
+                                        final_factor *= factor;
+                                    }
+                                },
+                                PassiveEffect::WhenHit { effect, target_rules } => {
+                                    triggers.push(StackEntry {
+                                        effect: effect.clone(),
+                                        targets: vec![target_rules.target(ability_source_loc, t)],
+                                        depth: depth + 1,
+                                    })
+                                },
+                                PassiveEffect::WhenDies { .. } => {},
+                            }
+                        }
+                        self.push_triggers(triggers);
+
+                        let final_dmg = damage as f32 * final_factor;
+                        self.stack.push(StackEntry {
+                            effect: Effect::ChangeHp { amount: -(final_dmg as i32) },
+                            targets: vec![t],
+                            depth,
+                        });
+                    }
+                },
+                Effect::MultipleEffects { effects } => {
+                    // reversed so the effects resolve in the order they are listed
+                    for e in effects.into_iter().rev() {
+                        self.stack.push(StackEntry { effect: e, targets: targets.clone(), depth });
+                    }
+                },
+                Effect::DestroyCard => {
+                    let mut triggers = vec![];
+                    for t in &targets {
+                        for (passive_effect, ability_source_loc) in self.passives_affecting(t) {
+                            if let PassiveEffect::WhenDies { effect, target_rules } = passive_effect
+                            {
+                                triggers.push(StackEntry {
+                                    effect: effect.clone(),
+                                    targets: vec![target_rules.target(ability_source_loc, *t)],
+                                    depth: depth + 1,
+                                });
+                            }
+                        }
+                    }
+                    self.push_resolved(Effect::DestroyCard, targets, events);
+                    self.push_triggers(triggers);
+                },
+                Effect::Move { .. } | Effect::Swap { .. } => {
+                    let swap = matches!(effect, Effect::Swap { .. });
+                    // earlier moves can block later ones
+                    for pair in targets.chunks_exact(2) {
+                        if self.can_relocate(&pair[0], &pair[1], swap) {
+                            self.push_resolved(effect.clone(), pair.to_vec(), events);
+                        }
+                    }
+                },
+                Effect::Push { distance } => {
+                    for from in targets {
+                        let mut to = from;
+                        for _ in 0..distance {
+                            let Some(row) = to.coord.x.checked_sub(1) else { break };
+                            let next = GridLocation { coord: UVec2::new(row, to.coord.y), ..to };
+                            if !self.can_relocate(&from, &next, false) {
+                                break;
+                            }
+                            to = next;
+                        }
+                        if to != from {
+                            self.push_resolved(Effect::Push { distance }, vec![from, to], events);
+                        }
+                    }
+                },
+                Effect::SummonCard { .. }
+                | Effect::GrantAbilities { .. }
+                | Effect::ChangeHp { .. }
+                | Effect::ChangeEnergy { .. } => self.push_resolved(effect, targets, events),
+            }
+        }
+
+        // out of steps, dead units still leave the board but their triggers are dropped too
+        let dead = self.dead_units();
+        if !dead.is_empty() {
+            self.push_resolved(Effect::DestroyCard, dead, events);
+        }
+    }
+
+    fn dead_units(&self) -> Vec<GridLocation> {
+        self.units().filter(|(_, u)| u.health.0 <= 0).map(|(loc, _)| loc).collect()
+    }
+
+    fn push_triggers(&mut self, triggers: Vec<StackEntry>) {
+        for trigger in triggers {
+            if trigger.depth > MAX_TRIGGER_DEPTH {
+                warn!("Trigger depth exceeded {MAX_TRIGGER_DEPTH}, skipping {:?}", trigger.effect);
+                continue;
+            }
+            self.stack.push(trigger);
+        }
+    }
+
+    /// Passive abilities on the board whose filter matches `target`, along with the location of the
+    /// unit that has them.
+    fn passives_affecting(&self, target: &GridLocation) -> Vec<(&PassiveEffect, GridLocation)> {
+        self.units()
+            .flat_map(|(loc, unit)| unit.abilities.0.iter().map(move |a| (a, loc)))
+            .filter_map(|(ability, ability_source_loc)| match ability {
+                Ability::Passive { passive_effect, target_filter }
+                    if target_filter.validate(target, self, &ability_source_loc) =>
+                {
+                    Some((passive_effect, ability_source_loc))
+                },
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn push_resolved(
+        &mut self,
+        effect: Effect,
+        targets: Vec<GridLocation>,
+        events: &mut Vec<EffectEvent>,
+    ) {
+        self.apply_effect(&effect, &targets);
+        events.push(EffectEvent { match_id: self.match_id, effect, targets });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cards::{
+        validate_step, AbilityCost, Cost, EffectType, ImplicitTargetRules, TargetAmount,
+        TargetFilter, TargetStep,
+    };
+
+    fn card(hp: u32, abilities: Vec<Ability>) -> Card {
+        Card {
+            name: format!("Test Bot {hp}"),
+            summon_cost: Cost::FREE,
+            hp,
+            abilities,
+            starting_energy: 3,
+            max_energy: 5,
+        }
+    }
+
+    fn activated(
+        effect: Effect,
+        energy: u32,
+        amount: TargetAmount,
+        filter: TargetFilter,
+    ) -> Ability {
+        Ability::Activated {
+            effect,
+            cost: AbilityCost::Static { cost: Cost { energy } },
+            target_rules: TargetRules {
+                amount,
+                filter,
+                selection: TargetSelection::Chosen,
+                then: vec![],
+                area: None,
+            },
+        }
+    }
+
+    fn attack(damage: u32) -> Effect {
+        Effect::Attack { damage, effect_type: EffectType::Physical, falloff: 0 }
+    }
+
+    fn when_hit(effect: Effect) -> Ability {
+        Ability::Passive {
+            passive_effect: PassiveEffect::WhenHit {
+                effect,
+                target_rules: ImplicitTargetRules::ThisUnit,
+            },
+            target_filter: TargetFilter::ThisUnit,
+        }
+    }
+
+    /// A started match where both command units are `deck`, on the first player's turn.
+    fn start(deck: Card) -> (MatchState, PlayerId, PlayerId) {
+        let (a, b) = (PlayerId::new(), PlayerId::new());
+        let mut state = MatchState::new(MatchId::new(), vec![a, b], 0);
+        state.start(vec![(a, deck.clone()), (b, deck)], a);
+        (state, a, b)
+    }
+
+    fn at(owner: PlayerId, x: u32, y: u32) -> GridLocation {
+        GridLocation { owner, coord: UVec2::new(x, y) }
+    }
+
+    /// Puts a unit on the board without summoning it.
+    fn place(state: &mut MatchState, loc: GridLocation, card: Card) {
+        let id = UnitId(state.next_unit_id);
+        state.next_unit_id += 1;
+        state.units.insert(loc, Unit::new(id, card));
+    }
+
+    fn action(
+        state: &MatchState,
+        unit: GridLocation,
+        ability_idx: usize,
+        targets: Vec<GridLocation>,
+    ) -> ActivateAbilityMessage {
+        ActivateAbilityMessage {
+            match_id: state.match_id,
+            unit_location: unit.coord,
+            ability_idx,
+            targets,
+            then_targets: vec![],
+            destinations: vec![],
+        }
+    }
+
+    /// The events as `(effect, targets)`, minus the energy changes that every action has.
+    fn log(events: &[EffectEvent]) -> Vec<(String, Vec<GridLocation>)> {
+        events
+            .iter()
+            .filter(|e| !matches!(e.effect, Effect::ChangeEnergy { amount: -2 | 1 }))
+            .map(|e| (format!("{:?}", e.effect), e.targets.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn rejects_invalid_actions() {
+        let deck = card(20, vec![
+            activated(attack(1), 2, TargetAmount::N { n: 1 }, TargetFilter::Enemy),
+            activated(attack(1), 4, TargetAmount::N { n: 1 }, TargetFilter::Enemy),
+            when_hit(Effect::ChangeEnergy { amount: 1 }),
+        ]);
+        let (mut state, a, b) = start(deck);
+        let (cc_a, cc_b) = (at(a, 0, 2), at(b, 0, 2));
+        let checksum = state.checksum();
+
+        let attempts = [
+            (PlayerId::new(), action(&state, cc_a, 0, vec![cc_b]), RuleError::NotInMatch),
+            (b, action(&state, cc_b, 0, vec![cc_a]), RuleError::NotYourTurn),
+            (a, action(&state, at(a, 1, 1), 0, vec![cc_b]), RuleError::NoUnit),
+            (a, action(&state, cc_a, 5, vec![cc_b]), RuleError::NoSuchAbility),
+            (a, action(&state, cc_a, 2, vec![cc_b]), RuleError::PassiveAbility),
+            (a, action(&state, cc_a, 1, vec![cc_b]), RuleError::NotEnoughEnergy),
+            (a, action(&state, cc_a, 0, vec![cc_a]), RuleError::InvalidTargets),
+            (a, action(&state, cc_a, 0, vec![]), RuleError::InvalidTargets),
+            (a, action(&state, cc_a, 0, vec![cc_b, at(b, 1, 1)]), RuleError::InvalidTargets),
+        ];
+        for (player, action, error) in attempts {
+            assert_eq!(state.apply_action(player, &action).err(), Some(error));
+        }
+        // nothing changed
+        assert_eq!(state.checksum(), checksum);
+        assert_eq!(state.current_turn, Some(a));
+
+        state.apply_action(a, &action(&state, cc_a, 0, vec![cc_b])).unwrap();
+        assert_eq!(state.current_turn, Some(b));
+        assert_eq!(state.units[&cc_a].energy.current, 1);
+        assert_eq!(state.units[&cc_b].health.0, 19);
+        // the turn's energy on top of the trigger's, up to the max
+        assert_eq!(state.units[&cc_b].energy.current, 5);
+    }
+
+    #[test]
+    fn later_steps_are_checked_from_the_previous_target() {
+        let mut chain = activated(attack(1), 1, TargetAmount::N { n: 1 }, TargetFilter::Enemy);
+        let Ability::Activated { target_rules, .. } = &mut chain else { unreachable!() };
+        target_rules.then = vec![TargetStep {
+            amount: TargetAmount::N { n: 1 },
+            filter: TargetFilter::And(vec![TargetFilter::Occupied, TargetFilter::Adjacent]),
+        }];
+        let rules = target_rules.clone();
+        let (mut state, a, b) = start(card(20, vec![chain]));
+        let (cc_a, cc_b) = (at(a, 0, 2), at(b, 0, 2));
+        // next to the enemy command unit, and next to our own
+        let (near_b, near_a) = (at(b, 1, 2), at(a, 1, 2));
+        place(&mut state, near_b, card(5, vec![]));
+        place(&mut state, near_a, card(5, vec![]));
+
+        let (amount, filter) = rules.steps().nth(1).unwrap();
+        let origin = TargetRules::step_origin(1, cc_a, &[vec![cc_b]]);
+        assert_eq!(origin, Some(cc_b));
+        assert!(validate_step(amount, filter, &[near_b], &state, origin.as_ref(), &[cc_b]));
+        assert!(!validate_step(amount, filter, &[near_a], &state, origin.as_ref(), &[cc_b]));
+        assert!(validate_step(amount, filter, &[near_a], &state, Some(&cc_a), &[]));
+        assert!(!validate_step(amount, filter, &[near_b], &state, None, &[]));
+
+        assert!(rules.validate(&[vec![cc_b], vec![near_b]], &state, &cc_a));
+        assert!(!rules.validate(&[vec![cc_b], vec![near_a]], &state, &cc_a));
+        assert!(!rules.validate(&[vec![cc_b], vec![cc_b]], &state, &cc_a));
+        assert!(!rules.validate(&[vec![cc_b]], &state, &cc_a));
+
+        let checksum = state.checksum();
+        let mut invalid = action(&state, cc_a, 0, vec![cc_b]);
+        invalid.then_targets = vec![vec![near_a]];
+        assert_eq!(state.apply_action(a, &invalid).err(), Some(RuleError::InvalidTargets));
+        assert_eq!(state.checksum(), checksum);
+
+        let mut valid = action(&state, cc_a, 0, vec![cc_b]);
+        valid.then_targets = vec![vec![near_b]];
+        state.apply_action(a, &valid).unwrap();
+        assert_eq!(state.units[&cc_b].health.0, 19);
+        assert_eq!(state.units[&near_b].health.0, 4);
+        assert_eq!(state.units[&near_a].health.0, 5);
+    }
+
+    #[test]
+    fn match_over_after_command_unit_dies() {
+        let deck =
+            card(1, vec![activated(attack(1), 0, TargetAmount::N { n: 1 }, TargetFilter::Enemy)]);
+        let (mut state, a, b) = start(deck);
+        let (cc_a, cc_b) = (at(a, 0, 2), at(b, 0, 2));
+
+        let events = state.apply_action(a, &action(&state, cc_a, 0, vec![cc_b])).unwrap();
+        assert!(events.iter().any(|e| matches!(e.effect, Effect::DestroyCard)));
+        assert_eq!(
+            state.outcome,
+            Some(MatchOutcome {
+                winner: a,
+                loser: b,
+                reason: MatchEndReason::CommandUnitDestroyed
+            })
+        );
+        assert_eq!(
+            state.apply_action(a, &action(&state, cc_a, 0, vec![cc_b])).err(),
+            Some(RuleError::MatchOver)
+        );
+    }
+
+    #[test]
+    fn triggers_resolve_before_waiting_effects() {
+        let hit =
+            Effect::MultipleEffects { effects: vec![attack(1), Effect::ChangeHp { amount: 3 }] };
+        let deck = card(20, vec![
+            activated(hit, 2, TargetAmount::N { n: 2 }, TargetFilter::Enemy),
+            when_hit(Effect::ChangeHp { amount: 10 }),
+        ]);
+        let (mut state, a, b) = start(deck.clone());
+        let (cc_a, cc_b, other) = (at(a, 0, 2), at(b, 0, 2), at(b, 1, 2));
+        place(&mut state, other, deck);
+
+        let events = state.apply_action(a, &action(&state, cc_a, 0, vec![cc_b, other])).unwrap();
+        let hp = |amount: i32, targets: &[GridLocation]| {
+            (format!("{:?}", Effect::ChangeHp { amount }), targets.to_vec())
+        };
+        // each hit's trigger goes on top of the stack, above the rest of the attack and the heal
+        assert_eq!(log(&events), vec![
+            hp(-1, &[cc_b]),
+            hp(10, &[cc_b]),
+            hp(-1, &[other]),
+            hp(10, &[other]),
+            hp(3, &[cc_b, other]),
+        ]);
+    }
+
+    #[test]
+    fn trigger_depth_is_capped() {
+        // every hit hits itself again
+        let deck = card(20, vec![
+            activated(attack(0), 2, TargetAmount::N { n: 1 }, TargetFilter::Enemy),
+            when_hit(attack(0)),
+        ]);
+        let (mut state, a, b) = start(deck);
+        let (cc_a, cc_b) = (at(a, 0, 2), at(b, 0, 2));
+
+        let events = state.apply_action(a, &action(&state, cc_a, 0, vec![cc_b])).unwrap();
+        let hits = events.iter().filter(|e| matches!(e.effect, Effect::ChangeHp { .. })).count();
+        // the attack itself, then one trigger per level of depth
+        assert_eq!(hits, MAX_TRIGGER_DEPTH + 1);
+        assert_eq!(state.current_turn, Some(b));
+        assert!(state.stack.is_empty());
+    }
+
+    #[test]
+    fn dead_units_are_destroyed_when_out_of_steps() {
+        let deck =
+            card(20, vec![activated(attack(1), 2, TargetAmount::N { n: 1 }, TargetFilter::Enemy)]);
+        let (mut state, a, b) = start(deck.clone());
+        let (cc_a, other) = (at(a, 0, 2), at(b, 1, 2));
+        // every hit hits itself twice more, far more than fits in the step limit
+        place(&mut state, other, card(20, vec![when_hit(attack(1)), when_hit(attack(1))]));
+
+        let events = state.apply_action(a, &action(&state, cc_a, 0, vec![other])).unwrap();
+        // stopped by the step limit, not by running out of triggers
+        let hits = events.iter().filter(|e| matches!(e.effect, Effect::ChangeHp { .. })).count();
+        assert!(hits > 20 && hits < MAX_RESOLUTION_STEPS);
+        assert!(!state.units.contains_key(&other));
+        assert!(state.dead_units().is_empty());
+        assert!(state.stack.is_empty());
+        assert_eq!(state.outcome, None);
+        assert_eq!(state.current_turn, Some(b));
+    }
+
+    #[test]
+    fn checksum_matches_replayed_state() {
+        let deck =
+            card(20, vec![activated(attack(3), 2, TargetAmount::N { n: 1 }, TargetFilter::Enemy)]);
+        let (a, b) = (PlayerId::new(), PlayerId::new());
+        let mut server = MatchState::new(MatchId::new(), vec![a, b], 7);
+        let mut client = MatchState::new(server.match_id, vec![a, b], 0);
+
+        let mut events = server.start(vec![(a, deck.clone()), (b, deck)], a);
+        let (cc_a, cc_b) = (at(a, 0, 2), at(b, 0, 2));
+        events.extend(server.apply_action(a, &action(&server, cc_a, 0, vec![cc_b])).unwrap());
+        for event in &events {
+            client.apply_effect(&event.effect, &event.targets);
+        }
+        client.current_turn = server.current_turn;
+        assert_eq!(client.checksum(), server.checksum());
+
+        // the same board in a fresh map, and restored from a snapshot
+        let mut reordered = client.clone();
+        let mut units = client.units().map(|(loc, unit)| (loc, unit.clone())).collect::<Vec<_>>();
+        units.reverse();
+        reordered.units = units.into_iter().collect();
+        assert_eq!(reordered.checksum(), server.checksum());
+        let mut restored = MatchState::new(server.match_id, vec![a, b], 0);
+        restored.restore(server.snapshot());
+        assert_eq!(restored.checksum(), server.checksum());
+        assert_eq!(restored.seed, server.seed);
+
+        client.units.get_mut(&cc_b).unwrap().health.0 += 1;
+        assert_ne!(client.checksum(), server.checksum());
+    }
+
+    #[test]
+    fn same_seed_picks_same_targets() {
+        let mut scatter = activated(attack(1), 1, TargetAmount::N { n: 2 }, TargetFilter::Enemy);
+        let Ability::Activated { target_rules, .. } = &mut scatter else { unreachable!() };
+        target_rules.selection = TargetSelection::Random { n: 2 };
+        let deck = card(20, vec![scatter]);
+        let (a, b) = (PlayerId::new(), PlayerId::new());
+
+        let targets = |seed: u64| {
+            let mut state = MatchState::new(MatchId::new(), vec![a, b], seed);
+            state.start(vec![(a, deck.clone()), (b, deck.clone())], a);
+            for y in [0, 1, 3, 4] {
+                place(&mut state, at(b, 0, y), card(5, vec![]));
+                place(&mut state, at(b, 1, y), card(5, vec![]));
+            }
+            let events = state.apply_action(a, &action(&state, at(a, 0, 2), 0, vec![])).unwrap();
+            log(&events)
+        };
+        for seed in 0..10 {
+            assert_eq!(targets(seed), targets(seed));
+        }
+        assert!((1..10).any(|seed| targets(seed) != targets(0)));
+    }
+
+    #[test]
+    fn units_without_destinations_are_known() {
+        let (mut state, a, b) = start(card(10, vec![]));
+        let move_adjacent = Effect::Move { destination: TargetFilter::Adjacent };
+        let cc = at(a, 0, 2);
+        assert!(!state.has_destination(&move_adjacent, &cc));
+
+        // boxed in by the command unit, the board's edge and two units
+        let unit = at(a, 0, 1);
+        for loc in [unit, at(a, 0, 0), at(a, 1, 1)] {
+            place(&mut state, loc, card(1, vec![]));
+        }
+        assert!(!state.has_destination(&move_adjacent, &unit));
+        assert!(state.has_destination(&Effect::Swap { destination: TargetFilter::Adjacent }, &unit));
+        assert!(state.has_destination(&move_adjacent, &at(a, 1, 1)));
+        assert!(!state.has_destination(&move_adjacent, &at(b, 1, 1)));
+    }
+}
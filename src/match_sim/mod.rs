@@ -1,13 +1,23 @@
+pub mod ai;
+pub mod draft;
+pub mod engine;
+pub mod replay;
+pub mod simulation;
+
+use std::fmt::{Display, Formatter};
+
 use bevy::{
-    ecs::{event::ManualEventReader, query::QueryData, system::BoxedSystem},
+    ecs::{query::QueryData, system::BoxedSystem},
     prelude::*,
+    utils::{HashMap, HashSet},
 };
 use bevy_mod_index::prelude::*;
 use extension_trait::extension_trait;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    cards::{mesh::NeedsMesh, Ability, Card, Effect, ImplicitTargetRules, PassiveEffect},
+    cards::{mesh::NeedsMesh, Ability, Card, Effect},
+    match_sim::engine::{Checksum, MatchSnapshot, MatchState, Unit},
     utils::Uuid,
 };
 
@@ -17,35 +27,35 @@ pub struct MatchSimPlugin {
 impl Plugin for MatchSimPlugin {
     fn build(&self, app: &mut App) {
         init_events(app);
-        let (specialized_effects, state_based_effects): (BoxedSystem, BoxedSystem) = if self.server
-        {
-            (
-                Box::new(IntoSystem::into_system(server_effects)),
-                Box::new(IntoSystem::into_system(server_state_based)),
-            )
+        app.init_resource::<Matches>();
+
+        // The server resolves effects in `MatchState::apply_action` before sending them,
+        // clients only need to replay them.
+        let apply_effects: BoxedSystem = if self.server {
+            Box::new(IntoSystem::into_system(|| {}))
         } else {
-            (
-                Box::new(IntoSystem::into_system(client_effects)),
-                Box::new(IntoSystem::into_system(|| {})),
-            )
+            Box::new(IntoSystem::into_system(client_effects))
+        };
+        let verify_checksums: BoxedSystem = if self.server {
+            Box::new(IntoSystem::into_system(|| {}))
+        } else {
+            Box::new(IntoSystem::into_system(verify_checksums))
         };
-
-        // TODO: use manual stack for effects instead of reading event queue
 
         app.add_systems(
             Update,
             (
                 start_match,
-                // apply_deferred,
-                specialized_effects,
-                common_effects,
-                // apply_deferred,
-                state_based_effects,
+                restore_snapshots,
+                apply_effects,
                 next_turn,
+                verify_checksums,
+                // apply_deferred,
+                sync_units.run_if(resource_changed::<Matches>),
             )
                 .chain(),
         );
-        app.add_systems(Update, cleanup_match);
+        app.add_systems(Update, (end_match, cleanup_match).chain());
     }
 }
 
@@ -58,6 +68,11 @@ impl MatchId {
         Self(Uuid::new())
     }
 }
+impl Display for MatchId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
 impl IndexInfo for MatchId {
     type Component = MatchId;
     type Value = MatchId;
@@ -76,6 +91,11 @@ impl PlayerId {
         Self(Uuid::new())
     }
 }
+impl Display for PlayerId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
 impl IndexInfo for PlayerId {
     type Component = PlayerId;
     type Value = PlayerId;
@@ -90,18 +110,51 @@ impl IndexInfo for PlayerId {
 #[derive(Component)]
 pub struct CurrentTurn;
 
+/// Where each player's deck is summoned at the start of a match.
+pub const COMMAND_UNIT_COORD: UVec2 = UVec2::new(0, 2);
+/// Rows on each player's half of the board
+pub const SIDE_ROWS: u32 = 2;
+/// Columns on each player's half, which line up across the halves
+pub const SIDE_COLS: u32 = 5;
+/// Size of the whole board in rows and columns, for laying it out
+pub const GRID_H: f32 = (SIDE_ROWS * 2) as f32;
+pub const GRID_W: f32 = SIDE_COLS as f32;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum MatchEndReason {
+    CommandUnitDestroyed,
+    Conceded,
+    /// The loser didn't reconnect before the grace period ran out
+    Disconnected,
+}
+
 #[derive(Resource)]
 pub struct Us(pub PlayerId);
 
+/// Present on clients while the server waits for a disconnected player to rejoin.
+#[derive(Resource)]
+pub struct MatchPaused {
+    pub player: PlayerId,
+    /// `Time::elapsed_seconds` when the player forfeits
+    pub forfeit_at: f32,
+}
+
+#[derive(Resource, Default)]
+pub struct Matches(pub HashMap<MatchId, MatchState>);
+
 // ====== Card Components ======
 
 #[derive(Component)]
 pub struct BaseCard(pub Card);
 
-#[derive(Component, Debug)]
+/// Identifies a unit across the `MatchState` and the entity mirroring it.
+#[derive(Component, Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct UnitId(pub u32);
+
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
 pub struct Health(pub i32);
 
-#[derive(Component, Debug)]
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
 pub struct Energy {
     pub current: u32,
     pub max: u32,
@@ -109,9 +162,29 @@ pub struct Energy {
 
 #[derive(Component, Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct GridLocation {
+    /// `x` is the row, counting from the owner's back row, and `y` is the column
     pub coord: UVec2,
     pub owner: PlayerId,
 }
+impl GridLocation {
+    /// Where this location is on the whole board as seen by `viewer`. See [`board_coord`].
+    pub fn board_coord(&self, viewer: PlayerId) -> IVec2 {
+        board_coord(self.coord, self.owner == viewer)
+    }
+}
+
+/// Maps a coordinate on either player's half into one board shared by both halves. Rows (`x`)
+/// count up from the viewer's back row to the opponent's back row, so moving forward is `+x`.
+/// Columns (`y`) line up across the halves.
+pub fn board_coord(coord: UVec2, friendly: bool) -> IVec2 {
+    let row = if friendly { coord.x } else { SIDE_ROWS * 2 - 1 - coord.x };
+    IVec2::new(row as i32, coord.y as i32)
+}
+
+/// Every coordinate on one player's half, ordered by row, then column.
+pub fn side_coords() -> impl Iterator<Item = UVec2> {
+    (0..SIDE_ROWS).flat_map(|x| (0..SIDE_COLS).map(move |y| UVec2::new(x, y)))
+}
 impl IndexInfo for GridLocation {
     type Component = GridLocation;
     type Value = GridLocation;
@@ -122,19 +195,8 @@ impl IndexInfo for GridLocation {
         *g
     }
 }
-pub struct OwnerIndex;
-impl IndexInfo for OwnerIndex {
-    type Component = GridLocation;
-    type Value = PlayerId;
-    type Storage = HashmapStorage<Self>;
-    type RefreshPolicy = ConservativeRefreshPolicy;
 
-    fn value(g: &GridLocation) -> Self::Value {
-        g.owner
-    }
-}
-
-#[derive(Component, Clone, Debug)]
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
 pub struct Abilities(pub Vec<Ability>);
 
 #[derive(QueryData, Debug)]
@@ -142,8 +204,9 @@ pub struct Abilities(pub Vec<Ability>);
 pub struct CardQuery {
     pub entity: Entity,
     pub match_id: &'static MatchId,
+    pub unit_id: &'static UnitId,
     pub name: &'static Name,
-    pub grid_loc: &'static GridLocation,
+    pub grid_loc: &'static mut GridLocation,
     pub abilities: &'static mut Abilities,
     pub health: &'static mut Health,
     pub energy: &'static mut Energy,
@@ -172,219 +235,191 @@ pub struct EffectEvent {
 pub struct NewTurnEvent {
     pub match_id: MatchId,
     pub next_player: PlayerId,
+    /// The state of the match once the turn has passed
+    pub checksum: Option<Checksum>,
+}
+
+#[derive(Event, Clone)]
+pub struct MatchEndEvent {
+    pub match_id: MatchId,
+    pub winner: PlayerId,
+    pub loser: PlayerId,
+    pub reason: MatchEndReason,
 }
 
 #[derive(Event, Clone)]
 pub struct CleanupMatchEvent {
-    match_id: MatchId,
+    pub match_id: MatchId,
+}
+
+/// A client's match state doesn't match the server's.
+#[derive(Event, Clone)]
+pub struct DesyncEvent {
+    pub match_id: MatchId,
+    pub expected: Checksum,
+    pub actual: Checksum,
+}
+
+/// Replaces a match's board, for clients that rejoined or fell out of sync.
+#[derive(Event, Clone)]
+pub struct SnapshotEvent {
+    pub match_id: MatchId,
+    pub snapshot: MatchSnapshot,
 }
 
 fn init_events(app: &mut App) {
     app.add_event::<StartMatchEvent>();
     app.add_event::<EffectEvent>();
     app.add_event::<NewTurnEvent>();
+    app.add_event::<MatchEndEvent>();
     app.add_event::<CleanupMatchEvent>();
+    app.add_event::<SnapshotEvent>();
+    app.add_event::<DesyncEvent>();
 }
 
 // ====== Systems ======
 
-fn start_match(mut commands: Commands, mut e: EventReader<StartMatchEvent>) {
+fn start_match(
+    mut commands: Commands,
+    mut e: EventReader<StartMatchEvent>,
+    mut matches: ResMut<Matches>,
+    markers: Query<&MatchId, With<PlayerId>>,
+) {
     for StartMatchEvent { match_id, players } in e.read() {
+        if markers.iter().any(|m| m == match_id) {
+            // rejoined a match that was never torn down
+            continue;
+        }
         info!("match {match_id:?} started");
         for player_id in players.iter() {
             let p = commands.spawn((*match_id, *player_id, Name::new("player_id_marker"))).id();
         }
+        // the server creates the state itself while matchmaking. Only the server picks random
+        // targets, so the seed doesn't matter here until a snapshot brings the real one.
+        matches
+            .0
+            .entry(*match_id)
+            .or_insert_with(|| MatchState::new(*match_id, players.clone(), 0));
+    }
+}
+
+fn restore_snapshots(
+    mut commands: Commands,
+    mut e: EventReader<SnapshotEvent>,
+    mut turns: EventWriter<NewTurnEvent>,
+    mut matches: ResMut<Matches>,
+    mut match_idx: Index<MatchId>,
+    units: Query<Entity, With<UnitId>>,
+) {
+    for SnapshotEvent { match_id, snapshot } in e.read() {
+        let Some(state) = matches.0.get_mut(match_id) else {
+            warn!("snapshot for unknown match {match_id:?}");
+            continue;
+        };
+        state.restore(snapshot.clone());
+        // rebuild every card, `sync_units` respawns them from the restored state
+        for e in units.iter_many(match_idx.lookup(match_id)) {
+            commands.entity(e).despawn_recursive();
+        }
+        if let Some(next_player) = state.current_turn {
+            turns.send(NewTurnEvent { match_id: *match_id, next_player, checksum: None });
+        }
     }
 }
 
 fn next_turn(
     mut commands: Commands,
     mut e: EventReader<NewTurnEvent>,
-    players: Query<(Entity, &MatchId, &PlayerId, Has<CurrentTurn>)>,
+    players: Query<(Entity, &MatchId, &PlayerId)>,
+    mut matches: ResMut<Matches>,
 ) {
-    for NewTurnEvent { match_id, next_player } in e.read() {
-        for (e, m, p, t) in players.iter() {
+    for NewTurnEvent { match_id, next_player, .. } in e.read() {
+        if let Some(state) = matches.0.get_mut(match_id) {
+            state.current_turn = Some(*next_player);
+        }
+        // commands apply in order, so the last turn change in a frame wins
+        for (e, m, p) in players.iter() {
             if m == match_id {
-                if t {
-                    commands.entity(e).remove::<CurrentTurn>();
-                } else if p == next_player {
+                if p == next_player {
                     commands.entity(e).insert(CurrentTurn);
+                } else {
+                    commands.entity(e).remove::<CurrentTurn>();
                 }
             }
         }
     }
 }
 
-fn client_effects(mut e: EventReader<EffectEvent>) {
-    for EffectEvent { match_id, effect, targets } in e.read() {}
+/// Compares each turn's checksum against the local state.
+///
+/// A turn can only be checked if no effects from later turns were applied in the same frame.
+fn verify_checksums(
+    mut e: EventReader<NewTurnEvent>,
+    mut desyncs: EventWriter<DesyncEvent>,
+    matches: Res<Matches>,
+) {
+    for NewTurnEvent { match_id, checksum, .. } in e.read() {
+        let (Some(expected), Some(state)) = (checksum, matches.0.get(match_id)) else { continue };
+        let actual = state.checksum();
+        if actual.effects_applied > expected.effects_applied || actual == *expected {
+            continue;
+        }
+        warn!("match {match_id:?} desynced: expected {expected:?}, found {actual:?}");
+        desyncs.send(DesyncEvent { match_id: *match_id, expected: *expected, actual });
+    }
 }
 
-fn common_effects(
-    mut commands: Commands,
-    mut e: EventReader<EffectEvent>,
-    mut cards: CardsMut,
-    mut loc_idx: Index<GridLocation>,
-) {
+fn client_effects(mut e: EventReader<EffectEvent>, mut matches: ResMut<Matches>) {
     for EffectEvent { match_id, effect, targets } in e.read() {
         debug!("effect {effect:?} with targets {targets:?}");
-        match effect {
-            Effect::SummonCard { card } => {
-                for t in targets {
-                    commands.spawn_card(card.clone(), *match_id, *t)
-                }
-            },
-            Effect::GrantAbilities { abilities } => {
-                for t in targets {
-                    cards
-                        .get_mut(loc_idx.single(t))
-                        .unwrap()
-                        .abilities
-                        .0
-                        .extend_from_slice(abilities);
-                }
-            },
-            Effect::ChangeHp { amount } => {
-                for t in targets {
-                    cards.get_mut(loc_idx.single(t)).unwrap().health.0 += amount;
-                }
-            },
-            Effect::ChangeEnergy { amount } => {
-                for t in targets {
-                    let mut e = cards.get_mut(loc_idx.single(t)).unwrap().energy;
-                    e.current = (e.current as i32 + amount).clamp(0, e.max as i32) as u32;
-                }
-            },
-            Effect::DestroyCard => {
-                // triggering abilities handled by server effects
-                for t in targets {
-                    println!("despawn {t:?}");
-                    commands.entity(loc_idx.single(t)).despawn_recursive();
-                }
-            },
-            Effect::Attack { .. } | Effect::MultipleEffects { .. } => {
-                // Handled by server_effects
-            },
-        }
+        let Some(state) = matches.0.get_mut(match_id) else {
+            warn!("effect for unknown match {match_id:?}");
+            continue;
+        };
+        state.apply_effect(effect, targets);
     }
 }
 
-// todo read from a stack instead of using event queue
-fn server_effects(
-    mut e: ResMut<Events<EffectEvent>>,
-    mut e_reader: Local<ManualEventReader<EffectEvent>>,
-    cards: Cards,
-    mut loc_idx: Index<GridLocation>,
+/// Mirrors the units of each `MatchState` into card entities.
+fn sync_units(
+    mut commands: Commands,
+    matches: Res<Matches>,
+    mut cards: CardsMut,
     mut match_idx: Index<MatchId>,
 ) {
-    let mut new_events = vec![];
-    for EffectEvent { match_id, effect, targets } in e_reader.read(&*e) {
-        match effect {
-            Effect::Attack { effect_type, damage } => {
-                for t in targets {
-                    let mut final_factor = 1.;
-                    for (ability, ability_source_loc) in cards
-                        .iter_many(match_idx.lookup(match_id))
-                        .flat_map(|card| card.abilities.0.iter().map(|a| (a, *card.grid_loc)))
-                    {
-                        if let Ability::Passive { passive_effect, target_filter } = ability {
-                            if target_filter.validate(t, &mut loc_idx, &cards, &ability_source_loc)
-                            {
-                                match passive_effect {
-                                    PassiveEffect::DamageResistance {
-                                        effect_type: effect_filter,
-                                        factor,
-                                    } => {
-                                        if *effect_type == *effect_filter {
-                                            final_factor *= factor;
-                                        }
-                                    },
-                                    PassiveEffect::WhenHit { effect, target_rules } => {
-                                        let target = match target_rules {
-                                            ImplicitTargetRules::ThisUnit => ability_source_loc,
-                                            ImplicitTargetRules::ThatUnit => *t,
-                                        };
-
-                                        new_events.push(EffectEvent {
-                                            match_id: *match_id,
-                                            effect: effect.clone(),
-                                            targets: vec![target],
-                                        })
-                                    },
-                                    PassiveEffect::WhenDies { .. } => {},
-                                }
-                            }
-                        }
+    for (match_id, state) in matches.0.iter() {
+        // units can move, so cards are matched up by id rather than by location
+        let locs = state.units().map(|(loc, unit)| (unit.id, loc)).collect::<HashMap<_, _>>();
+        let mut synced = HashSet::new();
+        let mut match_cards = cards.iter_many_mut(match_idx.lookup(match_id));
+        while let Some(mut card) = match_cards.fetch_next() {
+            match locs.get(card.unit_id).map(|loc| (*loc, &state.units[loc])) {
+                // a duplicate entity for an already synced unit is stale as well
+                Some((loc, unit)) if synced.insert(unit.id) => {
+                    // only touched when it moved, so `Changed<GridLocation>` means a move
+                    if *card.grid_loc != loc {
+                        *card.grid_loc = loc;
                     }
+                    *card.health = unit.health.clone();
+                    *card.energy = unit.energy.clone();
+                    *card.abilities = unit.abilities.clone();
+                },
+                _ => commands.entity(card.entity).despawn_recursive(),
+            }
+        }
 
-                    let final_dmg = *damage as f32 * final_factor;
-
-                    new_events.push(EffectEvent {
-                        match_id: *match_id,
-                        effect: Effect::ChangeHp { amount: -(final_dmg as i32) },
-                        targets: vec![*t],
-                    });
-                }
-            },
-            Effect::MultipleEffects { effects } => {
-                for e in effects {
-                    new_events.push(EffectEvent {
-                        match_id: *match_id,
-                        effect: e.clone(),
-                        targets: targets.clone(),
-                    })
-                }
-            },
-            Effect::DestroyCard => {
-                // despawning handled by common effects
-                for t in targets {
-                    for (ability, ability_source_loc) in cards
-                        .iter_many(match_idx.lookup(match_id))
-                        .flat_map(|card| card.abilities.0.iter().map(|a| (a, *card.grid_loc)))
-                    {
-                        if let Ability::Passive { passive_effect, target_filter } = ability {
-                            if target_filter.validate(t, &mut loc_idx, &cards, &ability_source_loc)
-                            {
-                                if let PassiveEffect::WhenDies { effect, target_rules } =
-                                    passive_effect
-                                {
-                                    let target = match target_rules {
-                                        ImplicitTargetRules::ThisUnit => ability_source_loc,
-                                        ImplicitTargetRules::ThatUnit => *t,
-                                    };
-
-                                    new_events.push(EffectEvent {
-                                        match_id: *match_id,
-                                        effect: effect.clone(),
-                                        targets: vec![target],
-                                    })
-                                }
-                            }
-                        }
-                    }
-                }
-            },
-            Effect::SummonCard { .. }
-            | Effect::GrantAbilities { .. }
-            | Effect::ChangeHp { .. }
-            | Effect::ChangeEnergy { .. } => {
-                // Handled by common_effects
-            },
+        for (loc, unit) in state.units() {
+            if !synced.contains(&unit.id) {
+                commands.spawn_unit(unit, *match_id, loc);
+            }
         }
     }
-    for ev in new_events.into_iter() {
-        e.send(ev);
-    }
 }
 
-fn server_state_based(mut e: EventWriter<EffectEvent>, cards: Cards) {
-    for card in &cards {
-        if card.health.0 <= 0 {
-            e.send(EffectEvent {
-                match_id: *card.match_id,
-                effect: Effect::DestroyCard,
-                targets: vec![*card.grid_loc],
-            });
-        }
+fn end_match(mut e: EventReader<MatchEndEvent>, mut cleanup: EventWriter<CleanupMatchEvent>) {
+    for MatchEndEvent { match_id, winner, loser, reason } in e.read() {
+        info!("match {match_id:?} ended: {winner:?} beat {loser:?} ({reason:?})");
+        cleanup.send(CleanupMatchEvent { match_id: *match_id });
     }
 }
 
@@ -392,10 +427,14 @@ fn cleanup_match(
     mut commands: Commands,
     mut e: EventReader<CleanupMatchEvent>,
     mut match_index: Index<MatchId>,
+    mut matches: ResMut<Matches>,
 ) {
     for CleanupMatchEvent { match_id } in e.read() {
+        matches.0.remove(match_id);
         for entity in match_index.lookup(match_id) {
-            commands.entity(entity).despawn();
+            if let Some(entity) = commands.get_entity(entity) {
+                entity.despawn_recursive();
+            }
         }
     }
 }
@@ -404,14 +443,15 @@ fn cleanup_match(
 
 #[extension_trait]
 impl CommandExts for Commands<'_, '_> {
-    fn spawn_card(&mut self, card: Card, mid: MatchId, loc: GridLocation) {
+    fn spawn_unit(&mut self, unit: &Unit, mid: MatchId, loc: GridLocation) {
         let card = self.spawn((
             mid,
-            Name::new(card.name.to_string()),
-            Health(card.hp as i32),
-            Energy { current: card.starting_energy, max: card.max_energy },
-            Abilities(card.abilities.clone()),
-            BaseCard(card),
+            unit.id,
+            Name::new(unit.card.name.to_string()),
+            unit.health.clone(),
+            unit.energy.clone(),
+            unit.abilities.clone(),
+            BaseCard(unit.card.clone()),
             (loc, SpatialBundle::default()),
             NeedsMesh,
         ));
@@ -0,0 +1,158 @@
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cards::Card,
+    match_sim::{
+        engine::{MatchOutcome, MatchState},
+        EffectEvent, MatchId, PlayerId,
+    },
+    network::messages::{
+        ActivateAbilityMessage, EffectMessage, MatchStartedMessage, NetworkMessage, NewTurnMessage,
+    },
+};
+
+/// Bumped whenever a change to the format or the rules would make older replays play back wrong.
+pub const REPLAY_VERSION: u32 = 2;
+pub const REPLAY_DIR: &str = "replays";
+const REPLAY_EXTENSION: &str = "replay";
+
+/// Everything needed to play back a match on a client without a server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u32,
+    pub match_id: MatchId,
+    pub players: Vec<PlayerId>,
+    pub decks: Vec<(PlayerId, Card)>,
+    pub first_player: PlayerId,
+    /// Seed of the recorded [`MatchState`]
+    pub seed: u64,
+    pub turns: Vec<ReplayTurn>,
+    pub outcome: Option<MatchOutcome>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayTurn {
+    pub player: PlayerId,
+    pub action: ActivateAbilityMessage,
+    pub effects: Vec<EffectMessage>,
+    /// `None` if the action ended the match
+    pub next_player: Option<PlayerId>,
+}
+
+impl Replay {
+    pub fn new(
+        match_id: MatchId,
+        decks: Vec<(PlayerId, Card)>,
+        first_player: PlayerId,
+        seed: u64,
+    ) -> Self {
+        Self {
+            version: REPLAY_VERSION,
+            match_id,
+            players: decks.iter().map(|(pid, _)| *pid).collect(),
+            decks,
+            first_player,
+            seed,
+            turns: vec![],
+            outcome: None,
+        }
+    }
+
+    pub fn record_turn(
+        &mut self,
+        player: PlayerId,
+        action: ActivateAbilityMessage,
+        effects: &[EffectEvent],
+        next_player: Option<PlayerId>,
+    ) {
+        let effects = effects
+            .iter()
+            .map(|EffectEvent { match_id, effect, targets }| EffectMessage {
+                match_id: *match_id,
+                effect: effect.clone(),
+                targets: targets.clone(),
+            })
+            .collect();
+        self.turns.push(ReplayTurn { player, action, effects, next_player });
+    }
+
+    pub fn save(&self) -> Result<PathBuf, Box<dyn Error>> {
+        std::fs::create_dir_all(REPLAY_DIR)?;
+        let started = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_secs();
+        let path = Path::new(REPLAY_DIR)
+            .join(format!("{started}_{}", self.match_id))
+            .with_extension(REPLAY_EXTENSION);
+        serde_json::to_writer(std::fs::File::create(&path)?, self)?;
+        Ok(path)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let replay: Replay = serde_json::from_reader(std::fs::File::open(path)?)?;
+        if replay.version != REPLAY_VERSION {
+            return Err(format!(
+                "Replay version {} is not supported (expected {REPLAY_VERSION})",
+                replay.version
+            )
+            .into());
+        }
+        Ok(replay)
+    }
+
+    /// The messages a client in the match received, grouped by turn and seen from `you`'s side of
+    /// the board. The first step starts the match.
+    pub fn steps(&self, you: PlayerId) -> Vec<Vec<NetworkMessage>> {
+        let match_id = self.match_id;
+        let mut state = MatchState::new(match_id, self.players.clone(), self.seed);
+
+        let mut start = vec![MatchStartedMessage {
+            match_id,
+            players: self.players.clone(),
+            you,
+            session_token: None,
+        }
+        .into()];
+        for EffectEvent { effect, targets, .. } in
+            state.start(self.decks.clone(), self.first_player)
+        {
+            start.push(EffectMessage { match_id, effect, targets }.into());
+        }
+        let checksum = state.checksum();
+        start.push(NewTurnMessage { match_id, next_player: self.first_player, checksum }.into());
+
+        // the effects are applied to `state` as well, to work out each turn's checksum
+        let mut steps = vec![start];
+        for turn in &self.turns {
+            let mut step = vec![];
+            for effect in &turn.effects {
+                state.apply_effect(&effect.effect, &effect.targets);
+                step.push(effect.clone().into());
+            }
+            if let Some(next_player) = turn.next_player {
+                state.current_turn = Some(next_player);
+                let checksum = state.checksum();
+                step.push(NewTurnMessage { match_id, next_player, checksum }.into());
+            }
+            steps.push(step);
+        }
+        steps
+    }
+}
+
+/// Saved replays, most recent first.
+pub fn list_replays() -> Vec<PathBuf> {
+    let Ok(dir) = std::fs::read_dir(REPLAY_DIR) else { return vec![] };
+    let mut replays = dir
+        .filter_map(|file| file.ok().map(|f| f.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == REPLAY_EXTENSION))
+        .collect::<Vec<_>>();
+    // file names start with the time the replay was saved
+    replays.sort();
+    replays.reverse();
+    replays
+}
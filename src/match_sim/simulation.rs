@@ -0,0 +1,102 @@
+use bevy::utils::HashMap;
+
+use crate::{
+    cards::{Card, Effect},
+    match_sim::{ai, engine::MatchState, GridLocation, MatchId, PlayerId},
+};
+
+/// Matches still running after this many turns are counted as draws.
+pub const MAX_TURNS: usize = 400;
+
+#[derive(Debug, Clone, Default)]
+pub struct AbilityStats {
+    pub uses: u32,
+    pub damage: u32,
+}
+
+/// Where a card is in its deck: the index of the ability summoning it on the deck, then on each
+/// card summoned on the way to it. Empty for the deck itself. Unlike names, these are never shared
+/// by two different cards.
+pub type CardPath = Vec<usize>;
+
+#[derive(Debug, Clone, Default)]
+pub struct DeckStats {
+    /// Keyed by the card and the index of the ability on it
+    pub abilities: HashMap<(CardPath, usize), AbilityStats>,
+    /// Number of times each card was summoned. Units summoned by anything but an activated ability
+    /// count as summoned by the ability that set it off.
+    pub summons: HashMap<CardPath, u32>,
+}
+impl DeckStats {
+    /// Adds `other`'s counts to these, for totals over many matches.
+    pub fn merge(&mut self, other: DeckStats) {
+        for (key, stats) in other.abilities {
+            let total = self.abilities.entry(key).or_default();
+            total.uses += stats.uses;
+            total.damage += stats.damage;
+        }
+        for (card, n) in other.summons {
+            *self.summons.entry(card).or_default() += n;
+        }
+    }
+}
+
+/// What happened in a single bot-vs-bot match.
+#[derive(Debug, Clone, Default)]
+pub struct SimulatedMatch {
+    /// Index into the decks the match was played with, `None` for a draw
+    pub winner: Option<usize>,
+    pub turns: usize,
+    /// Indexed the same as the decks
+    pub stats: [DeckStats; 2],
+}
+
+/// Plays `decks[0]` against `decks[1]` with [`ai::choose_action`] on both sides, without any
+/// rendering or networking.
+pub fn simulate_match(decks: [&Card; 2], first_player: usize) -> SimulatedMatch {
+    let players = [PlayerId::new(), PlayerId::new()];
+    let mut state = MatchState::new(MatchId::new(), players.to_vec(), rand::random());
+    let decks = players.iter().copied().zip(decks.iter().map(|deck| (*deck).clone())).collect();
+    state.start(decks, players[first_player]);
+    // only the command units are on the board so far
+    let mut paths = state.units.values().map(|unit| (unit.id, vec![])).collect::<HashMap<_, _>>();
+
+    let mut result = SimulatedMatch::default();
+    while state.outcome.is_none() && result.turns < MAX_TURNS {
+        let Some(player) = state.current_turn else { break };
+        let seat = players.iter().position(|p| *p == player).unwrap();
+        let Some(action) = ai::choose_action(&state, player) else { break };
+
+        let source = GridLocation { owner: player, coord: action.unit_location };
+        let Some(path) = state.units.get(&source).and_then(|unit| paths.get(&unit.id)).cloned()
+        else {
+            break;
+        };
+        let Ok(resolved) = state.apply_action(player, &action) else { break };
+
+        let stats =
+            result.stats[seat].abilities.entry((path.clone(), action.ability_idx)).or_default();
+        stats.uses += 1;
+        for event in &resolved {
+            match &event.effect {
+                Effect::ChangeHp { amount } if *amount < 0 => {
+                    stats.damage += (-amount) as u32 * event.targets.len() as u32;
+                },
+                Effect::SummonCard { .. } => {
+                    let mut summoned = path.clone();
+                    summoned.push(action.ability_idx);
+                    for unit in event.targets.iter().filter_map(|t| state.units.get(t)) {
+                        paths.insert(unit.id, summoned.clone());
+                    }
+                    *result.stats[seat].summons.entry(summoned).or_default() += 1;
+                },
+                _ => {},
+            }
+        }
+        result.turns += 1;
+    }
+
+    result.winner =
+        state.outcome.and_then(|outcome| players.iter().position(|p| *p == outcome.winner));
+    result
+}
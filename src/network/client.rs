@@ -3,8 +3,9 @@ use std::{
     time::SystemTime,
 };
 
-use bevy::{log, prelude::*};
+use bevy::{ecs::system::SystemParam, log, prelude::*, utils::HashMap};
 use bevy_renet::{
+    client_just_connected,
     renet::{
         transport::{ClientAuthentication, NetcodeClientTransport},
         ConnectionConfig, DefaultChannel, RenetClient,
@@ -16,24 +17,36 @@ use extension_trait::extension_trait;
 use serde::Deserialize;
 
 use crate::{
-    match_sim::{EffectEvent, NewTurnEvent, StartMatchEvent, Us},
+    match_sim::{
+        draft::DraftPack, DesyncEvent, EffectEvent, MatchEndEvent, MatchEndReason, MatchId,
+        MatchPaused, Matches, NewTurnEvent, SnapshotEvent, StartMatchEvent, Us,
+    },
     network::{
-        messages::{EffectMessage, NetworkMessage, NewTurnMessage, ProtocolErrorMessage},
-        PORT,
+        messages::{
+            BoardSnapshotMessage, DraftPackMessage, EffectMessage, MatchEndedMessage,
+            MatchPausedMessage, MatchResumedMessage, MatchStartedMessage, NetworkMessage,
+            NewTurnMessage, ProfileMessage, ProtocolErrorMessage, RejoinMatchMessage,
+            RequestProfileMessage, RequestSnapshotMessage, SessionToken, SetPlayerNameMessage,
+        },
+        PORT, RECONNECT_GRACE_PERIOD,
     },
 };
 
 #[derive(Resource, Deserialize)]
 pub struct ClientConfig {
     pub server_ip: IpAddr,
+    /// Also identifies the player's profile on the server. Nothing stops another client from
+    /// using the same id, see [`PlayerProfile`](super::profile::PlayerProfile).
     pub client_id: u64,
+    /// Renames the profile when connecting
+    #[serde(default)]
+    pub player_name: Option<String>,
 }
 
 pub struct ClientPlugin;
 impl Plugin for ClientPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins((RenetClientPlugin, NetcodeClientPlugin));
-        app.insert_resource(RenetClient::new(ConnectionConfig::default()));
 
         let config_path = std::env::args().nth(1).expect("Must provide config path!");
         let config: ClientConfig = serde_json::from_reader(
@@ -41,23 +54,41 @@ impl Plugin for ClientPlugin {
         )
         .expect("invalid config");
 
-        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
-        let authentication = ClientAuthentication::Unsecure {
-            server_addr: SocketAddr::new(config.server_ip, PORT),
-            client_id: config.client_id,
-            user_data: None,
-            protocol_id: 0,
-        };
-        let current_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
-        let transport = NetcodeClientTransport::new(current_time, authentication, socket).unwrap();
-
+        let (client, transport) = connect(&config);
+        app.insert_resource(client);
         app.insert_resource(config);
         app.insert_resource(transport);
 
         app.add_systems(First, read_messages);
+        app.init_resource::<PendingDesyncs>();
+        app.add_systems(PreUpdate, reconnect.run_if(resource_exists::<MatchSession>));
+        app.add_systems(PreUpdate, report_desyncs);
+        app.add_systems(PreUpdate, request_profile.run_if(client_just_connected));
     }
 }
 
+fn connect(config: &ClientConfig) -> (RenetClient, NetcodeClientTransport) {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+    let authentication = ClientAuthentication::Unsecure {
+        server_addr: SocketAddr::new(config.server_ip, PORT),
+        client_id: config.client_id,
+        user_data: None,
+        protocol_id: 0,
+    };
+    let current_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+    let transport = NetcodeClientTransport::new(current_time, authentication, socket).unwrap();
+    (RenetClient::new(ConnectionConfig::default()), transport)
+}
+
+/// The match this client is playing, kept so it can be rejoined after a disconnect.
+#[derive(Resource)]
+struct MatchSession {
+    match_id: MatchId,
+    token: SessionToken,
+    /// Runs while disconnected, the server will have forfeited the match when it finishes
+    disconnected: Option<Timer>,
+}
+
 #[extension_trait]
 pub impl ClientExt for RenetClient {
     fn next_msg(&mut self) -> Option<NetworkMessage> {
@@ -84,32 +115,186 @@ pub impl ClientExt for RenetClient {
     }
 }
 
-fn read_messages(
-    mut client: ResMut<RenetClient>,
-    mut start_match: EventWriter<StartMatchEvent>,
-    mut effects: EventWriter<EffectEvent>,
-    mut turns: EventWriter<NewTurnEvent>,
-    mut commands: Commands,
-) {
-    while let Some(msg) = client.next_msg() {
+/// Applies messages from the server to the local matches.
+#[derive(SystemParam)]
+pub struct MessageHandler<'w, 's> {
+    start_match: EventWriter<'w, StartMatchEvent>,
+    effects: EventWriter<'w, EffectEvent>,
+    turns: EventWriter<'w, NewTurnEvent>,
+    match_end: EventWriter<'w, MatchEndEvent>,
+    snapshots: EventWriter<'w, SnapshotEvent>,
+    commands: Commands<'w, 's>,
+    time: Res<'w, Time>,
+}
+impl MessageHandler<'_, '_> {
+    /// Hands back any message a client doesn't expect to receive.
+    pub fn handle(&mut self, msg: NetworkMessage) -> Result<(), NetworkMessage> {
         match msg {
-            NetworkMessage::MatchStartedMessage(data) => {
-                commands.insert_resource(Us(data.you));
-                start_match
-                    .send(StartMatchEvent { match_id: data.match_id, players: data.players });
+            NetworkMessage::MatchStartedMessage(MatchStartedMessage {
+                match_id,
+                players,
+                you,
+                session_token,
+            }) => {
+                self.commands.insert_resource(Us(you));
+                self.commands.remove_resource::<DraftPack>();
+                if let Some(token) = session_token {
+                    self.commands.insert_resource(MatchSession {
+                        match_id,
+                        token,
+                        disconnected: None,
+                    });
+                }
+                self.start_match.send(StartMatchEvent { match_id, players });
+            },
+            NetworkMessage::DraftPackMessage(DraftPackMessage {
+                match_id,
+                cards,
+                your_pick,
+                picks,
+            }) => {
+                self.commands.insert_resource(DraftPack { match_id, cards, your_pick, picks });
             },
             NetworkMessage::EffectMessage(EffectMessage { match_id, effect, targets }) => {
-                effects.send(EffectEvent { match_id, effect, targets });
+                self.effects.send(EffectEvent { match_id, effect, targets });
             },
-            NetworkMessage::NewTurnMessage(NewTurnMessage { match_id, next_player }) => {
-                turns.send(NewTurnEvent { match_id, next_player });
+            NetworkMessage::NewTurnMessage(NewTurnMessage { match_id, next_player, checksum }) => {
+                self.turns.send(NewTurnEvent { match_id, next_player, checksum: Some(checksum) });
+            },
+            NetworkMessage::MatchEndedMessage(MatchEndedMessage {
+                match_id,
+                winner,
+                loser,
+                reason,
+            }) => {
+                self.commands.remove_resource::<MatchSession>();
+                self.commands.remove_resource::<MatchPaused>();
+                self.match_end.send(MatchEndEvent { match_id, winner, loser, reason });
+            },
+            NetworkMessage::BoardSnapshotMessage(BoardSnapshotMessage { match_id, snapshot }) => {
+                self.snapshots.send(SnapshotEvent { match_id, snapshot });
+            },
+            NetworkMessage::MatchPausedMessage(MatchPausedMessage {
+                player, grace_period, ..
+            }) => {
+                self.commands.insert_resource(MatchPaused {
+                    player,
+                    forfeit_at: self.time.elapsed_seconds() + grace_period as f32,
+                });
+            },
+            NetworkMessage::MatchResumedMessage(MatchResumedMessage { .. }) => {
+                self.commands.remove_resource::<MatchPaused>();
+            },
+            NetworkMessage::ProfileMessage(ProfileMessage { profile }) => {
+                self.commands.insert_resource(profile);
             },
             NetworkMessage::ProtocolErrorMessage(ProtocolErrorMessage { msg }) => {
                 log::error!("ProtocolError from server: {msg}")
             },
-            other => {
-                client.send_error(format!("Unhandleable NetworkMessage: {other:?}"));
-            },
+            other => return Err(other),
         }
+        Ok(())
+    }
+}
+
+fn read_messages(mut client: ResMut<RenetClient>, mut handler: MessageHandler) {
+    while let Some(msg) = client.next_msg() {
+        if let Err(other) = handler.handle(msg) {
+            client.send_error(format!("Unhandleable NetworkMessage: {other:?}"));
+        }
+    }
+}
+
+fn request_profile(mut client: ResMut<RenetClient>, config: Res<ClientConfig>) {
+    match &config.player_name {
+        Some(name) => client.send(SetPlayerNameMessage { name: name.clone() }),
+        None => client.send(RequestProfileMessage),
+    }
+}
+
+/// Reconnects to the server and rejoins the current match after losing connection.
+fn reconnect(
+    mut commands: Commands,
+    mut client: ResMut<RenetClient>,
+    mut session: ResMut<MatchSession>,
+    config: Res<ClientConfig>,
+    time: Res<Time>,
+    matches: Res<Matches>,
+    us: Res<Us>,
+    mut match_end: EventWriter<MatchEndEvent>,
+) {
+    if client.is_connected() {
+        if session.disconnected.take().is_some() {
+            info!("Reconnected, rejoining match {}", session.match_id);
+            client.send(RejoinMatchMessage {
+                match_id: session.match_id,
+                session_token: session.token,
+            });
+        }
+        return;
+    }
+
+    let match_id = session.match_id;
+    let grace_period = session
+        .disconnected
+        .get_or_insert_with(|| Timer::new(RECONNECT_GRACE_PERIOD, TimerMode::Once));
+    if grace_period.tick(time.delta()).finished() {
+        log::warn!("Could not reconnect to match {match_id} in time");
+        commands.remove_resource::<MatchSession>();
+        commands.remove_resource::<MatchPaused>();
+        let Some(winner) = matches.0.get(&match_id).and_then(|state| state.opponent(us.0)) else {
+            return;
+        };
+        match_end.send(MatchEndEvent {
+            match_id,
+            winner,
+            loser: us.0,
+            reason: MatchEndReason::Disconnected,
+        });
+        return;
+    }
+
+    // a fresh transport stays in the connecting state until it connects or times out
+    if client.is_disconnected() {
+        let (client, transport) = connect(&config);
+        commands.insert_resource(client);
+        commands.insert_resource(transport);
+    }
+}
+
+/// Matches that failed a checksum, waiting on a snapshot to report what was different.
+#[derive(Resource, Default)]
+struct PendingDesyncs(HashMap<MatchId, DesyncEvent>);
+
+/// Resyncs matches that failed a checksum and reports the difference to the server.
+///
+/// Runs between receiving a snapshot and restoring it, so the old state can still be compared.
+fn report_desyncs(
+    mut desyncs: EventReader<DesyncEvent>,
+    mut snapshots: EventReader<SnapshotEvent>,
+    mut pending: ResMut<PendingDesyncs>,
+    matches: Res<Matches>,
+    mut client: ResMut<RenetClient>,
+) {
+    for desync in desyncs.read() {
+        if pending.0.insert(desync.match_id, desync.clone()).is_none() {
+            client.send(RequestSnapshotMessage { match_id: desync.match_id });
+        }
+    }
+
+    for SnapshotEvent { match_id, snapshot } in snapshots.read() {
+        let Some(DesyncEvent { expected, actual, .. }) = pending.0.remove(match_id) else {
+            continue;
+        };
+        let Some(state) = matches.0.get(match_id) else { continue };
+        client.send_error(format!(
+            "Checksum mismatch in match {match_id}: expected {:016x} after {} effects, found \
+             {:016x} after {} (client vs server):\n{}",
+            expected.hash,
+            expected.effects_applied,
+            actual.hash,
+            actual.effects_applied,
+            state.diff(snapshot).join("\n"),
+        ));
     }
 }
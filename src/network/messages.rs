@@ -4,56 +4,193 @@ use serde::{Deserialize, Serialize};
 use crate::{
     cards::{Card, Effect},
     make_enum,
-    match_sim::{GridLocation, MatchId, PlayerId},
+    match_sim::{
+        engine::{Checksum, MatchSnapshot},
+        GridLocation, MatchEndReason, MatchId, PlayerId,
+    },
+    network::profile::PlayerProfile,
 };
 
+/// Proves which player a client is when it rejoins a match after disconnecting.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct SessionToken(u64);
+impl SessionToken {
+    pub fn new() -> Self {
+        Self(rand::random())
+    }
+}
+
 make_enum! {
-    #[derive(Debug, Serialize, Deserialize)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
     pub enum NetworkMessage {
         JoinMatchmakingQueueMessage,
+        JoinDraftMessage,
+        DraftPackMessage,
+        DraftPickMessage,
         MatchStartedMessage,
         EffectMessage,
         NewTurnMessage,
         ActivateAbilityMessage,
+        ConcedeMessage,
+        MatchEndedMessage,
+        RejoinMatchMessage,
+        RequestSnapshotMessage,
+        BoardSnapshotMessage,
+        MatchPausedMessage,
+        MatchResumedMessage,
+        RequestProfileMessage,
+        ProfileMessage,
+        SetPlayerNameMessage,
+        SaveDeckMessage,
+        DeleteDeckMessage,
         ProtocolErrorMessage,
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JoinMatchmakingQueueMessage {
     pub player_name: String,
     pub deck: Card,
+    /// Play against a server controlled opponent instead of waiting for another player
+    pub vs_bot: bool,
+}
+
+/// Queues for a match where both players draft their decks first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JoinDraftMessage {
+    pub player_name: String,
+    pub vs_bot: bool,
+}
+
+/// The pack being drafted from, sent to both players after every pick.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DraftPackMessage {
+    /// The match the draft turns into
+    pub match_id: MatchId,
+    pub cards: Vec<Card>,
+    pub your_pick: bool,
+    /// Cards you have drafted so far
+    pub picks: Vec<Card>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DraftPickMessage {
+    pub match_id: MatchId,
+    pub card_idx: usize,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MatchStartedMessage {
     pub match_id: MatchId,
     pub players: Vec<PlayerId>,
     pub you: PlayerId,
+    /// `None` when played back from a replay
+    pub session_token: Option<SessionToken>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EffectMessage {
     pub match_id: MatchId,
     pub effect: Effect,
     pub targets: Vec<GridLocation>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NewTurnMessage {
     pub match_id: MatchId,
     pub next_player: PlayerId,
+    pub checksum: Checksum,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ActivateAbilityMessage {
     pub match_id: MatchId,
     pub unit_location: UVec2,
     pub ability_idx: usize,
     pub targets: Vec<GridLocation>,
+    /// Targets for each of the ability's later steps, see
+    /// [`TargetRules::then`](crate::cards::TargetRules::then)
+    #[serde(default)]
+    pub then_targets: Vec<Vec<GridLocation>>,
+    /// Where each target of every step goes, in the same order, for abilities that move units
+    #[serde(default)]
+    pub destinations: Vec<GridLocation>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConcedeMessage {
+    pub match_id: MatchId,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchEndedMessage {
+    pub match_id: MatchId,
+    pub winner: PlayerId,
+    pub loser: PlayerId,
+    pub reason: MatchEndReason,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RejoinMatchMessage {
+    pub match_id: MatchId,
+    pub session_token: SessionToken,
+}
+
+/// Asks the server for a [`BoardSnapshotMessage`] to resync a match.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestSnapshotMessage {
+    pub match_id: MatchId,
+}
+
+/// The full board of a match, which replaces whatever the client had.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BoardSnapshotMessage {
+    pub match_id: MatchId,
+    pub snapshot: MatchSnapshot,
+}
+
+/// A player disconnected, no actions are accepted until they rejoin.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchPausedMessage {
+    pub match_id: MatchId,
+    pub player: PlayerId,
+    /// Seconds left before the player forfeits
+    pub grace_period: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchResumedMessage {
+    pub match_id: MatchId,
+}
+
+/// Asks the server for a [`ProfileMessage`] with this client's profile.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestProfileMessage;
+
+/// Sent in reply to a profile request or change, and after every match.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileMessage {
+    pub profile: PlayerProfile,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetPlayerNameMessage {
+    pub name: String,
+}
+
+/// Saves a deck to the profile, replacing any saved deck with the same name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SaveDeckMessage {
+    pub name: String,
+    pub deck: Card,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteDeckMessage {
+    pub name: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProtocolErrorMessage {
     pub(crate) msg: String,
 }
@@ -1,12 +1,18 @@
+use std::time::Duration;
+
 mod client;
 pub mod messages;
+pub mod profile;
 mod server;
 
-pub use client::{ClientConfig, ClientExt, ClientPlugin};
+pub use client::{ClientConfig, ClientExt, ClientPlugin, MessageHandler};
 pub use server::{ServerExt, ServerPlugin};
 
 pub const PORT: u16 = 17922;
 
+/// How long a disconnected player has to rejoin before forfeiting their match.
+pub const RECONNECT_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
 pub struct NwDebugPlugin;
 impl bevy::app::Plugin for NwDebugPlugin {
     fn build(&self, app: &mut bevy::app::App) {
@@ -0,0 +1,203 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use bevy::{prelude::Resource, utils::HashMap};
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cards::{deck::random_summonable_card, Ability, Card, Effect},
+    match_sim::{MatchEndReason, MatchId},
+};
+
+pub const PROFILE_DIR: &str = "profiles";
+const PROFILE_EXTENSION: &str = "json";
+
+/// Cards a new player owns
+const STARTER_CARDS: usize = 10;
+pub const STARTING_RATING: i32 = 1000;
+/// Most rating a single match can win or lose
+const RATING_K: f32 = 32.;
+/// Matches kept in a profile's history
+const MAX_HISTORY: usize = 50;
+/// Longest player or deck name
+pub const MAX_NAME_LEN: usize = 24;
+
+/// Everything the server remembers about a player between sessions, keyed by the `client_id` they
+/// connect with.
+///
+/// The server uses unsecure authentication, so a `client_id` is whatever the client puts in its
+/// config. Anyone who connects with the same id gets the same profile. Don't rely on profiles for
+/// anything that needs to belong to one player until connections are authenticated.
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
+pub struct PlayerProfile {
+    pub client_id: u64,
+    pub name: String,
+    /// Cards that can be put in saved decks. For now that is just the starter cards, nothing adds to
+    /// the collection yet.
+    pub cards: Vec<Card>,
+    /// Command centers by deck name, like [`Deck::deck`](crate::cards::deck::Deck::deck)
+    pub decks: BTreeMap<String, Card>,
+    /// Most recent last
+    pub history: Vec<MatchRecord>,
+    pub rating: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchRecord {
+    pub match_id: MatchId,
+    /// Seconds since the unix epoch
+    pub ended_at: u64,
+    /// `None` for a bot
+    pub opponent: Option<String>,
+    pub won: bool,
+    pub reason: MatchEndReason,
+    pub rating_change: i32,
+}
+
+impl PlayerProfile {
+    /// A fresh profile with a starter collection that is the same every time for `client_id`.
+    pub fn new(client_id: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(client_id);
+        Self {
+            client_id,
+            name: format!("player{client_id}"),
+            cards: (0..STARTER_CARDS).map(|_| random_summonable_card(&mut rng)).collect(),
+            decks: BTreeMap::new(),
+            history: vec![],
+            rating: STARTING_RATING,
+        }
+    }
+
+    pub fn wins(&self) -> usize {
+        self.history.iter().filter(|record| record.won).count()
+    }
+
+    pub fn losses(&self) -> usize {
+        self.history.len() - self.wins()
+    }
+
+    pub fn set_name(&mut self, name: &str) -> Result<(), String> {
+        self.name = check_name(name)?;
+        Ok(())
+    }
+
+    /// Saves `deck`, replacing any deck with the same name. Every card it summons must be owned.
+    pub fn save_deck(&mut self, name: &str, deck: Card) -> Result<(), String> {
+        let name = check_name(name)?;
+        let unowned = self.unowned_cards(&deck);
+        if !unowned.is_empty() {
+            return Err(format!("Cards not in your collection: {}", unowned.join(", ")));
+        }
+
+        self.decks.insert(name, deck);
+        Ok(())
+    }
+
+    pub fn delete_deck(&mut self, name: &str) -> Result<(), String> {
+        match self.decks.remove(name) {
+            Some(_) => Ok(()),
+            None => Err(format!("No saved deck named {name}.")),
+        }
+    }
+
+    /// Whether every card `deck` summons is in the collection.
+    pub fn owns_cards(&self, deck: &Card) -> bool {
+        self.unowned_cards(deck).is_empty()
+    }
+
+    fn unowned_cards<'a>(&self, deck: &'a Card) -> Vec<&'a str> {
+        deck.abilities
+            .iter()
+            .filter_map(|ability| match ability {
+                Ability::Activated { effect: Effect::SummonCard { card }, .. } => Some(card),
+                _ => None,
+            })
+            .filter(|card| !self.cards.iter().any(|owned| owned.name == card.name))
+            .map(|card| card.name.as_str())
+            .collect()
+    }
+
+    pub fn record_match(&mut self, record: MatchRecord) {
+        self.rating += record.rating_change;
+        self.history.push(record);
+        if self.history.len() > MAX_HISTORY {
+            self.history.drain(..self.history.len() - MAX_HISTORY);
+        }
+    }
+
+    fn path(client_id: u64) -> PathBuf {
+        Path::new(PROFILE_DIR).join(client_id.to_string()).with_extension(PROFILE_EXTENSION)
+    }
+
+    /// Reads the saved profile for `client_id`, or makes a new one if there is none.
+    pub fn load(client_id: u64) -> Result<Self, Box<dyn Error>> {
+        let path = Self::path(client_id);
+        if !path.exists() {
+            return Ok(Self::new(client_id));
+        }
+        let profile: PlayerProfile = serde_json::from_reader(std::fs::File::open(path)?)?;
+        if profile.client_id != client_id {
+            return Err(format!("Profile for {client_id} belongs to {}", profile.client_id).into());
+        }
+        Ok(profile)
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        std::fs::create_dir_all(PROFILE_DIR)?;
+        serde_json::to_writer(std::fs::File::create(Self::path(self.client_id))?, self)?;
+        Ok(())
+    }
+}
+
+impl MatchRecord {
+    pub fn new(
+        match_id: MatchId,
+        opponent: Option<String>,
+        won: bool,
+        reason: MatchEndReason,
+        rating_change: i32,
+    ) -> Self {
+        let ended_at = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Self { match_id, ended_at, opponent, won, reason, rating_change }
+    }
+}
+
+fn check_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        return Err(format!("Names must be 1 to {MAX_NAME_LEN} characters."));
+    }
+    Ok(name.to_string())
+}
+
+/// Elo change for a player rated `rating` after playing someone rated `opponent`.
+pub fn rating_change(rating: i32, opponent: i32, won: bool) -> i32 {
+    let expected = 1. / (1. + 10f32.powf((opponent - rating) as f32 / 400.));
+    let score = if won { 1. } else { 0. };
+    (RATING_K * (score - expected)).round() as i32
+}
+
+/// Profiles of connected players, loaded from [`PROFILE_DIR`] the first time they are needed.
+#[derive(Resource, Default)]
+pub struct Profiles(HashMap<u64, PlayerProfile>);
+impl Profiles {
+    pub fn get(&mut self, client_id: u64) -> Result<&mut PlayerProfile, Box<dyn Error>> {
+        if !self.0.contains_key(&client_id) {
+            self.0.insert(client_id, PlayerProfile::load(client_id)?);
+        }
+        Ok(self.0.get_mut(&client_id).unwrap())
+    }
+
+    /// Drops a profile from memory, it is read from disk again the next time it's needed.
+    pub fn unload(&mut self, client_id: u64) {
+        self.0.remove(&client_id);
+    }
+}
@@ -1,14 +1,14 @@
 use std::{
     net::{Ipv4Addr, SocketAddr, UdpSocket},
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
 
 use bevy::{
+    ecs::system::SystemParam,
     log,
     prelude::*,
     utils::{hashbrown::hash_map::Entry, HashMap},
 };
-use bevy_mod_index::prelude::Index;
 use bevy_renet::{
     renet::{
         transport::{NetcodeServerTransport, ServerAuthentication, ServerConfig},
@@ -18,19 +18,29 @@ use bevy_renet::{
     RenetServerPlugin,
 };
 use extension_trait::extension_trait;
+use rand::thread_rng;
 
 use crate::{
-    cards::{Ability, Card, Effect},
+    cards::{deck::seeded_deck, validation, Card},
     match_sim::{
-        Cards, CurrentTurn, EffectEvent, GridLocation, MatchId, NewTurnEvent, OwnerIndex, PlayerId,
+        ai,
+        draft::{choose_draft_pick, DraftState},
+        engine::{MatchOutcome, MatchState},
+        replay::Replay,
+        EffectEvent, MatchEndEvent, MatchEndReason, MatchId, Matches, NewTurnEvent, PlayerId,
         StartMatchEvent,
     },
     network::{
         messages::{
-            ActivateAbilityMessage, EffectMessage, JoinMatchmakingQueueMessage,
-            MatchStartedMessage, NetworkMessage, NewTurnMessage, ProtocolErrorMessage,
+            ActivateAbilityMessage, BoardSnapshotMessage, ConcedeMessage, DeleteDeckMessage,
+            DraftPackMessage, DraftPickMessage, EffectMessage, JoinDraftMessage,
+            JoinMatchmakingQueueMessage, MatchEndedMessage, MatchPausedMessage,
+            MatchResumedMessage, MatchStartedMessage, NetworkMessage, NewTurnMessage,
+            ProfileMessage, ProtocolErrorMessage, RejoinMatchMessage, RequestProfileMessage,
+            RequestSnapshotMessage, SaveDeckMessage, SessionToken, SetPlayerNameMessage,
         },
-        PORT,
+        profile::{rating_change, MatchRecord, PlayerProfile, Profiles},
+        PORT, RECONNECT_GRACE_PERIOD,
     },
 };
 
@@ -56,11 +66,32 @@ impl Plugin for ServerPlugin {
         app.insert_resource(MMQueue::default());
         app.insert_resource(MatchClientMap::default());
         app.insert_resource(AbilityQueue::default());
+        app.insert_resource(MatchRecordings::default());
+        app.insert_resource(Sessions::default());
+        app.insert_resource(RejoinQueue::default());
+        app.insert_resource(Bots::default());
+        app.insert_resource(DraftQueue::default());
+        app.insert_resource(Drafts::default());
+        app.insert_resource(DraftPickQueue::default());
+        app.insert_resource(MatchSeats::default());
+        app.insert_resource(Profiles::default());
         app.add_systems(First, read_messages);
         app.add_systems(PreUpdate, matchmaking.run_if(resource_changed::<MMQueue>));
+        app.add_systems(PreUpdate, start_drafts.run_if(resource_changed::<DraftQueue>));
         app.add_systems(
             Update,
-            (process_abilities, send_match_start, send_effects, send_turn_change).chain(),
+            (
+                rejoin_matches,
+                expire_sessions,
+                bot_turns,
+                process_draft_picks,
+                process_abilities,
+                send_match_start,
+                send_effects,
+                send_turn_change,
+                send_match_end,
+            )
+                .chain(),
         );
     }
 }
@@ -69,16 +100,109 @@ impl Plugin for ServerPlugin {
 struct ConnectedClients(HashMap<ClientId, Option<PlayerId>>);
 #[derive(Resource, Default)]
 struct MatchClientMap(HashMap<MatchId, Vec<ClientId>>);
+/// Who played each match, `None` for bots. Unlike [`MatchClientMap`] this keeps disconnected
+/// players, so the result still reaches their profile.
+#[derive(Resource, Default)]
+struct MatchSeats(HashMap<MatchId, Vec<(PlayerId, Option<ClientId>)>>);
 
 #[derive(Resource, Default)]
 struct MMQueue(HashMap<ClientId, QueueInfo>);
 struct QueueInfo {
     deck: Card,
     player_name: String,
+    vs_bot: bool,
+}
+
+/// Who submitted an action.
+enum Actor {
+    Client(ClientId),
+    Bot(PlayerId),
+}
+impl Actor {
+    fn reject(&self, server: &mut RenetServer, msg: impl std::fmt::Display) {
+        match self {
+            Actor::Client(client_id) => server.send_error(client_id, msg),
+            Actor::Bot(pid) => log::error!("Bot {pid} made an invalid action: {msg}"),
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+struct AbilityQueue(Vec<(Actor, ActivateAbilityMessage)>);
+
+/// Server controlled players, see [`ai::choose_action`].
+#[derive(Resource, Default)]
+struct Bots(HashMap<PlayerId, Bot>);
+struct Bot {
+    match_id: MatchId,
+    /// Runs between the start of the bot's turn and it taking an action
+    thinking: Option<Timer>,
+}
+
+/// How long bots wait before acting, so their moves can be followed.
+const BOT_THINK_TIME: Duration = Duration::from_millis(800);
+
+#[derive(Resource, Default)]
+struct DraftQueue(HashMap<ClientId, JoinDraftMessage>);
+
+/// Drafts in progress, by the id of the match they turn into.
+#[derive(Resource, Default)]
+struct Drafts(HashMap<MatchId, Draft>);
+struct Draft {
+    state: DraftState,
+    /// `None` seats are drafted by a bot
+    seats: [Option<ClientId>; 2],
+}
+impl Draft {
+    /// Lets bots pick until it's a player's turn or the draft is over.
+    fn bot_picks(&mut self) {
+        while !self.state.is_finished() && self.seats[self.state.picking].is_none() {
+            let idx = choose_draft_pick(&self.state.pack);
+            let seat = self.state.picking;
+            self.state.pick(&mut thread_rng(), seat, idx).unwrap();
+        }
+    }
+
+    fn send_packs(&self, server: &mut RenetServer, match_id: MatchId) {
+        for (seat, client_id) in self.seats.iter().enumerate() {
+            let Some(client_id) = client_id else { continue };
+            server.send(client_id, DraftPackMessage {
+                match_id,
+                cards: self.state.pack.clone(),
+                your_pick: self.state.picking == seat,
+                picks: self.state.picks[seat].clone(),
+            });
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+struct DraftPickQueue(Vec<(ClientId, DraftPickMessage)>);
+
+#[derive(Resource, Default)]
+struct MatchRecordings(HashMap<MatchId, Replay>);
+
+/// Lets players rejoin their match after disconnecting.
+#[derive(Resource, Default)]
+struct Sessions(HashMap<SessionToken, Session>);
+struct Session {
+    match_id: MatchId,
+    player_id: PlayerId,
+    /// Runs while the player is disconnected, they forfeit when it finishes
+    grace_period: Option<Timer>,
+}
+impl Sessions {
+    fn token(&self, player_id: PlayerId) -> Option<SessionToken> {
+        self.0.iter().find(|(_, s)| s.player_id == player_id).map(|(token, _)| *token)
+    }
+
+    fn is_paused(&self, match_id: &MatchId) -> bool {
+        self.0.values().any(|s| s.match_id == *match_id && s.grace_period.is_some())
+    }
 }
 
 #[derive(Resource, Default)]
-struct AbilityQueue(Vec<(ClientId, ActivateAbilityMessage)>);
+struct RejoinQueue(Vec<(ClientId, RejoinMatchMessage)>);
 
 #[extension_trait]
 pub impl ServerExt for RenetServer {
@@ -112,6 +236,15 @@ fn read_messages(
     mut clients: ResMut<ConnectedClients>,
     mut mm_queue: ResMut<MMQueue>,
     mut ability_queue: ResMut<AbilityQueue>,
+    mut rejoin_queue: ResMut<RejoinQueue>,
+    mut match_end: EventWriter<MatchEndEvent>,
+    mut match_map: ResMut<MatchClientMap>,
+    mut sessions: ResMut<Sessions>,
+    matches: Res<Matches>,
+    mut draft_queue: ResMut<DraftQueue>,
+    mut draft_picks: ResMut<DraftPickQueue>,
+    mut drafts: ResMut<Drafts>,
+    mut profiles: ResMut<Profiles>,
 ) {
     for event in server_events.read() {
         match event {
@@ -121,7 +254,34 @@ fn read_messages(
             },
             ServerEvent::ClientDisconnected { client_id, reason } => {
                 info!("Client {client_id} disconnected: {reason}");
-                clients.0.remove(client_id);
+                profiles.unload(client_id.raw());
+                mm_queue.0.remove(client_id);
+                draft_queue.0.remove(client_id);
+                drafts.0.retain(|_, draft| {
+                    if !draft.seats.contains(&Some(*client_id)) {
+                        return true;
+                    }
+                    for other in draft.seats.iter().flatten().filter(|c| *c != client_id) {
+                        server.send_error(other, "Your opponent left the draft.");
+                    }
+                    false
+                });
+                let Some(Some(player)) = clients.0.remove(client_id) else { continue };
+                let Some(session) = sessions.0.values_mut().find(|s| s.player_id == player) else {
+                    continue;
+                };
+                session.grace_period = Some(Timer::new(RECONNECT_GRACE_PERIOD, TimerMode::Once));
+
+                let match_id = session.match_id;
+                let Some(match_clients) = match_map.0.get_mut(&match_id) else { continue };
+                match_clients.retain(|c| c != client_id);
+                for c in match_clients.iter() {
+                    server.send(c, MatchPausedMessage {
+                        match_id,
+                        player,
+                        grace_period: RECONNECT_GRACE_PERIOD.as_secs(),
+                    });
+                }
             },
         }
     }
@@ -132,16 +292,89 @@ fn read_messages(
                 NetworkMessage::JoinMatchmakingQueueMessage(JoinMatchmakingQueueMessage {
                     deck,
                     player_name,
-                }) => match mm_queue.0.entry(*client_id) {
+                    vs_bot,
+                }) => {
+                    if let Err(e) = check_deck(&deck) {
+                        server.send_error(client_id, e);
+                        continue;
+                    }
+                    match mm_queue.0.entry(*client_id) {
+                        Entry::Vacant(e) => {
+                            e.insert(QueueInfo { player_name, deck: deck.clone(), vs_bot });
+                        },
+                        Entry::Occupied(_) => {
+                            server.send_error(client_id, "already in queue".to_string());
+                        },
+                    }
+                },
+                NetworkMessage::JoinDraftMessage(msg) => match draft_queue.0.entry(*client_id) {
                     Entry::Vacant(e) => {
-                        e.insert(QueueInfo { player_name, deck: deck.clone() });
+                        e.insert(msg);
                     },
                     Entry::Occupied(_) => {
                         server.send_error(client_id, "already in queue".to_string());
                     },
                 },
+                NetworkMessage::DraftPickMessage(msg) => draft_picks.0.push((*client_id, msg)),
                 NetworkMessage::ActivateAbilityMessage(msg) => {
-                    ability_queue.0.push((*client_id, msg))
+                    ability_queue.0.push((Actor::Client(*client_id), msg))
+                },
+                NetworkMessage::ConcedeMessage(ConcedeMessage { match_id: conceded_match }) => {
+                    let (Some(loser), Some(match_clients)) =
+                        (match_id, match_map.0.get(&conceded_match))
+                    else {
+                        server.send_error(client_id, "Not in a match.");
+                        continue;
+                    };
+                    if !match_clients.contains(client_id) {
+                        server.send_error(client_id, "Not in that match.");
+                        continue;
+                    }
+                    let Some(end) =
+                        matches.0.get(&conceded_match).and_then(|state| concession(state, *loser))
+                    else {
+                        server.send_error(client_id, "Opponent not found.");
+                        continue;
+                    };
+                    match_end.send(end);
+                },
+                NetworkMessage::RejoinMatchMessage(msg) => rejoin_queue.0.push((*client_id, msg)),
+                NetworkMessage::RequestSnapshotMessage(RequestSnapshotMessage {
+                    match_id: requested_match,
+                }) => {
+                    let (Some(match_clients), Some(state)) =
+                        (match_map.0.get(&requested_match), matches.0.get(&requested_match))
+                    else {
+                        server.send_error(client_id, "No such match.");
+                        continue;
+                    };
+                    if !match_clients.contains(client_id) {
+                        server.send_error(client_id, "Not in that match.");
+                        continue;
+                    }
+                    server.send(client_id, BoardSnapshotMessage {
+                        match_id: requested_match,
+                        snapshot: state.snapshot(),
+                    });
+                },
+                NetworkMessage::RequestProfileMessage(RequestProfileMessage) => {
+                    update_profile(&mut server, &mut profiles, client_id, |_| Ok(()));
+                },
+                NetworkMessage::SetPlayerNameMessage(SetPlayerNameMessage { name }) => {
+                    update_profile(&mut server, &mut profiles, client_id, |profile| {
+                        profile.set_name(&name)
+                    });
+                },
+                NetworkMessage::SaveDeckMessage(SaveDeckMessage { name, deck }) => {
+                    update_profile(&mut server, &mut profiles, client_id, |profile| {
+                        check_deck(&deck)?;
+                        profile.save_deck(&name, deck)
+                    });
+                },
+                NetworkMessage::DeleteDeckMessage(DeleteDeckMessage { name }) => {
+                    update_profile(&mut server, &mut profiles, client_id, |profile| {
+                        profile.delete_deck(&name)
+                    });
                 },
                 NetworkMessage::ProtocolErrorMessage(ProtocolErrorMessage { msg }) => {
                     log::error!("ProtocolError from client {client_id}: {msg}")
@@ -154,47 +387,190 @@ fn read_messages(
     }
 }
 
-fn matchmaking(
-    mut mm_queue: ResMut<MMQueue>,
-    mut start_match: EventWriter<StartMatchEvent>,
-    mut effects: EventWriter<EffectEvent>,
-    mut start_turn: EventWriter<NewTurnEvent>,
-    mut match_map: ResMut<MatchClientMap>,
-    mut clients: ResMut<ConnectedClients>,
+/// Lists everything wrong with `deck`, ready to send to a client.
+fn check_deck(deck: &Card) -> Result<(), String> {
+    validation::validate(deck).map_err(|issues| {
+        let issues = issues.iter().map(|i| format!("\n  {i}")).collect::<String>();
+        format!("Invalid deck:{issues}")
+    })
+}
+
+/// Applies `update` to a client's profile, then saves it and sends it back to them.
+fn update_profile(
+    server: &mut RenetServer,
+    profiles: &mut Profiles,
+    client_id: &ClientId,
+    update: impl FnOnce(&mut PlayerProfile) -> Result<(), String>,
 ) {
+    let profile = match profiles.get(client_id.raw()) {
+        Ok(profile) => profile,
+        Err(e) => {
+            log::error!("Failed to load profile of client {client_id}: {e}");
+            server.send_error(client_id, "Could not load your profile.");
+            return;
+        },
+    };
+    if let Err(e) = update(profile) {
+        server.send_error(client_id, e);
+        return;
+    }
+    if let Err(e) = profile.save() {
+        log::error!("Failed to save profile of client {client_id}: {e}");
+    }
+    server.send(client_id, ProfileMessage { profile: profile.clone() });
+}
+
+fn matchmaking(mut mm_queue: ResMut<MMQueue>, mut starter: MatchStarter) {
     debug!("{} players in queue", mm_queue.0.len());
 
-    if mm_queue.0.len() < 2 {
+    if mm_queue.0.len() < 2 && !mm_queue.0.values().any(|info| info.vs_bot) {
         return;
     }
 
-    let match_id = MatchId::new();
-
-    let mut i = mm_queue.0.drain();
-    let mut players = i
-        .by_ref()
-        .take(2)
+    // `None` seats are taken by a bot
+    let (vs_bot, mut humans): (Vec<_>, Vec<_>) =
+        mm_queue.0.drain().partition(|(_, info)| info.vs_bot);
+    let mut pairings = vs_bot
+        .into_iter()
         .map(|(client_id, info)| {
-            let pid = PlayerId::new();
-            clients.0.insert(client_id, Some(pid));
-            match_map.0.entry(match_id).or_insert(vec![]).push(client_id);
-            (pid, Some(info.deck))
+            let (seed, deck) = std::iter::repeat_with(rand::random::<u64>)
+                .map(|seed| (seed, seeded_deck(seed).deck))
+                .find(|(_, deck)| check_deck(deck).is_ok())
+                .unwrap();
+            info!("Bot for client {client_id} plays deck seed {seed}");
+            vec![(Some(client_id), info.deck), (None, deck)]
         })
         .collect::<Vec<_>>();
-    *mm_queue = MMQueue(i.collect());
+    while humans.len() >= 2 {
+        let seats = humans.drain(..2).map(|(client_id, info)| (Some(client_id), info.deck));
+        pairings.push(seats.collect());
+    }
+    *mm_queue = MMQueue(humans.into_iter().collect());
 
-    for (pid, card) in &mut players {
-        effects.send(EffectEvent {
-            match_id,
-            effect: Effect::SummonCard { card: card.take().unwrap() },
-            targets: vec![GridLocation { owner: *pid, coord: UVec2::new(0, 2) }],
-        });
+    for seats in pairings {
+        starter.start(MatchId::new(), seats);
+    }
+}
+
+fn start_drafts(
+    mut draft_queue: ResMut<DraftQueue>,
+    mut drafts: ResMut<Drafts>,
+    mut server: ResMut<RenetServer>,
+) {
+    if draft_queue.0.len() < 2 && !draft_queue.0.values().any(|join| join.vs_bot) {
+        return;
+    }
+
+    let (vs_bot, mut humans): (Vec<_>, Vec<_>) =
+        draft_queue.0.drain().partition(|(_, join)| join.vs_bot);
+    let mut pairings =
+        vs_bot.into_iter().map(|(client_id, _)| [Some(client_id), None]).collect::<Vec<_>>();
+    while humans.len() >= 2 {
+        let mut pair = humans.drain(..2).map(|(client_id, _)| Some(client_id));
+        pairings.push([pair.next().unwrap(), pair.next().unwrap()]);
     }
+    *draft_queue = DraftQueue(humans.into_iter().collect());
 
-    let players = players.into_iter().map(|(pid, _)| pid).collect::<Vec<_>>();
-    let p1 = players[0]; //todo random
-    start_match.send(StartMatchEvent { match_id, players });
-    start_turn.send(NewTurnEvent { match_id, next_player: p1 });
+    for seats in pairings {
+        let match_id = MatchId::new();
+        let mut draft = Draft { state: DraftState::new(&mut thread_rng()), seats };
+        draft.bot_picks();
+        draft.send_packs(&mut server, match_id);
+        drafts.0.insert(match_id, draft);
+    }
+}
+
+fn process_draft_picks(
+    mut draft_picks: ResMut<DraftPickQueue>,
+    mut drafts: ResMut<Drafts>,
+    mut server: ResMut<RenetServer>,
+    mut starter: MatchStarter,
+) {
+    for (client_id, DraftPickMessage { match_id, card_idx }) in draft_picks.0.drain(..) {
+        let Some(draft) = drafts.0.get_mut(&match_id) else {
+            server.send_error(&client_id, "No such draft.");
+            continue;
+        };
+        let Some(seat) = draft.seats.iter().position(|c| *c == Some(client_id)) else {
+            server.send_error(&client_id, "Not in that draft.");
+            continue;
+        };
+        if let Err(e) = draft.state.pick(&mut thread_rng(), seat, card_idx) {
+            server.send_error(&client_id, e);
+            continue;
+        }
+        draft.bot_picks();
+
+        if !draft.state.is_finished() {
+            draft.send_packs(&mut server, match_id);
+            continue;
+        }
+        let draft = drafts.0.remove(&match_id).unwrap();
+        let decks = draft.state.decks().map(|deck| deck.deck);
+        if let Some(e) = decks.iter().find_map(|deck| check_deck(deck).err()) {
+            for client_id in draft.seats.iter().flatten() {
+                server.send_error(client_id, format!("The draft made an unplayable deck. {e}"));
+            }
+            continue;
+        }
+        starter.start(match_id, draft.seats.into_iter().zip(decks).collect());
+    }
+}
+
+/// Everything that needs to know about a new match.
+#[derive(SystemParam)]
+struct MatchStarter<'w> {
+    start_match: EventWriter<'w, StartMatchEvent>,
+    effects: EventWriter<'w, EffectEvent>,
+    start_turn: EventWriter<'w, NewTurnEvent>,
+    match_map: ResMut<'w, MatchClientMap>,
+    clients: ResMut<'w, ConnectedClients>,
+    matches: ResMut<'w, Matches>,
+    recordings: ResMut<'w, MatchRecordings>,
+    sessions: ResMut<'w, Sessions>,
+    bots: ResMut<'w, Bots>,
+    seats: ResMut<'w, MatchSeats>,
+}
+impl MatchStarter<'_> {
+    /// Starts a match between the decks of `seats`, where `None` seats are taken by a bot.
+    fn start(&mut self, match_id: MatchId, seats: Vec<(Option<ClientId>, Card)>) {
+        let decks = seats
+            .into_iter()
+            .map(|(client_id, deck)| {
+                let pid = PlayerId::new();
+                self.seats.0.entry(match_id).or_insert(vec![]).push((pid, client_id));
+                match client_id {
+                    Some(client_id) => {
+                        self.clients.0.insert(client_id, Some(pid));
+                        self.match_map.0.entry(match_id).or_insert(vec![]).push(client_id);
+                        self.sessions.0.insert(SessionToken::new(), Session {
+                            match_id,
+                            player_id: pid,
+                            grace_period: None,
+                        });
+                    },
+                    None => {
+                        self.bots.0.insert(pid, Bot { match_id, thinking: None });
+                    },
+                }
+                (pid, deck)
+            })
+            .collect::<Vec<_>>();
+
+        let players = decks.iter().map(|(pid, _)| *pid).collect::<Vec<_>>();
+        let p1 = players[0]; //todo random
+
+        let seed = rand::random::<u64>();
+        self.recordings.0.insert(match_id, Replay::new(match_id, decks.clone(), p1, seed));
+
+        let mut state = MatchState::new(match_id, players.clone(), seed);
+        self.effects.send_batch(state.start(decks, p1));
+        let checksum = state.checksum();
+        self.matches.0.insert(match_id, state);
+
+        self.start_match.send(StartMatchEvent { match_id, players });
+        self.start_turn.send(NewTurnEvent { match_id, next_player: p1, checksum: Some(checksum) });
+    }
 }
 
 fn send_match_start(
@@ -202,13 +578,16 @@ fn send_match_start(
     mut server: ResMut<RenetServer>,
     client_map: Res<MatchClientMap>,
     player_map: Res<ConnectedClients>,
+    sessions: Res<Sessions>,
 ) {
     for StartMatchEvent { match_id, players } in start_match.read() {
         for client_id in client_map.0.get(match_id).unwrap() {
+            let you = player_map.0.get(client_id).unwrap().unwrap();
             server.send(client_id, MatchStartedMessage {
                 match_id: *match_id,
                 players: players.clone(),
-                you: player_map.0.get(client_id).unwrap().unwrap(),
+                you,
+                session_token: sessions.token(you),
             })
         }
     }
@@ -220,7 +599,8 @@ fn send_effects(
     client_map: Res<MatchClientMap>,
 ) {
     for EffectEvent { match_id, effect, targets } in effects.read() {
-        for client_id in client_map.0.get(match_id).unwrap() {
+        let Some(match_clients) = client_map.0.get(match_id) else { continue };
+        for client_id in match_clients {
             server.send(client_id, EffectMessage {
                 match_id: *match_id,
                 effect: effect.clone(),
@@ -234,105 +614,418 @@ fn send_turn_change(
     mut turns: EventReader<NewTurnEvent>,
     mut server: ResMut<RenetServer>,
     client_map: Res<MatchClientMap>,
+    matches: Res<Matches>,
 ) {
-    for NewTurnEvent { match_id, next_player } in turns.read() {
-        for client_id in client_map.0.get(match_id).unwrap() {
-            server
-                .send(client_id, NewTurnMessage { match_id: *match_id, next_player: *next_player });
+    for NewTurnEvent { match_id, next_player, checksum } in turns.read() {
+        let Some(match_clients) = client_map.0.get(match_id) else { continue };
+        let Some(checksum) = checksum.or_else(|| matches.0.get(match_id).map(|s| s.checksum()))
+        else {
+            continue;
+        };
+        for client_id in match_clients {
+            server.send(client_id, NewTurnMessage {
+                match_id: *match_id,
+                next_player: *next_player,
+                checksum,
+            });
+        }
+    }
+}
+
+fn send_match_end(
+    mut match_end: EventReader<MatchEndEvent>,
+    mut server: ResMut<RenetServer>,
+    mut client_map: ResMut<MatchClientMap>,
+    mut clients: ResMut<ConnectedClients>,
+    mut recordings: ResMut<MatchRecordings>,
+    mut sessions: ResMut<Sessions>,
+    mut bots: ResMut<Bots>,
+    mut seats: ResMut<MatchSeats>,
+    mut profiles: ResMut<Profiles>,
+) {
+    for MatchEndEvent { match_id, winner, loser, reason } in match_end.read() {
+        sessions.0.retain(|_, s| s.match_id != *match_id);
+        bots.0.retain(|_, b| b.match_id != *match_id);
+
+        if let Some(mut replay) = recordings.0.remove(match_id) {
+            replay.outcome = Some(MatchOutcome { winner: *winner, loser: *loser, reason: *reason });
+            match replay.save() {
+                Ok(path) => info!("Saved replay of match {match_id} to {}", path.display()),
+                Err(e) => log::error!("Failed to save replay of match {match_id}: {e}"),
+            }
+        }
+
+        // Ignore duplicate end events for a match that was already torn down
+        let Some(match_clients) = client_map.0.remove(match_id) else { continue };
+        for client_id in match_clients {
+            server.send(&client_id, MatchEndedMessage {
+                match_id: *match_id,
+                winner: *winner,
+                loser: *loser,
+                reason: *reason,
+            });
+            if let Some(pid) = clients.0.get_mut(&client_id) {
+                *pid = None;
+            }
+        }
+
+        let Some(seats) = seats.0.remove(match_id) else { continue };
+        for (client_id, profile) in record_match(&mut profiles, *match_id, &seats, *winner, *reason)
+        {
+            if clients.0.contains_key(&client_id) {
+                server.send(&client_id, ProfileMessage { profile });
+            }
         }
     }
 }
 
+/// Adds a finished match to the history of every player in it. Ratings only change between two
+/// players, not against bots. Returns the updated profiles.
+fn record_match(
+    profiles: &mut Profiles,
+    match_id: MatchId,
+    seats: &[(PlayerId, Option<ClientId>)],
+    winner: PlayerId,
+    reason: MatchEndReason,
+) -> Vec<(ClientId, PlayerProfile)> {
+    // name and rating from before the match, `None` for bots
+    let before = seats
+        .iter()
+        .map(|(pid, client_id)| {
+            let info = client_id.and_then(|client_id| match profiles.get(client_id.raw()) {
+                Ok(profile) => Some((profile.name.clone(), profile.rating)),
+                Err(e) => {
+                    log::error!("Failed to load profile of client {client_id}: {e}");
+                    None
+                },
+            });
+            (*pid, info)
+        })
+        .collect::<Vec<_>>();
+
+    let mut updated = vec![];
+    for ((pid, client_id), (_, info)) in seats.iter().zip(&before) {
+        let (Some(client_id), Some((_, rating))) = (client_id, info) else { continue };
+        let Some((_, opponent)) = before.iter().find(|(other, _)| other != pid) else { continue };
+        let won = *pid == winner;
+        let change = match opponent {
+            Some((_, opponent_rating)) => rating_change(*rating, *opponent_rating, won),
+            None => 0,
+        };
+
+        let Ok(profile) = profiles.get(client_id.raw()) else { continue };
+        let opponent_name = opponent.as_ref().map(|(name, _)| name.clone());
+        profile.record_match(MatchRecord::new(match_id, opponent_name, won, reason, change));
+        if let Err(e) = profile.save() {
+            log::error!("Failed to save profile of client {client_id}: {e}");
+        }
+        updated.push((*client_id, profile.clone()));
+    }
+    updated
+}
+
 fn process_abilities(
     mut ability_queue: ResMut<AbilityQueue>,
     mut effects: EventWriter<EffectEvent>,
     mut turns: EventWriter<NewTurnEvent>,
-    cards: Cards,
-    cur_turns: Query<Has<CurrentTurn>>,
-    mut player_idx: Index<PlayerId>,
-    mut owner_idx: Index<OwnerIndex>,
-    mut loc_idx: Index<GridLocation>,
+    mut match_end: EventWriter<MatchEndEvent>,
+    mut matches: ResMut<Matches>,
+    mut recordings: ResMut<MatchRecordings>,
     clients: Res<ConnectedClients>,
-    client_map: Res<MatchClientMap>,
+    sessions: Res<Sessions>,
     mut server: ResMut<RenetServer>,
 ) {
-    for (client_id, activation) in ability_queue.0.drain(..) {
-        let Some(Some(pid)) = clients.0.get(&client_id) else {
-            server.send_error(&client_id, "Not in a match.");
+    for (actor, activation) in ability_queue.0.drain(..) {
+        let match_id = activation.match_id;
+        let pid = match &actor {
+            Actor::Client(client_id) => {
+                let Some(Some(pid)) = clients.0.get(client_id) else {
+                    actor.reject(&mut server, "Not in a match.");
+                    continue;
+                };
+                *pid
+            },
+            Actor::Bot(pid) => *pid,
+        };
+
+        let Some(state) = matches.0.get_mut(&match_id) else {
+            actor.reject(&mut server, "No such match.");
             continue;
         };
+        if sessions.is_paused(&match_id) {
+            actor.reject(&mut server, "Match is paused until your opponent reconnects.");
+            continue;
+        }
+
+        let resolved = match state.apply_action(pid, &activation) {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                actor.reject(&mut server, e);
+                // nothing else would move the match along
+                if let Actor::Bot(pid) = actor {
+                    match_end.send_batch(concession(state, pid));
+                }
+                continue;
+            },
+        };
+
+        let next_player = state.outcome.is_none().then_some(state.current_turn).flatten();
+        if let Some(replay) = recordings.0.get_mut(&match_id) {
+            replay.record_turn(pid, activation, &resolved, next_player);
+        }
+        effects.send_batch(resolved);
+
+        if let Some(MatchOutcome { winner, loser, reason }) = state.outcome {
+            match_end.send(MatchEndEvent { match_id, winner, loser, reason });
+        } else if let Some(next_player) = next_player {
+            let checksum = Some(state.checksum());
+            turns.send(NewTurnEvent { match_id, next_player, checksum });
+        }
+    }
+}
+
+/// The end of the match where `loser` gives up, if there is an opponent to win it.
+fn concession(state: &MatchState, loser: PlayerId) -> Option<MatchEndEvent> {
+    Some(MatchEndEvent {
+        match_id: state.match_id,
+        winner: state.opponent(loser)?,
+        loser,
+        reason: MatchEndReason::Conceded,
+    })
+}
+
+/// Queues an action for each bot whose turn it is, once it has finished thinking. A bot with
+/// nothing it can do concedes, rather than leave the match waiting on it.
+fn bot_turns(
+    mut turns: EventReader<NewTurnEvent>,
+    mut bots: ResMut<Bots>,
+    mut ability_queue: ResMut<AbilityQueue>,
+    mut match_end: EventWriter<MatchEndEvent>,
+    matches: Res<Matches>,
+    sessions: Res<Sessions>,
+    time: Res<Time>,
+) {
+    for NewTurnEvent { next_player, .. } in turns.read() {
+        if let Some(bot) = bots.0.get_mut(next_player) {
+            bot.thinking = Some(Timer::new(BOT_THINK_TIME, TimerMode::Once));
+        }
+    }
 
-        if !cur_turns.get(player_idx.single(&pid)).unwrap() {
-            server.send_error(&client_id, "Not your turn.");
+    for (pid, bot) in bots.0.iter_mut() {
+        if sessions.is_paused(&bot.match_id) {
+            continue;
+        }
+        let Some(thinking) = &mut bot.thinking else { continue };
+        if !thinking.tick(time.delta()).finished() {
             continue;
         }
+        bot.thinking = None;
 
-        let source_loc = GridLocation { owner: *pid, coord: activation.unit_location };
-        let Ok(card) = cards.get(loc_idx.single(&source_loc)) else {
-            server.send_error(&client_id, "No unit there.");
+        let Some(state) = matches.0.get(&bot.match_id) else { continue };
+        match ai::choose_action(state, *pid) {
+            Some(action) => ability_queue.0.push((Actor::Bot(*pid), action)),
+            None => {
+                log::error!("Bot {pid} has no legal actions, conceding");
+                match_end.send_batch(concession(state, *pid));
+            },
+        }
+    }
+}
+
+fn rejoin_matches(
+    mut rejoin_queue: ResMut<RejoinQueue>,
+    mut sessions: ResMut<Sessions>,
+    mut clients: ResMut<ConnectedClients>,
+    mut match_map: ResMut<MatchClientMap>,
+    matches: Res<Matches>,
+    mut server: ResMut<RenetServer>,
+) {
+    for (client_id, RejoinMatchMessage { match_id, session_token }) in rejoin_queue.0.drain(..) {
+        if !matches!(clients.0.get(&client_id), Some(None)) {
+            server.send_error(&client_id, "Already in a match.");
+            continue;
+        }
+        let Some(session) = sessions.0.get_mut(&session_token).filter(|s| s.match_id == match_id)
+        else {
+            server.send_error(&client_id, "No such session.");
             continue;
         };
-
-        let Some(ability) = card.abilities.0.get(activation.ability_idx) else {
-            server.send_error(&client_id, "No such ability.");
+        let (Some(state), Some(match_clients)) =
+            (matches.0.get(&match_id), match_map.0.get_mut(&match_id))
+        else {
+            server.send_error(&client_id, "Match is over.");
             continue;
         };
+        if session.grace_period.take().is_none() {
+            server.send_error(&client_id, "Already connected to this match.");
+            continue;
+        }
+
+        let you = session.player_id;
+        info!("Client {client_id} rejoined match {match_id} as {you:?}");
+        clients.0.insert(client_id, Some(you));
+        match_clients.push(client_id);
+        server.send(&client_id, MatchStartedMessage {
+            match_id,
+            players: state.players.clone(),
+            you,
+            session_token: Some(session_token),
+        });
+        server.send(&client_id, BoardSnapshotMessage { match_id, snapshot: state.snapshot() });
 
-        let Ability::Activated { effect, cost, target_rules } = ability else {
-            server.send_error(&client_id, "Ability is passive.");
+        let mut still_paused = false;
+        for session in sessions.0.values().filter(|s| s.match_id == match_id) {
+            let Some(grace_period) = &session.grace_period else { continue };
+            still_paused = true;
+            server.send(&client_id, MatchPausedMessage {
+                match_id,
+                player: session.player_id,
+                grace_period: grace_period.remaining().as_secs(),
+            });
+        }
+        if !still_paused {
+            for c in match_clients.iter() {
+                server.send(c, MatchResumedMessage { match_id });
+            }
+        }
+    }
+}
+
+/// Forfeits matches for players who didn't rejoin in time.
+fn expire_sessions(
+    time: Res<Time>,
+    mut sessions: ResMut<Sessions>,
+    matches: Res<Matches>,
+    mut match_end: EventWriter<MatchEndEvent>,
+) {
+    for session in sessions.0.values_mut() {
+        let Some(grace_period) = &mut session.grace_period else { continue };
+        if !grace_period.tick(time.delta()).just_finished() {
+            continue;
+        }
+        let loser = session.player_id;
+        let Some(winner) = matches.0.get(&session.match_id).and_then(|s| s.opponent(loser)) else {
             continue;
         };
+        info!("{loser:?} did not rejoin match {} in time", session.match_id);
+        match_end.send(MatchEndEvent {
+            match_id: session.match_id,
+            winner,
+            loser,
+            reason: MatchEndReason::Disconnected,
+        });
+    }
+}
 
-        let energy_cost = cost.get(&effect).energy;
-        if energy_cost > card.energy.current {
-            server.send_error(&client_id, "Not enough energy.");
-            continue;
+#[cfg(test)]
+mod tests {
+    use bevy_renet::renet::RenetClient;
+
+    use super::*;
+    use crate::cards::{
+        Ability, AbilityCost, Cost, Effect, EffectType, TargetAmount, TargetFilter, TargetRules,
+        TargetSelection,
+    };
+
+    /// A Command Center whose only ability costs more energy than it will have for a while.
+    fn broke_deck() -> Card {
+        Card {
+            name: "Broke Command Center".to_string(),
+            summon_cost: Cost::FREE,
+            hp: 10,
+            abilities: vec![Ability::Activated {
+                effect: Effect::Attack { damage: 1, effect_type: EffectType::Physical, falloff: 0 },
+                cost: AbilityCost::Static { cost: Cost { energy: 10 } },
+                target_rules: TargetRules {
+                    amount: TargetAmount::N { n: 1 },
+                    filter: TargetFilter::Enemy,
+                    selection: TargetSelection::Chosen,
+                    then: vec![],
+                    area: None,
+                },
+            }],
+            starting_energy: 0,
+            max_energy: 10,
         }
+    }
 
-        let next_player = clients
-            .0
-            .get(
-                client_map
-                    .0
-                    .get(&activation.match_id)
-                    .unwrap()
-                    .iter()
-                    .filter(|c| **c != client_id)
-                    .next()
-                    .unwrap(),
-            )
-            .unwrap()
-            .unwrap();
-        if !target_rules.validate(
-            &activation.targets,
-            &mut loc_idx,
-            &cards,
-            &[*pid, next_player],
-            &source_loc,
-        ) {
-            server.send_error(&client_id, "Invalid Targets.");
-            continue;
+    #[test]
+    fn bot_without_actions_concedes() {
+        let (player, bot) = (PlayerId::new(), PlayerId::new());
+        let match_id = MatchId::new();
+        let mut state = MatchState::new(match_id, vec![player, bot], 0);
+        state.start(vec![(player, broke_deck()), (bot, broke_deck())], bot);
+        assert!(ai::choose_action(&state, bot).is_none());
+
+        let mut app = App::new();
+        app.add_event::<NewTurnEvent>();
+        app.add_event::<MatchEndEvent>();
+        app.init_resource::<AbilityQueue>();
+        app.init_resource::<Sessions>();
+        app.init_resource::<Time>();
+        app.insert_resource(Matches([(match_id, state)].into_iter().collect()));
+        app.insert_resource(Bots([(bot, Bot { match_id, thinking: None })].into_iter().collect()));
+        app.add_systems(Update, bot_turns);
+
+        app.world.send_event(NewTurnEvent { match_id, next_player: bot, checksum: None });
+        app.update();
+        app.world.resource_mut::<Time>().advance_by(BOT_THINK_TIME);
+        app.update();
+
+        let events = app.world.resource::<Events<MatchEndEvent>>();
+        let ends = events.get_reader().read(events).cloned().collect::<Vec<_>>();
+        assert_eq!(ends.len(), 1);
+        assert_eq!((ends[0].match_id, ends[0].winner, ends[0].loser), (match_id, player, bot));
+        assert_eq!(ends[0].reason, MatchEndReason::Conceded);
+        assert!(app.world.resource::<AbilityQueue>().0.is_empty());
+    }
+
+    #[test]
+    fn invalid_decks_are_rejected() {
+        let client_id = ClientId::from_raw(1);
+        let mut client = RenetClient::new(ConnectionConfig::default());
+        let mut server = RenetServer::new(ConnectionConfig::default());
+        server.add_connection(client_id);
+
+        let deck = Card { starting_energy: 20, ..broke_deck() };
+        let msg: NetworkMessage =
+            JoinMatchmakingQueueMessage { player_name: "Player".to_string(), deck, vs_bot: true }
+                .into();
+        client.send_message(DefaultChannel::ReliableOrdered, bincode::serialize(&msg).unwrap());
+        for packet in client.get_packets_to_send() {
+            server.process_packet_from(&packet, client_id).unwrap();
         }
 
-        effects.send(EffectEvent {
-            match_id: activation.match_id,
-            effect: Effect::ChangeEnergy { amount: -(energy_cost as i32) },
-            targets: vec![*card.grid_loc],
-        });
-        effects.send(EffectEvent {
-            match_id: activation.match_id,
-            effect: effect.clone(),
-            targets: activation.targets,
-        });
+        let mut app = App::new();
+        app.add_event::<ServerEvent>();
+        app.add_event::<MatchEndEvent>();
+        app.init_resource::<ConnectedClients>();
+        app.init_resource::<MMQueue>();
+        app.init_resource::<AbilityQueue>();
+        app.init_resource::<RejoinQueue>();
+        app.init_resource::<MatchClientMap>();
+        app.init_resource::<Sessions>();
+        app.init_resource::<Matches>();
+        app.init_resource::<DraftQueue>();
+        app.init_resource::<DraftPickQueue>();
+        app.init_resource::<Drafts>();
+        app.init_resource::<Profiles>();
+        app.insert_resource(server);
+        app.world.resource_mut::<ConnectedClients>().0.insert(client_id, None);
+        app.add_systems(Update, read_messages);
+        app.update();
 
-        turns.send(NewTurnEvent { match_id: activation.match_id, next_player });
-        effects.send(EffectEvent {
-            match_id: activation.match_id,
-            effect: Effect::ChangeEnergy { amount: 1 },
-            targets: cards
-                .iter_many(owner_idx.lookup(&next_player))
-                .map(|card| *card.grid_loc)
-                .collect(),
-        });
+        assert!(app.world.resource::<MMQueue>().0.is_empty());
+        let mut server = app.world.resource_mut::<RenetServer>();
+        for packet in server.get_packets_to_send(client_id).unwrap() {
+            client.process_packet(&packet);
+        }
+        let reply = client.receive_message(DefaultChannel::ReliableOrdered).unwrap();
+        let NetworkMessage::ProtocolErrorMessage(ProtocolErrorMessage { msg }) =
+            bincode::deserialize(&reply).unwrap()
+        else {
+            panic!("expected a protocol error");
+        };
+        assert!(msg.starts_with("Invalid deck:"), "{msg}");
     }
 }
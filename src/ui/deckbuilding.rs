@@ -1,15 +1,403 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, window::ReceivedCharacter};
+use bevy_renet::renet::RenetClient;
+use rand::thread_rng;
 
-use crate::cards::deck::{random_deck, Deck};
+use crate::{
+    cards::{
+        deck::{make_deck, random_summonable_card, Decks, RANDOM_DECK},
+        deck_list::{CardLibrary, CARD_LIBRARY_PATH},
+        price::price_card,
+        Ability, Card, Effect,
+    },
+    network::{
+        messages::SaveDeckMessage,
+        profile::{PlayerProfile, MAX_NAME_LEN},
+        ClientExt,
+    },
+    ui::{
+        button::{ClickHandler, GameButton},
+        despawn_all_with_marker,
+        font::CustomText,
+        SceneState, UiManager,
+    },
+};
+
+/// Where saved decks go, to be picked up by the deck asset loader.
+const DECK_DIR: &str = "assets/decks";
+/// Pool cards shown at once
+const PAGE_SIZE: usize = 6;
+/// Cards added to the pool by each press of "Generate"
+const GENERATED_CARDS: usize = 6;
 
 #[derive(Resource, Reflect)]
-pub struct CustomDeck(pub Deck);
+pub struct DeckbuilderConfig {
+    pub deck_size: usize,
+    /// Most energy the summon costs of a deck can add up to
+    pub budget: u32,
+}
+impl Default for DeckbuilderConfig {
+    fn default() -> Self {
+        Self { deck_size: 5, budget: 30 }
+    }
+}
+
+/// The deck being built.
+#[derive(Resource)]
+pub struct CustomDeck {
+    pub name: String,
+    pub cards: Vec<Card>,
+    /// Result of the last save
+    pub status: String,
+}
+impl Default for CustomDeck {
+    fn default() -> Self {
+        Self { name: "Custom".to_string(), cards: vec![], status: String::new() }
+    }
+}
+impl CustomDeck {
+    fn total_cost(&self) -> u32 {
+        self.cards.iter().map(|card| card.summon_cost.energy).sum()
+    }
+
+    fn can_add(&self, card: &Card, config: &DeckbuilderConfig) -> bool {
+        self.cards.len() < config.deck_size
+            && self.total_cost() + card.summon_cost.energy <= config.budget
+    }
+
+    fn save(&self) -> Result<String, String> {
+        // the random deck would replace it in the menu
+        if self.name.trim() == RANDOM_DECK {
+            return Err(format!("\"{RANDOM_DECK}\" is taken by the random deck."));
+        }
+        let deck = make_deck(self.cards.clone());
+        if let Err(issues) = deck.validate() {
+            return Err(issues.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n"));
+        }
+        let path = format!("{DECK_DIR}/{}.deck", self.name.trim());
+        let json = serde_json::to_string_pretty(&deck).map_err(|e| e.to_string())?;
+        std::fs::write(&path, json).map_err(|e| format!("Couldn't write {path}: {e}"))?;
+        Ok(path)
+    }
+}
+
+pub struct PoolCard {
+    pub card: Card,
+    /// Where the card came from, like "Library" or "Shrapnel"
+    pub source: String,
+}
+
+#[derive(Resource, Default)]
+pub struct CardPool {
+    pub cards: Vec<PoolCard>,
+    pub page: usize,
+}
+impl CardPool {
+    fn add(&mut self, card: Card, source: &str) {
+        if !self.cards.iter().any(|c| c.card.name == card.name) {
+            self.cards.push(PoolCard { card, source: source.to_string() });
+        }
+    }
+
+    fn generate(&mut self) {
+        let mut rng = thread_rng();
+        for _ in 0..GENERATED_CARDS {
+            self.add(random_summonable_card(&mut rng), "Generated");
+        }
+    }
+
+    fn pages(&self) -> usize {
+        self.cards.len().div_ceil(PAGE_SIZE).max(1)
+    }
+}
+
+#[derive(Component)]
+pub struct DeckbuilderUi;
 
 pub struct DeckbuildingPlugin;
 impl Plugin for DeckbuildingPlugin {
     fn build(&self, app: &mut App) {
-        app.register_type::<CustomDeck>();
-        app.insert_resource(CustomDeck(random_deck()));
-        app.add_plugins(bevy_inspector_egui::quick::ResourceInspectorPlugin::<CustomDeck>::new());
+        app.register_type::<DeckbuilderConfig>();
+        app.init_resource::<DeckbuilderConfig>();
+        app.init_resource::<CustomDeck>();
+        app.init_resource::<CardPool>();
+        app.add_plugins(
+            bevy_inspector_egui::quick::ResourceInspectorPlugin::<DeckbuilderConfig>::new()
+                .run_if(in_state(SceneState::Deckbuilding)),
+        );
+
+        app.add_systems(OnEnter(SceneState::Deckbuilding), fill_card_pool);
+        app.add_systems(OnExit(SceneState::Deckbuilding), despawn_all_with_marker::<DeckbuilderUi>);
+        app.add_systems(
+            Update,
+            (
+                edit_deck_name,
+                (despawn_all_with_marker::<DeckbuilderUi>, spawn_deckbuilder).chain().run_if(
+                    resource_changed::<CustomDeck>
+                        .or_else(resource_changed::<CardPool>)
+                        .or_else(resource_changed::<DeckbuilderConfig>),
+                ),
+            )
+                .chain()
+                .run_if(in_state(SceneState::Deckbuilding)),
+        );
+    }
+}
+
+/// Fills the pool with the saved card library, every card in a loaded deck, and a few new ones.
+pub fn fill_card_pool(
+    mut pool: ResMut<CardPool>,
+    decks: Res<Decks>,
+    profile: Option<Res<PlayerProfile>>,
+) {
+    *pool = CardPool::default();
+
+    for card in profile.iter().flat_map(|profile| &profile.cards) {
+        pool.add(card.clone(), "Collection");
+    }
+
+    match std::fs::read_to_string(CARD_LIBRARY_PATH).map(|text| ron::from_str(&text)) {
+        Ok(Ok(CardLibrary(library))) => {
+            for (name, def) in library {
+                pool.add(def.to_card(&name), "Library");
+            }
+        },
+        Ok(Err(e)) => warn!("Invalid card library {CARD_LIBRARY_PATH}: {e}"),
+        Err(e) => warn!("Couldn't read card library {CARD_LIBRARY_PATH}: {e}"),
+    }
+
+    let mut deck_names = decks.0.keys().collect::<Vec<_>>();
+    deck_names.sort();
+    for name in deck_names {
+        for ability in &decks.0[name].deck.abilities {
+            if let Ability::Activated { effect: Effect::SummonCard { card }, .. } = ability {
+                pool.add(card.clone(), name);
+            }
+        }
+    }
+
+    pool.generate();
+}
+
+pub fn spawn_deckbuilder(
+    mut commands: Commands,
+    deck: Res<CustomDeck>,
+    pool: Res<CardPool>,
+    config: Res<DeckbuilderConfig>,
+    mut ui: UiManager,
+) {
+    let text = CustomText::default().size(20.).color(Color::NAVY);
+    let card_text = CustomText::default().size(14.).color(Color::BLACK);
+    let column = |width| NodeBundle {
+        style: Style {
+            width: Val::Percent(width),
+            height: Val::Percent(100.),
+            flex_direction: FlexDirection::Column,
+            align_items: AlignItems::Center,
+            padding: UiRect::all(Val::Vh(1.)),
+            ..default()
+        },
+        ..default()
+    };
+    let row = || NodeBundle {
+        style: Style {
+            flex_direction: FlexDirection::Row,
+            align_items: AlignItems::Center,
+            margin: UiRect::all(Val::Vh(0.4)),
+            ..default()
+        },
+        ..default()
+    };
+    let card_box = NodeBundle {
+        style: Style { width: Val::Vw(40.), padding: UiRect::all(Val::Vh(0.5)), ..default() },
+        background_color: Color::WHITE.into(),
+        ..default()
+    };
+
+    let total_cost = deck.total_cost();
+    let first = pool.page * PAGE_SIZE;
+    let shown = pool.cards.iter().enumerate().skip(first).take(PAGE_SIZE);
+
+    commands
+        .spawn((DeckbuilderUi, NodeBundle {
+            style: Style {
+                width: Val::Percent(100.),
+                height: Val::Percent(100.),
+                flex_direction: FlexDirection::Row,
+                ..default()
+            },
+            ..default()
+        }))
+        .with_children(|base| {
+            base.spawn(column(60.)).with_children(|pool_column| {
+                pool_column.spawn(row()).with_children(|header| {
+                    header
+                        .spawn(button(12., pool.page > 0, |mut pool: ResMut<CardPool>| {
+                            pool.page -= 1;
+                        }))
+                        .add_child(ui.spawn_text(text.clone().text("< Prev")).id());
+                    header.spawn(NodeBundle::default()).add_child(
+                        ui.spawn_text(text.clone().text(format!(
+                            "Card pool, page {}/{}",
+                            pool.page + 1,
+                            pool.pages()
+                        )))
+                        .id(),
+                    );
+                    let has_next = pool.page + 1 < pool.pages();
+                    header
+                        .spawn(button(12., has_next, |mut pool: ResMut<CardPool>| {
+                            pool.page += 1;
+                        }))
+                        .add_child(ui.spawn_text(text.clone().text("Next >")).id());
+                    header
+                        .spawn(button(14., true, |mut pool: ResMut<CardPool>| {
+                            pool.generate();
+                            pool.page = pool.pages() - 1;
+                        }))
+                        .add_child(ui.spawn_text(text.clone().text("Generate")).id());
+                });
+
+                for (idx, PoolCard { card, source }) in shown {
+                    let price =
+                        price_card(&card.abilities, card.hp, card.starting_energy, card.max_energy);
+                    let label = format!(
+                        "{{{}}} {}\nprice {price:.1}, from {source}",
+                        card.summon_cost.energy,
+                        card.full_text()
+                    );
+                    pool_column.spawn(row()).with_children(|entry| {
+                        entry
+                            .spawn(card_box.clone())
+                            .add_child(ui.spawn_text(card_text.clone().text(label)).id());
+                        let can_add = deck.can_add(card, &config);
+                        entry
+                            .spawn(button(
+                                6.,
+                                can_add,
+                                move |mut deck: ResMut<CustomDeck>, pool: Res<CardPool>| {
+                                    deck.cards.push(pool.cards[idx].card.clone());
+                                },
+                            ))
+                            .add_child(ui.spawn_text(text.clone().text("+")).id());
+                    });
+                }
+            });
+
+            base.spawn(column(40.)).with_children(|deck_column| {
+                deck_column.spawn(NodeBundle::default()).add_child(
+                    ui.spawn_text(
+                        text.clone().size(26.).text(format!("{} (type to rename)", deck.name)),
+                    )
+                    .id(),
+                );
+                deck_column.spawn(NodeBundle::default()).add_child(
+                    ui.spawn_text(text.clone().text(format!(
+                        "{}/{} cards, {total_cost}/{} energy",
+                        deck.cards.len(),
+                        config.deck_size,
+                        config.budget
+                    )))
+                    .id(),
+                );
+
+                for (idx, card) in deck.cards.iter().enumerate() {
+                    let label = format!("{{{}}} {}", card.summon_cost.energy, card.name);
+                    deck_column.spawn(row()).with_children(|entry| {
+                        entry
+                            .spawn(NodeBundle {
+                                style: Style { width: Val::Vw(25.), ..default() },
+                                background_color: Color::WHITE.into(),
+                                ..default()
+                            })
+                            .add_child(ui.spawn_text(card_text.clone().size(18.).text(label)).id());
+                        entry
+                            .spawn(button(6., true, move |mut deck: ResMut<CustomDeck>| {
+                                deck.cards.remove(idx);
+                            }))
+                            .add_child(ui.spawn_text(text.clone().text("-")).id());
+                    });
+                }
+
+                let complete = deck.cards.len() == config.deck_size && !deck.name.trim().is_empty();
+                deck_column
+                    .spawn(button(
+                        30.,
+                        complete,
+                        |mut deck: ResMut<CustomDeck>,
+                         profile: Option<Res<PlayerProfile>>,
+                         mut client: ResMut<RenetClient>| {
+                            let path = match deck.save() {
+                                Ok(path) => path,
+                                Err(e) => {
+                                    deck.status = e;
+                                    return;
+                                },
+                            };
+                            deck.status = format!("Saved to {path}");
+                            // only decks from the collection can go in the profile
+                            let cards = make_deck(deck.cards.clone()).deck;
+                            if profile.is_some_and(|profile| profile.owns_cards(&cards)) {
+                                client.send(SaveDeckMessage {
+                                    name: deck.name.trim().to_string(),
+                                    deck: cards,
+                                });
+                                deck.status += " and your profile";
+                            }
+                        },
+                    ))
+                    .add_child(ui.spawn_text(text.clone().text("Save")).id());
+                deck_column
+                    .spawn(button(30., true, |mut next: ResMut<NextState<SceneState>>| {
+                        next.set(SceneState::MainMenu);
+                    }))
+                    .add_child(ui.spawn_text(text.clone().text("Back")).id());
+                deck_column
+                    .spawn(NodeBundle::default())
+                    .add_child(ui.spawn_text(card_text.clone().text(deck.status.clone())).id());
+            });
+        });
+}
+
+fn button<M>(
+    width: f32,
+    active: bool,
+    handler: impl IntoSystem<(), (), M>,
+) -> (NodeBundle, GameButton) {
+    let node = NodeBundle {
+        style: Style {
+            width: Val::Vh(width),
+            margin: UiRect::all(Val::Vh(0.4)),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        ..default()
+    };
+    (node, GameButton {
+        bg_color: Color::WHITE,
+        hover_color: Color::GREEN,
+        disabled_color: Color::GRAY,
+        click_handler: ClickHandler::new(handler),
+        active,
+    })
+}
+
+/// Typing on the deck builder renames the deck.
+pub fn edit_deck_name(
+    mut chars: EventReader<ReceivedCharacter>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut deck: ResMut<CustomDeck>,
+) {
+    let mut name = deck.name.clone();
+    for c in chars.read().flat_map(|c| c.char.chars()) {
+        let allowed = c.is_ascii_alphanumeric() || c == ' ' || c == '-' || c == '_';
+        if allowed && name.chars().count() < MAX_NAME_LEN {
+            name.push(c);
+        }
+    }
+    if keys.just_pressed(KeyCode::Backspace) {
+        name.pop();
+    }
+    if name != deck.name {
+        deck.name = name;
     }
 }
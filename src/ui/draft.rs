@@ -0,0 +1,121 @@
+use bevy::prelude::*;
+use bevy_renet::renet::RenetClient;
+
+use crate::{
+    match_sim::draft::{DraftPack, DRAFT_DECK_SIZE},
+    network::{messages::DraftPickMessage, ClientExt},
+    ui::{
+        button::{ClickHandler, GameButton},
+        font::CustomText,
+        SceneState, UiManager,
+    },
+};
+
+#[derive(Component)]
+pub struct DraftUi;
+
+pub fn transition_to_draft(mut s: ResMut<NextState<SceneState>>) {
+    s.set(SceneState::Draft);
+}
+
+pub fn spawn_draft(mut commands: Commands, pack: Res<DraftPack>, mut ui: UiManager) {
+    let text = CustomText::default().size(24.).color(Color::NAVY).centered();
+    let card_text = CustomText::default().size(14.).color(Color::BLACK);
+
+    let heading = if pack.your_pick { "Pick a card" } else { "Waiting for your opponent's pick" };
+    let picked = pack.picks.iter().map(|card| card.name.as_str()).collect::<Vec<_>>().join(", ");
+
+    commands
+        .spawn((DraftUi, NodeBundle {
+            style: Style {
+                width: Val::Percent(100.),
+                height: Val::Percent(100.),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            ..default()
+        }))
+        .with_children(|base| {
+            base.spawn(NodeBundle::default())
+                .add_child(ui.spawn_text(text.clone().text(heading)).id());
+
+            base.spawn(NodeBundle {
+                style: Style { flex_direction: FlexDirection::Row, ..default() },
+                ..default()
+            })
+            .with_children(|row| {
+                for (card_idx, card) in pack.cards.iter().enumerate() {
+                    let match_id = pack.match_id;
+                    row.spawn((
+                        NodeBundle {
+                            style: Style {
+                                width: Val::Vw(22.),
+                                margin: UiRect::all(Val::Vh(0.5)),
+                                padding: UiRect::all(Val::Vh(0.5)),
+                                ..default()
+                            },
+                            ..default()
+                        },
+                        GameButton {
+                            bg_color: Color::WHITE,
+                            hover_color: Color::GREEN,
+                            disabled_color: Color::GRAY,
+                            click_handler: ClickHandler::new(
+                                move |mut client: ResMut<RenetClient>,
+                                      mut pack: ResMut<DraftPack>| {
+                                    client.send(DraftPickMessage { match_id, card_idx });
+                                    pack.your_pick = false;
+                                },
+                            ),
+                            active: pack.your_pick,
+                        },
+                    ))
+                    .add_child(
+                        ui.spawn_text(card_text.clone().text(format!(
+                            "{{{}}} {}",
+                            card.summon_cost.energy,
+                            card.full_text()
+                        )))
+                        .id(),
+                    );
+                }
+            });
+
+            base.spawn(NodeBundle::default()).add_child(
+                ui.spawn_text(
+                    text.clone().size(20.).text(format!(
+                        "Your deck ({}/{DRAFT_DECK_SIZE}): {picked}",
+                        pack.picks.len()
+                    )),
+                )
+                .id(),
+            );
+
+            base.spawn((
+                NodeBundle {
+                    style: Style {
+                        width: Val::Vh(20.),
+                        margin: UiRect::all(Val::Vh(2.)),
+                        justify_content: JustifyContent::Center,
+                        ..default()
+                    },
+                    ..default()
+                },
+                GameButton {
+                    bg_color: Color::WHITE,
+                    hover_color: Color::GREEN,
+                    disabled_color: Color::GRAY,
+                    click_handler: ClickHandler::new(
+                        |mut commands: Commands, mut s: ResMut<NextState<SceneState>>| {
+                            commands.remove_resource::<DraftPack>();
+                            s.set(SceneState::MainMenu);
+                        },
+                    ),
+                    active: true,
+                },
+            ))
+            .add_child(ui.spawn_text(text.text("Leave")).id());
+        });
+}
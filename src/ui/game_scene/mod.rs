@@ -6,16 +6,23 @@ use bevy::{
 };
 use bevy_mod_index::prelude::Index;
 use bevy_mod_picking::prelude::*;
+use bevy_renet::renet::RenetClient;
 
 use crate::{
     cards::Ability,
     match_sim::{
-        BaseCard, Cards, CurrentTurn, Energy, GridLocation, Health, PlayerId, StartMatchEvent, Us,
+        BaseCard, Cards, CurrentTurn, Energy, GridLocation, Health, MatchEndEvent, MatchEndReason,
+        MatchId, MatchPaused, PlayerId, StartMatchEvent, Us, GRID_H, GRID_W,
+    },
+    network::{
+        messages::{ActivateAbilityMessage, ConcedeMessage, RequestSnapshotMessage},
+        ClientExt,
     },
     ui::{
         button::{ClickHandler, GameButton},
         font::CustomText,
         game_scene::targeting::Targeting,
+        replay::ReplayPlayer,
         SceneState, UiManager,
     },
 };
@@ -26,6 +33,25 @@ pub fn transition_to_match(e: EventReader<StartMatchEvent>, mut s: ResMut<NextSt
     }
 }
 
+#[derive(Resource)]
+pub struct MatchResult {
+    pub won: bool,
+    pub reason: MatchEndReason,
+}
+
+pub fn transition_to_main_menu(
+    mut e: EventReader<MatchEndEvent>,
+    us: Res<Us>,
+    mut s: ResMut<NextState<SceneState>>,
+    mut commands: Commands,
+) {
+    for MatchEndEvent { winner, reason, .. } in e.read() {
+        commands.insert_resource(MatchResult { won: *winner == us.0, reason: *reason });
+        commands.remove_resource::<Targeting>();
+        s.0 = Some(SceneState::MainMenu)
+    }
+}
+
 #[derive(Component)]
 pub struct MatchScenery;
 
@@ -37,6 +63,8 @@ pub fn spawn_match(
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut camera: Query<(&mut Transform, &mut Projection), With<Camera>>,
+    replay: Option<Res<ReplayPlayer>>,
+    mut ui: UiManager,
 ) {
     // table
     commands.spawn((
@@ -81,26 +109,148 @@ pub fn spawn_match(
     //     far: 20.1,
     //     ..default()
     // });
+
+    ui.spawn_text(CustomText::default().color(Color::WHITE).size(20.).centered()).insert((
+        MatchScenery,
+        PauseBanner,
+        Name::new("pause_banner"),
+    ));
+
+    if replay.is_some() {
+        // replays have their own controls
+        return;
+    }
+    let match_buttons = commands
+        .spawn((MatchScenery, Name::new("match_buttons"), NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Vh(1.),
+                right: Val::Vh(1.),
+                flex_direction: FlexDirection::Row,
+                ..default()
+            },
+            ..default()
+        }))
+        .id();
+    let button_style =
+        Style { margin: UiRect::left(Val::Vh(1.)), padding: UiRect::all(Val::Vh(1.)), ..default() };
+
+    let resync_text = ui.spawn_text(CustomText::new("Resync").color(Color::WHITE).size(15.)).id();
+    commands
+        .spawn((
+            Name::new("resync_button"),
+            NodeBundle { style: button_style.clone(), ..default() },
+            GameButton {
+                bg_color: Color::GRAY,
+                hover_color: Color::hex("#5aad65").unwrap(),
+                disabled_color: Color::GRAY,
+                click_handler: ClickHandler::new(
+                    |mut client: ResMut<RenetClient>, matches: Query<&MatchId, With<PlayerId>>| {
+                        let Some(match_id) = matches.iter().next() else { return };
+                        client.send(RequestSnapshotMessage { match_id: *match_id });
+                    },
+                ),
+                active: true,
+            },
+        ))
+        .add_child(resync_text)
+        .set_parent(match_buttons);
+
+    let concede_text = ui.spawn_text(CustomText::new("Concede").color(Color::WHITE).size(15.)).id();
+    commands
+        .spawn((
+            Name::new("concede_button"),
+            NodeBundle { style: button_style, ..default() },
+            GameButton {
+                bg_color: Color::GRAY,
+                hover_color: Color::RED,
+                disabled_color: Color::GRAY,
+                click_handler: ClickHandler::new(
+                    |mut client: ResMut<RenetClient>, matches: Query<&MatchId, With<PlayerId>>| {
+                        let Some(match_id) = matches.iter().next() else { return };
+                        client.send(ConcedeMessage { match_id: *match_id });
+                    },
+                ),
+                active: true,
+            },
+        ))
+        .add_child(concede_text)
+        .set_parent(match_buttons);
+}
+
+#[derive(Component)]
+pub struct PauseBanner;
+
+pub fn update_pause_banner(
+    paused: Option<Res<MatchPaused>>,
+    time: Res<Time>,
+    mut banner: Query<(&mut Text, &mut Style), With<PauseBanner>>,
+) {
+    let Ok((mut text, mut style)) = banner.get_single_mut() else { return };
+    style.position_type = PositionType::Absolute;
+    style.top = Val::Vh(45.);
+    style.width = Val::Percent(100.);
+    text.sections[0].value = match paused {
+        Some(paused) => {
+            let left = (paused.forfeit_at - time.elapsed_seconds()).max(0.).ceil();
+            format!("Opponent disconnected\nWaiting {left}s for them to rejoin")
+        },
+        None => String::new(),
+    };
 }
 
-pub const GRID_H: f32 = 4.;
-pub const GRID_W: f32 = 5.;
+/// Seconds a unit takes to slide to a new location
+const MOVE_DURATION: f32 = 0.3;
+
+/// Slides a card from where it was to its new [`GridLocation`].
+#[derive(Component)]
+pub struct Moving {
+    from: Vec2,
+    to: Vec2,
+    elapsed: f32,
+}
 
 pub fn update_card_transforms(
-    mut cards: Query<(&GridLocation, &mut Transform), Changed<GridLocation>>,
-    players: Query<&PlayerId>,
+    mut commands: Commands,
+    mut cards: Query<(Entity, Ref<GridLocation>, &mut Transform), Changed<GridLocation>>,
     us: Res<Us>,
 ) {
-    for (GridLocation { owner, coord }, mut t) in &mut cards {
+    for (e, loc, mut t) in &mut cards {
         // 5x4 -> 20x12   4x/3x
-        let row = if *owner == us.0 { coord.x as f32 } else { GRID_H - coord.x as f32 - 1. };
-        let col = coord.y as f32;
+        let board = loc.board_coord(us.0).as_vec2();
+        let (row, col) = (board.x, board.y);
 
         let scale_w = BATTLEFIELD_HALF_W * 2. / GRID_W as f32;
         let scale_h = BATTLEFIELD_HALF_H * 2. / GRID_H as f32;
 
-        t.translation.x = ((col + 0.5) * scale_w) - (BATTLEFIELD_HALF_W);
-        t.translation.y = ((row + 0.5) * scale_h) - (BATTLEFIELD_HALF_H);
+        let to = Vec2::new(
+            ((col + 0.5) * scale_w) - (BATTLEFIELD_HALF_W),
+            ((row + 0.5) * scale_h) - (BATTLEFIELD_HALF_H),
+        );
+        let from = t.translation.truncate();
+        if loc.is_added() || from == to {
+            t.translation.x = to.x;
+            t.translation.y = to.y;
+        } else {
+            commands.entity(e).insert(Moving { from, to, elapsed: 0. });
+        }
+    }
+}
+
+pub fn animate_moves(
+    mut commands: Commands,
+    mut cards: Query<(Entity, &mut Moving, &mut Transform)>,
+    time: Res<Time>,
+) {
+    for (e, mut moving, mut t) in &mut cards {
+        moving.elapsed += time.delta_seconds();
+        let progress = (moving.elapsed / MOVE_DURATION).min(1.);
+        let pos = moving.from.lerp(moving.to, progress);
+        t.translation.x = pos.x;
+        t.translation.y = pos.y;
+        if progress >= 1. {
+            commands.entity(e).remove::<Moving>();
+        }
     }
 }
 
@@ -160,6 +310,7 @@ pub fn create_ability_overlay(
     current_turns: Query<Has<CurrentTurn>>,
     mut player_idx: Index<PlayerId>,
     us: Res<Us>,
+    replay: Option<Res<ReplayPlayer>>,
     window: Query<&Window>,
     mut ui: UiManager,
 ) {
@@ -180,7 +331,7 @@ pub fn create_ability_overlay(
     };
 
     let owners_turn = current_turns.get(player_idx.single(&card.grid_loc.owner)).unwrap();
-    let buttons_active = owners_turn && card.grid_loc.owner == us.0;
+    let buttons_active = owners_turn && card.grid_loc.owner == us.0 && replay.is_none();
 
     let scrollbar = commands
         .spawn((Name::new("scrollbar"), NodeBundle {
@@ -221,12 +372,18 @@ pub fn create_ability_overlay(
                 for (i, ability) in card.abilities.0.iter().enumerate() {
                     let active = buttons_active
                         && match ability {
-                            Ability::Activated { effect, cost, .. } => {
-                                let energy_cost = cost.get(effect).energy;
-                                card.energy.current >= energy_cost
-                            },
+                            Ability::Activated { effect, cost, .. } => cost
+                                .get(effect)
+                                .is_ok_and(|cost| card.energy.current >= cost.energy),
                             Ability::Passive { .. } => false,
                         };
+                    // the server picks the targets, so there's nothing to choose
+                    let picks_targets = matches!(
+                            ability,
+                            Ability::Activated { target_rules, .. } if target_rules.selection.is_chosen()
+                        );
+                    let match_id = *card.match_id;
+                    let unit_location = card.grid_loc.coord;
 
                     base.spawn((
                         NodeBundle { style: Style { margin, ..default() }, ..default() },
@@ -234,13 +391,29 @@ pub fn create_ability_overlay(
                             bg_color: Color::GRAY,
                             hover_color: Color::hex("#5aad65").unwrap(),
                             disabled_color: if buttons_active { Color::RED } else { Color::GRAY },
-                            click_handler: ClickHandler::new(move |mut commands: Commands| {
-                                commands.insert_resource(Targeting {
-                                    source: card_entity,
-                                    ability_idx: i,
-                                    chosen: vec![],
-                                })
-                            }),
+                            click_handler: ClickHandler::new(
+                                move |mut commands: Commands, mut client: ResMut<RenetClient>| {
+                                    if !picks_targets {
+                                        client.send(ActivateAbilityMessage {
+                                            match_id,
+                                            unit_location,
+                                            ability_idx: i,
+                                            targets: vec![],
+                                            then_targets: vec![],
+                                            destinations: vec![],
+                                        });
+                                        return;
+                                    }
+                                    commands.insert_resource(Targeting {
+                                        source: card_entity,
+                                        ability_idx: i,
+                                        chosen: vec![],
+                                        picked: vec![],
+                                        destinations: vec![],
+                                        picking_destinations: false,
+                                    })
+                                },
+                            ),
                             active,
                         },
                     ))
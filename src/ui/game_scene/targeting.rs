@@ -1,18 +1,15 @@
-use bevy::{prelude::*, utils::HashMap};
-use bevy_mod_index::prelude::Index;
+use bevy::{ecs::system::SystemParam, prelude::*, utils::HashMap};
 use bevy_mod_picking::prelude::*;
 use bevy_renet::renet::RenetClient;
 
 use crate::{
-    cards::Ability,
-    match_sim::{BaseCard, Cards, GridLocation, MatchId, PlayerId},
+    cards::{validate_step, Ability, TargetRules},
+    match_sim::{BaseCard, Cards, GridLocation, Matches, GRID_H, GRID_W},
     network::{messages::ActivateAbilityMessage, ClientExt},
     ui::{
         button::{ClickHandler, GameButton},
         font::CustomText,
-        game_scene::{
-            create_ability_overlay, BATTLEFIELD_HALF_H, BATTLEFIELD_HALF_W, GRID_H, GRID_W,
-        },
+        game_scene::{create_ability_overlay, BATTLEFIELD_HALF_H, BATTLEFIELD_HALF_W},
         UiManager,
     },
 };
@@ -23,22 +20,30 @@ pub struct TargetingUI;
 pub struct TargetingSubmit;
 #[derive(Component)]
 pub struct TargetingIndicator;
+/// Marks a location the hovered target's area would also hit
+#[derive(Component)]
+pub struct AreaHighlight;
 
 #[derive(Resource)]
 pub struct Targeting {
     pub source: Entity,
     pub ability_idx: usize,
+    /// Targets picked for the current step
     pub chosen: Vec<GridLocation>,
+    /// Targets of the steps already submitted, see [`TargetRules::then`]
+    pub picked: Vec<Vec<GridLocation>>,
+    /// Where each picked unit goes, for abilities that move units
+    pub destinations: Vec<GridLocation>,
+    /// Set once every step is submitted and the destinations are being picked
+    pub picking_destinations: bool,
 }
 
 pub fn start_targeting(
     targeting: Res<Targeting>,
     cards: Cards,
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut loc_idx: Index<GridLocation>,
-    players: Query<&PlayerId>,
+    mut indicators: TargetingIndicators,
+    matches: Res<Matches>,
     mut ui: UiManager,
 ) {
     if !targeting.is_added() {
@@ -86,110 +91,284 @@ pub fn start_targeting(
                     bg_color: Color::GRAY,
                     hover_color: Color::hex("#5aad65").unwrap(),
                     disabled_color: Color::RED,
-                    click_handler: ClickHandler::new(
-                        |indicators: Query<Entity, With<TargetingIndicator>>,
-                         cards: Query<Entity, With<BaseCard>>,
-                         ui: Query<Entity, With<TargetingUI>>,
-                         mut commands: Commands| {
-                            commands.entity(ui.single()).despawn_recursive();
-                            for e in &indicators {
-                                commands.entity(e).despawn();
-                            }
-                            commands.remove_resource::<Targeting>();
-                            for card in &cards {
-                                // restore overlay click handler
-                                commands
-                                    .entity(card)
-                                    .insert(On::<Pointer<Click>>::run(create_ability_overlay));
-                            }
-                        },
-                    ),
+                    click_handler: ClickHandler::new(|mut indicators: TargetingIndicators| {
+                        indicators.close()
+                    }),
                     active: true,
                 },
             ))
             .add_child(ui.spawn_text(CustomText::new("Cancel").color(Color::WHITE).size(15.)).id());
         });
 
-    let source_card = cards.get(targeting.source).unwrap();
-    let Ability::Activated { target_rules, .. } =
-        source_card.abilities.0.get(targeting.ability_idx).unwrap()
-    else {
-        panic!("Activated passive abillity!");
-    };
-    let mut indicators = HashMap::new();
-    for p in players.iter() {
-        for x in 0..(GRID_H as u32 / 2) {
-            for y in 0..(GRID_W as u32) {
-                let loc = GridLocation { coord: UVec2 { x, y }, owner: *p };
-
-                if !target_rules.filter.validate(&loc, &mut loc_idx, &cards, source_card.grid_loc) {
-                    continue;
-                }
-
-                let e = commands
-                    .spawn((Name::new("floor_targeting_helper"), TargetingIndicator, PbrBundle {
-                        mesh: meshes.add(Plane3d::new(Vec3::Z)),
-                        material: materials.add(StandardMaterial {
-                            perceptual_roughness: 0.9,
-                            ..Color::rgba(0.8, 0., 0., 0.1).into()
-                        }),
-                        transform: Transform::from_xyz(0., 0., 0.01).with_scale(Vec3::new(
-                            BATTLEFIELD_HALF_W / GRID_W * 0.8,
-                            BATTLEFIELD_HALF_H / GRID_H * 0.8,
-                            1.,
-                        )),
-                        ..default()
-                    }))
-                    .id();
-                indicators.insert(loc, e);
+    indicators.show(step_options(&targeting, &cards, &matches), toggle_target);
+}
+
+/// Spawns and clears the floor markers on the locations that can be picked.
+#[derive(SystemParam)]
+pub struct TargetingIndicators<'w, 's> {
+    commands: Commands<'w, 's>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+    materials: ResMut<'w, Assets<StandardMaterial>>,
+    indicators: Query<'w, 's, Entity, With<TargetingIndicator>>,
+    highlights: Query<'w, 's, Entity, With<AreaHighlight>>,
+    cards: Query<'w, 's, (Entity, &'static GridLocation), With<BaseCard>>,
+    ui: Query<'w, 's, Entity, With<TargetingUI>>,
+}
+impl TargetingIndicators<'_, '_> {
+    /// Replaces any indicators with ones on `locations`, which run `on_click` when clicked.
+    fn show<M>(
+        &mut self,
+        locations: impl IntoIterator<Item = GridLocation>,
+        on_click: impl IntoSystem<(), (), M> + Copy,
+    ) {
+        self.clear();
+
+        let mut indicators = HashMap::new();
+        for loc in locations {
+            let e = self
+                .commands
+                .spawn((Name::new("floor_targeting_helper"), TargetingIndicator, PbrBundle {
+                    mesh: self.meshes.add(Plane3d::new(Vec3::Z)),
+                    material: self.materials.add(StandardMaterial {
+                        perceptual_roughness: 0.9,
+                        ..Color::rgba(0.8, 0., 0., 0.1).into()
+                    }),
+                    transform: Transform::from_xyz(0., 0., 0.01).with_scale(Vec3::new(
+                        BATTLEFIELD_HALF_W / GRID_W * 0.8,
+                        BATTLEFIELD_HALF_H / GRID_H * 0.8,
+                        1.,
+                    )),
+                    ..default()
+                }))
+                .id();
+            indicators.insert(loc, e);
+        }
+
+        for (card_e, card_loc) in &self.cards {
+            let mut cmds = self.commands.entity(card_e);
+            if let Some(indicator_e) = indicators.get(card_loc) {
+                cmds.add_child(*indicator_e);
+                indicators.insert(*card_loc, card_e);
+                // remove overlay click handler
+                cmds.remove::<On<Pointer<Click>>>();
             }
         }
+
+        for (loc, e) in indicators.into_iter() {
+            self.commands.entity(e).insert(loc).insert((
+                On::<Pointer<Click>>::run(on_click),
+                On::<Pointer<Over>>::run(preview_area),
+                On::<Pointer<Out>>::run(|mut indicators: TargetingIndicators| {
+                    indicators.clear_area()
+                }),
+            ));
+        }
     }
 
-    for card in &cards {
-        let mut cmds = commands.entity(card.entity);
-        if let Some(indicator_e) = indicators.get(card.grid_loc) {
-            cmds.add_child(*indicator_e);
-            indicators.insert(*card.grid_loc, card.entity);
-            // remove overlay click handler
-            cmds.remove::<On<Pointer<Click>>>();
+    /// Replaces any area highlights with ones on `locations`.
+    fn show_area(&mut self, locations: impl IntoIterator<Item = GridLocation>) {
+        self.clear_area();
+
+        let cards = self.cards.iter().map(|(e, loc)| (*loc, e)).collect::<HashMap<_, _>>();
+        for loc in locations {
+            let highlight = (Name::new("area_highlight"), AreaHighlight, PbrBundle {
+                mesh: self.meshes.add(Plane3d::new(Vec3::Z)),
+                material: self.materials.add(StandardMaterial {
+                    perceptual_roughness: 0.9,
+                    ..Color::rgba(0.9, 0.6, 0., 0.4).into()
+                }),
+                // above the targeting indicators
+                transform: Transform::from_xyz(0., 0., 0.02).with_scale(Vec3::new(
+                    BATTLEFIELD_HALF_W / GRID_W * 0.9,
+                    BATTLEFIELD_HALF_H / GRID_H * 0.9,
+                    1.,
+                )),
+                ..default()
+            });
+            match cards.get(&loc) {
+                Some(card_e) => {
+                    self.commands.entity(*card_e).with_children(|card| {
+                        card.spawn(highlight);
+                    });
+                },
+                None => {
+                    self.commands.spawn((highlight, loc));
+                },
+            }
         }
     }
 
-    for (loc, e) in indicators.into_iter() {
-        commands.entity(e).insert(loc).insert(On::<Pointer<Click>>::run(
-            |listener: Listener<Pointer<Click>>,
-             cards: Query<(Entity, &GridLocation, Option<&Children>)>,
-             mut targeting: ResMut<Targeting>,
-             mut indicators: Query<&mut Handle<StandardMaterial>, With<TargetingIndicator>>,
-             mut materials: ResMut<Assets<StandardMaterial>>| {
-                let (card_e, loc, children) = cards.get(listener.listener()).unwrap();
-
-                // find indicator between when either
-                //   - this listener is the indicator
-                //   - this card's indicator is its child
-                let mut indicator_iter = indicators.iter_many_mut(
-                    children.iter().flat_map(|c| c.iter()).chain(std::iter::once(&card_e)),
-                );
-                if targeting.chosen.contains(loc) {
-                    targeting.chosen.retain(|x| x != loc);
-                    while let Some(mut i) = indicator_iter.fetch_next() {
-                        *i = materials.add(StandardMaterial {
-                            perceptual_roughness: 0.9,
-                            ..Color::rgba(0.8, 0., 0., 0.1).into()
-                        });
-                    }
-                } else {
-                    targeting.chosen.push(*loc);
-                    while let Some(mut i) = indicator_iter.fetch_next() {
-                        *i = materials.add(StandardMaterial {
-                            perceptual_roughness: 0.9,
-                            ..Color::rgba(0.8, 0., 0., 0.6).into()
-                        });
-                    }
-                }
-            },
-        ));
+    fn clear_area(&mut self) {
+        for e in &self.highlights {
+            self.commands.entity(e).despawn();
+        }
+    }
+
+    /// Removes every indicator and gives the cards back their overlay click handler.
+    fn clear(&mut self) {
+        self.clear_area();
+        for e in &self.indicators {
+            self.commands.entity(e).despawn();
+        }
+        for (card_e, _) in &self.cards {
+            self.commands
+                .entity(card_e)
+                .remove::<(On<Pointer<Over>>, On<Pointer<Out>>)>()
+                .insert(On::<Pointer<Click>>::run(create_ability_overlay));
+        }
+    }
+
+    /// Ends targeting, whether it was submitted or cancelled.
+    fn close(&mut self) {
+        self.clear();
+        for e in &self.ui {
+            self.commands.entity(e).despawn_recursive();
+        }
+        self.commands.remove_resource::<Targeting>();
+    }
+}
+
+fn toggle_target(
+    listener: Listener<Pointer<Click>>,
+    cards: Query<(Entity, &GridLocation, Option<&Children>)>,
+    mut targeting: ResMut<Targeting>,
+    mut indicators: Query<&mut Handle<StandardMaterial>, With<TargetingIndicator>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let (card_e, loc, children) = cards.get(listener.listener()).unwrap();
+
+    // find indicator between when either
+    //   - this listener is the indicator
+    //   - this card's indicator is its child
+    let mut indicator_iter = indicators
+        .iter_many_mut(children.iter().flat_map(|c| c.iter()).chain(std::iter::once(&card_e)));
+    if targeting.chosen.contains(loc) {
+        targeting.chosen.retain(|x| x != loc);
+        while let Some(mut i) = indicator_iter.fetch_next() {
+            *i = materials.add(StandardMaterial {
+                perceptual_roughness: 0.9,
+                ..Color::rgba(0.8, 0., 0., 0.1).into()
+            });
+        }
+    } else {
+        targeting.chosen.push(*loc);
+        while let Some(mut i) = indicator_iter.fetch_next() {
+            *i = materials.add(StandardMaterial {
+                perceptual_roughness: 0.9,
+                ..Color::rgba(0.8, 0., 0., 0.6).into()
+            });
+        }
+    }
+}
+
+/// Highlights everything the ability's area would hit if the hovered location were picked.
+fn preview_area(
+    listener: Listener<Pointer<Over>>,
+    locs: Query<&GridLocation>,
+    targeting: Res<Targeting>,
+    cards: Cards,
+    matches: Res<Matches>,
+    mut indicators: TargetingIndicators,
+) {
+    // destinations aren't targets, see `DeckProblem::AreaDestination`
+    if targeting.picking_destinations {
+        return;
+    }
+    let card = cards.get(targeting.source).unwrap();
+    let Some(Ability::Activated { target_rules, .. }) = card.abilities.0.get(targeting.ability_idx)
+    else {
+        return;
+    };
+    let (Some(area), Some(state), Ok(center)) =
+        (&target_rules.area, matches.0.get(card.match_id), locs.get(listener.listener()))
+    else {
+        return;
+    };
+    indicators.show_area(area.locations(center, state).map(|(loc, _)| loc));
+}
+
+/// Locations that can be picked for the current step.
+fn step_options(targeting: &Targeting, cards: &Cards, matches: &Matches) -> Vec<GridLocation> {
+    let card = cards.get(targeting.source).unwrap();
+    let Some(Ability::Activated { effect, target_rules, .. }) =
+        card.abilities.0.get(targeting.ability_idx)
+    else {
+        panic!("Activated passive abillity!");
+    };
+    let step = targeting.picked.len();
+    let (Some((_, filter)), Some(state)) =
+        (target_rules.steps().nth(step), matches.0.get(card.match_id))
+    else {
+        return vec![];
+    };
+    let origin = TargetRules::step_origin(step, *card.grid_loc, &targeting.picked);
+    let taken = targeting.picked.concat();
+    state
+        .locations()
+        .filter(|loc| !taken.contains(loc))
+        .filter(|loc| origin.is_some_and(|origin| filter.validate(loc, state, &origin)))
+        // a unit that can't go anywhere would leave nothing to pick as its destination
+        .filter(|loc| effect.destination_filter().is_none() || state.has_destination(effect, loc))
+        .collect()
+}
+
+/// Where the next picked unit without a destination can move, if it can move anywhere.
+fn destination_options(
+    targeting: &Targeting,
+    cards: &Cards,
+    matches: &Matches,
+) -> Vec<GridLocation> {
+    let card = cards.get(targeting.source).unwrap();
+    let Some(Ability::Activated { effect, .. }) = card.abilities.0.get(targeting.ability_idx)
+    else {
+        return vec![];
+    };
+    let targets = targeting.picked.concat();
+    let (Some(unit), Some(state)) =
+        (targets.get(targeting.destinations.len()), matches.0.get(card.match_id))
+    else {
+        return vec![];
+    };
+    state
+        .locations()
+        .filter(|d| !targeting.destinations.contains(d))
+        .filter(|d| state.valid_destination(effect, unit, d))
+        .collect()
+}
+
+fn pick_destination(
+    listener: Listener<Pointer<Click>>,
+    locs: Query<&GridLocation>,
+    mut targeting: ResMut<Targeting>,
+    mut indicators: TargetingIndicators,
+    cards: Cards,
+    matches: Res<Matches>,
+    mut client: ResMut<RenetClient>,
+) {
+    let loc = locs.get(listener.listener()).unwrap();
+    targeting.destinations.push(*loc);
+    if targeting.destinations.len() < targeting.picked.iter().map(Vec::len).sum() {
+        show_destinations(&targeting, &cards, &matches, &mut indicators);
+    } else {
+        send_targets(&targeting, &cards, &mut client);
+        indicators.close();
+    }
+}
+
+/// Shows where the next picked unit can go. If the earlier picks took every spot it could have
+/// gone to, the server would reject the ability, so targeting is cancelled instead.
+fn show_destinations(
+    targeting: &Targeting,
+    cards: &Cards,
+    matches: &Matches,
+    indicators: &mut TargetingIndicators,
+) {
+    let options = destination_options(targeting, cards, matches);
+    if options.is_empty() {
+        warn!("No destinations left for the picked units, cancelling the ability");
+        indicators.close();
+    } else {
+        indicators.show(options, pick_destination);
     }
 }
 
@@ -197,8 +376,7 @@ pub fn check_targets(
     cards: Cards,
     targeting: Res<Targeting>,
     mut btn: Query<&mut GameButton, With<TargetingSubmit>>,
-    mut grid_idx: Index<GridLocation>,
-    players: Query<&PlayerId>,
+    matches: Res<Matches>,
 ) {
     let card = cards.get(targeting.source).unwrap();
     let ability = card.abilities.0.get(targeting.ability_idx).unwrap();
@@ -206,9 +384,15 @@ pub fn check_targets(
         panic!("Activated passive abillity!");
     };
 
-    let players = players.iter().copied().collect::<Vec<PlayerId>>();
-    let targets_valid =
-        target_rules.validate(&targeting.chosen, &mut grid_idx, &cards, &players, card.grid_loc);
+    let state = matches.0.get(card.match_id).unwrap();
+    let step = targeting.picked.len();
+    let origin = TargetRules::step_origin(step, *card.grid_loc, &targeting.picked);
+    // destinations are submitted by picking the last one
+    let targets_valid = !targeting.picking_destinations
+        && target_rules.steps().nth(step).is_some_and(|(amount, filter)| {
+            let taken = targeting.picked.concat();
+            validate_step(amount, filter, &targeting.chosen, state, origin.as_ref(), &taken)
+        });
 
     let mut btn = btn.single_mut();
     if targets_valid && !btn.active {
@@ -219,29 +403,46 @@ pub fn check_targets(
 }
 
 fn submit_targets(
-    ui: Query<Entity, With<TargetingUI>>,
-    indicators: Query<Entity, With<TargetingIndicator>>,
-    cards: Query<(Entity, &MatchId, &GridLocation), With<BaseCard>>,
-    mut commands: Commands,
+    mut indicators: TargetingIndicators,
     mut client: ResMut<RenetClient>,
-    targeting: Res<Targeting>,
+    mut targeting: ResMut<Targeting>,
+    cards: Cards,
+    matches: Res<Matches>,
 ) {
-    commands.entity(ui.single()).despawn_recursive();
-    for e in &indicators {
-        commands.entity(e).despawn();
+    let card = cards.get(targeting.source).unwrap();
+    let Some(Ability::Activated { effect, target_rules, .. }) =
+        card.abilities.0.get(targeting.ability_idx)
+    else {
+        panic!("Activated passive abillity!");
+    };
+
+    let chosen = std::mem::take(&mut targeting.chosen);
+    targeting.picked.push(chosen);
+    if targeting.picked.len() <= target_rules.then.len() {
+        let options = step_options(&targeting, &cards, &matches);
+        indicators.show(options, toggle_target);
+        return;
     }
-    commands.remove_resource::<Targeting>();
 
-    for (card_e, _, _) in &cards {
-        // restore overlay click handler
-        commands.entity(card_e).insert(On::<Pointer<Click>>::run(create_ability_overlay));
+    if effect.destination_filter().is_some() && targeting.picked.iter().any(|t| !t.is_empty()) {
+        // pick where each unit goes before sending anything
+        targeting.picking_destinations = true;
+        show_destinations(&targeting, &cards, &matches, &mut indicators);
+        return;
     }
 
-    let (_, mid, loc) = cards.get(targeting.source).unwrap();
+    send_targets(&targeting, &cards, &mut client);
+    indicators.close();
+}
+
+fn send_targets(targeting: &Targeting, cards: &Cards, client: &mut RenetClient) {
+    let card = cards.get(targeting.source).unwrap();
     client.send(ActivateAbilityMessage {
-        match_id: *mid,
-        unit_location: loc.coord,
+        match_id: *card.match_id,
+        unit_location: card.grid_loc.coord,
         ability_idx: targeting.ability_idx,
-        targets: targeting.chosen.clone(), // todo: mem swap
+        targets: targeting.picked.first().cloned().unwrap_or_default(),
+        then_targets: targeting.picked.iter().skip(1).cloned().collect(),
+        destinations: targeting.destinations.clone(),
     })
 }
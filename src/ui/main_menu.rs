@@ -1,30 +1,53 @@
-use bevy::{app::AppExit, prelude::*};
+use bevy::{app::AppExit, prelude::*, window::ReceivedCharacter};
 use bevy_mod_picking::prelude::*;
 use bevy_renet::renet::RenetClient;
 
 use crate::{
-    cards::deck::Decks,
-    network::{messages::JoinMatchmakingQueueMessage, ClientConfig, ClientExt},
+    cards::deck::{Decks, RandomDeckSeed, RANDOM_DECK},
+    match_sim::{replay::list_replays, MatchEndReason},
+    network::{
+        messages::{JoinDraftMessage, JoinMatchmakingQueueMessage},
+        profile::PlayerProfile,
+        ClientConfig, ClientExt,
+    },
     ui::{
         button::{ClickHandler, GameButton},
         font::{CustomText, DefaultFont},
-        UiManager,
+        game_scene::MatchResult,
+        replay::ReplayPlayer,
+        SceneState, UiManager,
     },
 };
 
 #[derive(Component)]
 pub struct MainMenu;
 
+/// Number of recent replays offered on the main menu
+const MENU_REPLAYS: usize = 3;
+
 #[derive(Component)]
 pub struct QueueButton;
 
+/// Present while the player waits in a queue, so a rebuilt menu keeps the queue buttons disabled
+#[derive(Resource)]
+pub struct Queued;
+
+/// Most digits a typed seed can have, so it always fits in a `u64`
+const MAX_SEED_DIGITS: u32 = 18;
+
 pub fn spawn_main_menu(
     mut commands: Commands,
     decks: Res<Decks>,
+    seed: Res<RandomDeckSeed>,
     font: Res<DefaultFont>,
+    result: Option<Res<MatchResult>>,
+    profile: Option<Res<PlayerProfile>>,
+    queued: Option<Res<Queued>>,
     mut ui: UiManager,
 ) {
+    let can_queue = queued.is_none();
     let deck_names = decks.0.keys().cloned().collect::<Vec<_>>();
+    let player_name = profile.as_ref().map_or("player".to_string(), |p| p.name.clone());
 
     commands
         .spawn((MainMenu, NodeBundle {
@@ -53,34 +76,196 @@ pub fn spawn_main_menu(
             };
 
             let text = CustomText::default().size(30.).color(Color::NAVY).centered();
+
+            if let Some(result) = result {
+                let outcome = if result.won { "Victory!" } else { "Defeat" };
+                let reason = match (result.reason, result.won) {
+                    (MatchEndReason::CommandUnitDestroyed, true) => {
+                        "Enemy Command Center destroyed"
+                    },
+                    (MatchEndReason::CommandUnitDestroyed, false) => "Command Center destroyed",
+                    (MatchEndReason::Conceded, true) => "Opponent conceded",
+                    (MatchEndReason::Conceded, false) => "Conceded",
+                    (MatchEndReason::Disconnected, true) => "Opponent disconnected",
+                    (MatchEndReason::Disconnected, false) => "Disconnected",
+                };
+                base.spawn(NodeBundle {
+                    style: Style { margin: UiRect::bottom(Val::Vh(4.)), ..default() },
+                    ..default()
+                })
+                .add_child(
+                    ui.spawn_text(text.clone().size(45.).text(format!("{outcome}\n{reason}"))).id(),
+                );
+            }
+            if let Some(profile) = &profile {
+                base.spawn(NodeBundle {
+                    style: Style { margin: UiRect::bottom(Val::Vh(2.)), ..default() },
+                    ..default()
+                })
+                .add_child(
+                    ui.spawn_text(text.clone().size(20.).text(format!(
+                        "{} - Rating {} ({}W {}L)",
+                        profile.name,
+                        profile.rating,
+                        profile.wins(),
+                        profile.losses()
+                    )))
+                    .id(),
+                );
+            }
             let mut decks = deck_names.iter().cloned().collect::<Vec<_>>();
             decks.sort();
             for name in decks {
-                let name_cloned = name.clone();
-                base.spawn((base_button.clone(), QueueButton, GameButton {
+                let mut row = base.spawn(NodeBundle {
+                    style: Style { flex_direction: FlexDirection::Row, ..default() },
+                    ..default()
+                });
+                for vs_bot in [false, true] {
+                    let name = name.clone();
+                    let player_name = player_name.clone();
+                    let label =
+                        if vs_bot { "vs Bot".to_string() } else { format!("Find Match ({name})") };
+                    let mut button = base_button.clone();
+                    if vs_bot {
+                        button.style.width = Val::Vh(15.);
+                    }
+                    row.with_children(|row| {
+                        row.spawn((button, QueueButton, GameButton {
+                            bg_color: Color::WHITE,
+                            hover_color: Color::GREEN,
+                            disabled_color: Color::GRAY,
+                            click_handler: ClickHandler::new(
+                                move |listener: Listener<Pointer<Click>>,
+                                      mut commands: Commands,
+                                      mut client: ResMut<RenetClient>,
+                                      config: Res<ClientConfig>,
+                                      mut btns: Query<&mut GameButton, With<QueueButton>>,
+                                      decks: Res<Decks>| {
+                                    let deck = decks.0.get(&name).unwrap();
+                                    if let Err(issues) = deck.validate() {
+                                        for issue in issues {
+                                            error!("Deck {name} is invalid: {issue}");
+                                        }
+                                        return;
+                                    }
+                                    client.send(JoinMatchmakingQueueMessage {
+                                        player_name: player_name.clone(),
+                                        deck: deck.deck.clone(),
+                                        vs_bot,
+                                    });
+                                    commands.insert_resource(Queued);
+                                    for mut btn in &mut btns {
+                                        btn.active = false;
+                                    }
+                                },
+                            ),
+                            active: can_queue,
+                        }))
+                        .add_child(ui.spawn_text(text.clone().text(label)).id());
+                    });
+                }
+
+                if name == RANDOM_DECK {
+                    let mut row = base.spawn(NodeBundle {
+                        style: Style { flex_direction: FlexDirection::Row, ..default() },
+                        ..default()
+                    });
+                    let seed_text =
+                        ui.spawn_text(text.clone().size(20.).text(seed_label(seed.0))).id();
+                    row.with_children(|row| {
+                        row.spawn(base_button.clone()).add_child(seed_text);
+                    });
+
+                    let mut button = base_button.clone();
+                    button.style.width = Val::Vh(15.);
+                    row.with_children(|row| {
+                        row.spawn((button, GameButton {
+                            bg_color: Color::WHITE,
+                            hover_color: Color::GREEN,
+                            disabled_color: Color::GRAY,
+                            click_handler: ClickHandler::new(|mut seed: ResMut<RandomDeckSeed>| {
+                                *seed = RandomDeckSeed::default();
+                            }),
+                            active: true,
+                        }))
+                        .add_child(ui.spawn_text(text.clone().text("New Seed")).id());
+                    });
+                }
+            }
+
+            let mut row = base.spawn(NodeBundle {
+                style: Style { flex_direction: FlexDirection::Row, ..default() },
+                ..default()
+            });
+            for vs_bot in [false, true] {
+                let label = if vs_bot { "vs Bot" } else { "Draft a Deck" };
+                let player_name = player_name.clone();
+                let mut button = base_button.clone();
+                if vs_bot {
+                    button.style.width = Val::Vh(15.);
+                }
+                row.with_children(|row| {
+                    row.spawn(
+                        (
+                            button,
+                            QueueButton,
+                            GameButton {
+                                bg_color: Color::WHITE,
+                                hover_color: Color::GREEN,
+                                disabled_color: Color::GRAY,
+                                click_handler:
+                                    ClickHandler::new(
+                                        move |mut commands: Commands,
+                                              mut client: ResMut<RenetClient>,
+                                              mut btns: Query<
+                                            &mut GameButton,
+                                            With<QueueButton>,
+                                        >| {
+                                            client.send(JoinDraftMessage {
+                                                player_name: player_name.clone(),
+                                                vs_bot,
+                                            });
+                                            commands.insert_resource(Queued);
+                                            for mut btn in &mut btns {
+                                                btn.active = false;
+                                            }
+                                        },
+                                    ),
+                                active: can_queue,
+                            },
+                        ),
+                    )
+                    .add_child(ui.spawn_text(text.clone().text(label)).id());
+                });
+            }
+
+            base.spawn((base_button.clone(), GameButton {
+                bg_color: Color::WHITE,
+                hover_color: Color::GREEN,
+                disabled_color: Color::GRAY,
+                click_handler: ClickHandler::new(|mut next: ResMut<NextState<SceneState>>| {
+                    next.set(SceneState::Deckbuilding);
+                }),
+                active: true,
+            }))
+            .add_child(ui.spawn_text(text.clone().text("Deck Builder")).id());
+
+            for path in list_replays().into_iter().take(MENU_REPLAYS) {
+                let Some(stem) = path.file_stem().map(|s| s.to_string_lossy().into_owned()) else {
+                    continue;
+                };
+                base.spawn((base_button.clone(), GameButton {
                     bg_color: Color::WHITE,
                     hover_color: Color::GREEN,
                     disabled_color: Color::GRAY,
-                    click_handler: ClickHandler::new(
-                        move |listener: Listener<Pointer<Click>>,
-                              mut client: ResMut<RenetClient>,
-                              config: Res<ClientConfig>,
-                              mut btns: Query<&mut GameButton, With<QueueButton>>,
-                              decks: Res<Decks>| {
-                            client.send(JoinMatchmakingQueueMessage {
-                                player_name: "player".to_string(),
-                                deck: decks.0.get(&name).unwrap().deck.clone(),
-                            });
-                            for mut btn in &mut btns {
-                                btn.active = false;
-                            }
-                        },
-                    ),
+                    click_handler: ClickHandler::new(move |mut commands: Commands| {
+                        if let Some(player) = ReplayPlayer::load(&path) {
+                            commands.insert_resource(player);
+                        }
+                    }),
                     active: true,
                 }))
-                .add_child(
-                    ui.spawn_text(text.clone().text(format!("Find Match ({})", name_cloned))).id(),
-                );
+                .add_child(ui.spawn_text(text.clone().text(format!("Watch Replay ({stem})"))).id());
             }
 
             base.spawn((base_button, GameButton {
@@ -95,3 +280,32 @@ pub fn spawn_main_menu(
             .add_child(ui.spawn_text(text.text("Quit")).id());
         });
 }
+
+/// Starting a match or draft takes the player out of the queue.
+pub fn leave_queue(mut commands: Commands) {
+    commands.remove_resource::<Queued>();
+}
+
+fn seed_label(seed: u64) -> String {
+    format!("Random deck seed: {seed} (type to change)")
+}
+
+/// Typing digits on the main menu edits the seed of the random deck.
+pub fn edit_seed(
+    mut chars: EventReader<ReceivedCharacter>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut seed: ResMut<RandomDeckSeed>,
+) {
+    let mut new_seed = seed.0;
+    for digit in chars.read().flat_map(|c| c.char.chars()).filter_map(|c| c.to_digit(10)) {
+        if new_seed.checked_ilog10().unwrap_or(0) + 1 < MAX_SEED_DIGITS {
+            new_seed = new_seed * 10 + digit as u64;
+        }
+    }
+    if keys.just_pressed(KeyCode::Backspace) {
+        new_seed /= 10;
+    }
+    if new_seed != seed.0 {
+        seed.0 = new_seed;
+    }
+}
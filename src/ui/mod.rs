@@ -1,8 +1,10 @@
 pub mod button;
 pub mod deckbuilding;
+pub mod draft;
 pub mod font;
 pub mod game_scene;
 pub mod main_menu;
+pub mod replay;
 
 use bevy::{
     ecs::system::{EntityCommands, SystemParam},
@@ -14,20 +16,26 @@ use bevy_mod_picking::prelude::*;
 
 use crate::{
     cards::{
-        deck::{load_decks, Decks},
+        deck::{
+            load_decks, sync_decks, update_random_deck, Deck, DeckLoader, Decks, RandomDeckSeed,
+        },
         mesh::spawn_card_mesh,
     },
-    match_sim::StartMatchEvent,
+    match_sim::{draft::DraftPack, MatchEndEvent, StartMatchEvent},
+    network::profile::PlayerProfile,
     ui::{
         button::update_buttons,
         deckbuilding::DeckbuildingPlugin,
+        draft::{spawn_draft, transition_to_draft, DraftUi},
         font::{scale_text, CustomText, DefaultFont, DynamicFontSize, FontPlugin},
         game_scene::{
-            scroll, setup_new_cards, spawn_match,
-            targeting::{check_targets, start_targeting, Targeting},
-            transition_to_match, update_card_transforms, update_stat_overlays, MatchScenery,
+            animate_moves, scroll, setup_new_cards, spawn_match,
+            targeting::{check_targets, start_targeting, Targeting, TargetingUI},
+            transition_to_main_menu, transition_to_match, update_card_transforms,
+            update_pause_banner, update_stat_overlays, MatchScenery,
         },
-        main_menu::{spawn_main_menu, MainMenu},
+        main_menu::{edit_seed, leave_queue, spawn_main_menu, MainMenu},
+        replay::{play_replay, spawn_replay_controls, update_replay_status, ReplayPlayer},
     },
 };
 
@@ -35,6 +43,8 @@ use crate::{
 pub enum SceneState {
     MainMenu,
     Match,
+    Deckbuilding,
+    Draft,
 }
 
 pub struct ScenePlugin;
@@ -48,13 +58,53 @@ impl Plugin for ScenePlugin {
 
         app.insert_state(SceneState::MainMenu);
 
+        app.init_asset::<Deck>();
+        app.init_asset_loader::<DeckLoader>();
         app.init_resource::<Decks>();
+        app.init_resource::<RandomDeckSeed>();
         app.add_systems(Startup, load_decks);
+        app.add_systems(
+            Update,
+            (
+                sync_decks,
+                update_random_deck.run_if(resource_changed::<RandomDeckSeed>),
+                // rebuild the menu so edited decks, seeds and profiles show up right away
+                (despawn_all_with_marker::<MainMenu>, spawn_main_menu).chain().run_if(
+                    in_state(SceneState::MainMenu).and_then(
+                        resource_changed::<Decks>
+                            .or_else(resource_exists_and_changed::<PlayerProfile>),
+                    ),
+                ),
+            )
+                .chain(),
+        );
+        app.add_systems(Update, edit_seed.run_if(in_state(SceneState::MainMenu)));
 
         app.add_systems(OnEnter(SceneState::MainMenu), spawn_main_menu);
         app.add_systems(OnExit(SceneState::MainMenu), despawn_all_with_marker::<MainMenu>);
+        app.add_systems(OnEnter(SceneState::Match), leave_queue);
+        app.add_systems(OnEnter(SceneState::Draft), leave_queue);
+        app.add_systems(
+            Update,
+            transition_to_draft
+                .run_if(in_state(SceneState::MainMenu).and_then(resource_added::<DraftPack>)),
+        );
+        app.add_systems(
+            Update,
+            (despawn_all_with_marker::<DraftUi>, spawn_draft).chain().run_if(
+                in_state(SceneState::Draft).and_then(resource_exists_and_changed::<DraftPack>),
+            ),
+        );
+        app.add_systems(OnExit(SceneState::Draft), despawn_all_with_marker::<DraftUi>);
         app.add_systems(OnEnter(SceneState::Match), spawn_match);
-        app.add_systems(OnExit(SceneState::Match), despawn_all_with_marker::<MatchScenery>);
+        app.add_systems(
+            OnEnter(SceneState::Match),
+            spawn_replay_controls.run_if(resource_exists::<ReplayPlayer>),
+        );
+        app.add_systems(
+            OnExit(SceneState::Match),
+            (despawn_all_with_marker::<MatchScenery>, despawn_all_with_marker::<TargetingUI>),
+        );
         // Hack to ensure `Out` handlers run before `Over`
         // app.add_systems(
         //     PreUpdate,
@@ -65,6 +115,11 @@ impl Plugin for ScenePlugin {
 
         app.add_systems(Update, update_buttons);
         app.add_systems(Update, transition_to_match.run_if(on_event::<StartMatchEvent>()));
+        app.add_systems(Update, transition_to_main_menu.run_if(on_event::<MatchEndEvent>()));
+        app.add_systems(
+            Update,
+            (play_replay, update_replay_status).chain().run_if(resource_exists::<ReplayPlayer>),
+        );
         app.add_systems(
             Update,
             (
@@ -72,7 +127,9 @@ impl Plugin for ScenePlugin {
                 setup_new_cards,
                 // apply_deferred,
                 update_card_transforms,
+                animate_moves,
                 update_stat_overlays,
+                update_pause_banner,
                 scroll,
             )
                 .chain()
@@ -0,0 +1,244 @@
+use std::path::Path;
+
+use bevy::{log, prelude::*};
+use bevy_mod_index::prelude::Index;
+
+use crate::{
+    match_sim::{engine::MatchOutcome, replay::Replay, MatchEndReason, MatchId, Matches, PlayerId},
+    network::{messages::NetworkMessage, MessageHandler},
+    ui::{
+        button::{ClickHandler, GameButton},
+        font::CustomText,
+        game_scene::{MatchResult, MatchScenery},
+        SceneState, UiManager,
+    },
+};
+
+const SPEEDS: [f32; 4] = [0.5, 1., 2., 4.];
+/// Seconds between steps at 1x speed
+const STEP_TIME: f32 = 1.5;
+
+/// Plays a saved replay through the same events a live match uses. Present only while watching.
+#[derive(Resource)]
+pub struct ReplayPlayer {
+    match_id: MatchId,
+    you: PlayerId,
+    outcome: Option<MatchOutcome>,
+    steps: Vec<Vec<NetworkMessage>>,
+    /// Number of steps that have been applied to the match
+    applied: usize,
+    /// Number of steps that should be applied to the match
+    target: usize,
+    playing: bool,
+    speed: usize,
+    elapsed: f32,
+    rewinding: bool,
+}
+impl ReplayPlayer {
+    pub fn new(replay: &Replay) -> Self {
+        let you = replay.players[0];
+        Self {
+            match_id: replay.match_id,
+            you,
+            outcome: replay.outcome,
+            steps: replay.steps(you),
+            applied: 0,
+            target: 1,
+            playing: false,
+            speed: 1,
+            elapsed: 0.,
+            rewinding: false,
+        }
+    }
+
+    pub fn load(path: &Path) -> Option<Self> {
+        match Replay::load(path) {
+            Ok(replay) => Some(Self::new(&replay)),
+            Err(e) => {
+                log::error!("Failed to load replay {}: {e}", path.display());
+                None
+            },
+        }
+    }
+
+    fn step_forward(&mut self) {
+        self.target = (self.target + 1).min(self.steps.len());
+        self.elapsed = 0.;
+    }
+
+    fn step_back(&mut self) {
+        // the first step starts the match, so it can't be undone
+        self.target = (self.target - 1).max(1);
+        self.elapsed = 0.;
+    }
+
+    fn status(&self) -> String {
+        let mut status = format!(
+            "Turn {}/{}  {}x{}",
+            self.target - 1,
+            self.steps.len() - 1,
+            SPEEDS[self.speed],
+            if self.playing { "" } else { " (paused)" },
+        );
+        if self.target == self.steps.len() {
+            if let Some(outcome) = self.outcome {
+                let winner = if outcome.winner == self.you { "Bottom" } else { "Top" };
+                let reason = match outcome.reason {
+                    MatchEndReason::CommandUnitDestroyed => "Command Center destroyed",
+                    MatchEndReason::Conceded => "Conceded",
+                    MatchEndReason::Disconnected => "Disconnected",
+                };
+                status.push_str(&format!("\n{winner} player wins ({reason})"));
+            }
+        }
+        status
+    }
+}
+
+pub fn play_replay(
+    mut player: ResMut<ReplayPlayer>,
+    mut handler: MessageHandler,
+    mut matches: ResMut<Matches>,
+    mut match_index: Index<MatchId>,
+    mut commands: Commands,
+    time: Res<Time>,
+) {
+    let player = &mut *player;
+
+    if player.rewinding {
+        // entities from the last playthrough are gone now, start over
+        player.rewinding = false;
+    } else if player.target < player.applied {
+        matches.0.remove(&player.match_id);
+        for entity in match_index.lookup(&player.match_id) {
+            if let Some(entity) = commands.get_entity(entity) {
+                entity.despawn_recursive();
+            }
+        }
+        player.applied = 0;
+        player.rewinding = true;
+        return;
+    }
+
+    if player.playing {
+        player.elapsed += time.delta_seconds() * SPEEDS[player.speed];
+        if player.elapsed >= STEP_TIME {
+            player.step_forward();
+        }
+        if player.target == player.steps.len() {
+            player.playing = false;
+        }
+    }
+
+    while player.applied < player.target {
+        for msg in player.steps[player.applied].iter().cloned() {
+            if let Err(msg) = handler.handle(msg) {
+                log::warn!("Skipping unexpected message in replay: {msg:?}");
+            }
+        }
+        player.applied += 1;
+    }
+}
+
+#[derive(Component)]
+pub struct ReplayStatus;
+
+pub fn update_replay_status(
+    player: Res<ReplayPlayer>,
+    mut status: Query<&mut Text, With<ReplayStatus>>,
+) {
+    for mut text in &mut status {
+        text.sections[0].value = player.status();
+    }
+}
+
+pub fn spawn_replay_controls(mut commands: Commands, mut ui: UiManager) {
+    let text = CustomText::default().color(Color::WHITE).size(15.).centered();
+    let buttons: [(&str, ClickHandler); 5] = [
+        ("<", ClickHandler::new(|mut player: ResMut<ReplayPlayer>| player.step_back())),
+        (
+            "Play/Pause",
+            ClickHandler::new(|mut player: ResMut<ReplayPlayer>| {
+                if player.target == player.steps.len() {
+                    return;
+                }
+                player.playing = !player.playing;
+                player.elapsed = 0.;
+            }),
+        ),
+        (">", ClickHandler::new(|mut player: ResMut<ReplayPlayer>| player.step_forward())),
+        (
+            "Speed",
+            ClickHandler::new(|mut player: ResMut<ReplayPlayer>| {
+                player.speed = (player.speed + 1) % SPEEDS.len();
+            }),
+        ),
+        (
+            "Exit",
+            ClickHandler::new(
+                |player: Res<ReplayPlayer>,
+                 mut matches: ResMut<Matches>,
+                 mut match_index: Index<MatchId>,
+                 mut s: ResMut<NextState<SceneState>>,
+                 mut commands: Commands| {
+                    matches.0.remove(&player.match_id);
+                    for entity in match_index.lookup(&player.match_id) {
+                        if let Some(entity) = commands.get_entity(entity) {
+                            entity.despawn_recursive();
+                        }
+                    }
+                    commands.remove_resource::<ReplayPlayer>();
+                    commands.remove_resource::<MatchResult>();
+                    s.0 = Some(SceneState::MainMenu);
+                },
+            ),
+        ),
+    ];
+
+    let status = ui.spawn_text(text.clone()).insert(ReplayStatus).id();
+    let mut bar = commands.spawn((MatchScenery, Name::new("replay_controls"), NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Vh(1.),
+            width: Val::Percent(100.),
+            flex_direction: FlexDirection::Column,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        ..default()
+    }));
+    bar.add_child(status);
+    let bar = bar.id();
+
+    let row = commands
+        .spawn(NodeBundle {
+            style: Style { flex_direction: FlexDirection::Row, ..default() },
+            ..default()
+        })
+        .id();
+    commands.entity(bar).add_child(row);
+
+    for (label, click_handler) in buttons {
+        let label = ui.spawn_text(text.clone().text(label)).id();
+        commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        margin: UiRect::all(Val::Vh(0.5)),
+                        padding: UiRect::all(Val::Vh(1.)),
+                        ..default()
+                    },
+                    ..default()
+                },
+                GameButton {
+                    bg_color: Color::GRAY,
+                    hover_color: Color::hex("#5aad65").unwrap(),
+                    disabled_color: Color::GRAY,
+                    click_handler,
+                    active: true,
+                },
+            ))
+            .add_child(label)
+            .set_parent(row);
+    }
+}
@@ -1,4 +1,4 @@
-use std::fmt::Formatter;
+use std::fmt::{Display, Formatter};
 
 #[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
 pub struct Uuid(bevy::utils::Uuid);
@@ -8,6 +8,12 @@ impl Uuid {
     }
 }
 
+impl Display for Uuid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
 struct UuidVisitor;
 impl<'de> serde::de::Visitor<'de> for UuidVisitor {
     type Value = Uuid;
@@ -25,6 +31,25 @@ impl<'de> serde::de::Visitor<'de> for UuidVisitor {
 
         Ok(Uuid(bevy::utils::Uuid::from_bytes(bytes)))
     }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.visit_byte_buf(v.to_vec())
+    }
+
+    // self-describing formats like json store bytes as a sequence
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        let mut bytes = Vec::with_capacity(16);
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        self.visit_byte_buf(bytes)
+    }
 }
 
 impl serde::Serialize for Uuid {